@@ -0,0 +1,157 @@
+// arrays.rs
+
+use std::cmp::Ordering;
+
+use crate::evaluator::{compare_values, CellError, Value, MAX_GROWTH};
+use crate::functions::arity;
+
+/// The most values SEQUENCE may produce: a table grown as far as it can in
+/// both directions.
+const MAX_CELLS: f64 = (MAX_GROWTH * MAX_GROWTH) as f64;
+
+fn optional_number(args: &[Value], index: usize, default: f64) -> Result<f64, CellError> {
+    match args.get(index) {
+        Some(Value::Empty) | None => Ok(default),
+        Some(v) => v.as_number(),
+    }
+}
+
+/// SEQUENCE(rows, [columns], [start], [step]): numbers counting up row by row.
+fn sequence(args: &[Value]) -> Result<Value, CellError> {
+    arity(args, 1, 4)?;
+    let rows = args[0].as_number()?.trunc();
+    let cols = optional_number(args, 1, 1.0)?.trunc();
+    let start = optional_number(args, 2, 1.0)?;
+    let step = optional_number(args, 3, 1.0)?;
+    if rows < 1.0 || cols < 1.0 {
+        return Err(CellError::Calc);
+    }
+    if rows * cols > MAX_CELLS {
+        return Err(CellError::Value);
+    }
+    let (rows, cols) = (rows as usize, cols as usize);
+    Ok(Value::Array(
+        (0..rows)
+            .map(|r| (0..cols).map(|c| Value::Number(start + step * (r * cols + c) as f64)).collect())
+            .collect(),
+    ))
+}
+
+/// SORT(array, [index], [order]): rows ordered by the `index`th column,
+/// descending when `order` is -1.
+fn sort(args: &[Value]) -> Result<Value, CellError> {
+    arity(args, 1, 3)?;
+    let mut rows = args[0].clone().into_rows();
+    let index = optional_number(args, 1, 1.0)?.trunc();
+    let descending = match optional_number(args, 2, 1.0)? {
+        1.0 => false,
+        -1.0 => true,
+        _ => return Err(CellError::Value),
+    };
+    if index < 1.0 || rows.first().is_some_and(|r| index as usize > r.len()) {
+        return Err(CellError::Value);
+    }
+    let key = index as usize - 1;
+    rows.sort_by(|a, b| {
+        let ordering = compare_values(&a[key], &b[key]);
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    Ok(Value::Array(rows))
+}
+
+/// UNIQUE(array): the distinct rows, in order of first appearance.
+fn unique(args: &[Value]) -> Result<Value, CellError> {
+    arity(args, 1, 1)?;
+    let mut rows: Vec<Vec<Value>> = Vec::new();
+    for row in args[0].clone().into_rows() {
+        let seen = rows.iter().any(|r| {
+            r.len() == row.len() && r.iter().zip(&row).all(|(a, b)| compare_values(a, b) == Ordering::Equal)
+        });
+        if !seen {
+            rows.push(row);
+        }
+    }
+    Ok(Value::Array(rows))
+}
+
+/// FILTER(array, include, [if_empty]): the rows of `array` whose `include`
+/// value is true. `#CALC!` when nothing is left and no `if_empty` is given.
+fn filter(args: &[Value]) -> Result<Value, CellError> {
+    arity(args, 2, 3)?;
+    let rows = args[0].clone().into_rows();
+    let include: Vec<Value> = args[1].clone().into_rows().into_iter().flatten().collect();
+    if include.len() != rows.len() {
+        return Err(CellError::Value);
+    }
+    let mut kept = Vec::new();
+    for (row, keep) in rows.into_iter().zip(&include) {
+        if keep.as_bool()? {
+            kept.push(row);
+        }
+    }
+    if kept.is_empty() {
+        return args.get(2).cloned().ok_or(CellError::Calc);
+    }
+    Ok(Value::Array(kept))
+}
+
+pub fn call(name: &str, args: &[Value]) -> Option<Result<Value, CellError>> {
+    let result = match name {
+        "SEQUENCE" => sequence(args),
+        "SORT" => sort(args),
+        "UNIQUE" => unique(args),
+        "FILTER" => filter(args),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(values: &[f64]) -> Value {
+        Value::Array(values.iter().map(|v| vec![Value::Number(*v)]).collect())
+    }
+
+    #[test]
+    fn test_array_functions() {
+        let sequence = call("SEQUENCE", &[Value::Number(2.0), Value::Number(2.0), Value::Number(10.0)]);
+        assert_eq!(
+            sequence,
+            Some(Ok(Value::Array(vec![
+                vec![Value::Number(10.0), Value::Number(11.0)],
+                vec![Value::Number(12.0), Value::Number(13.0)],
+            ])))
+        );
+        assert_eq!(call("SEQUENCE", &[Value::Number(0.0)]), Some(Err(CellError::Calc)));
+        assert_eq!(call("SEQUENCE", &[Value::Number(1e9)]), Some(Err(CellError::Value)));
+        assert_eq!(call("SEQUENCE", &[Value::Number(1e6), Value::Number(2.0)]), Some(Err(CellError::Value)));
+        let Some(Ok(Value::Array(rows))) = call("SEQUENCE", &[Value::Number(5000.0)]) else { panic!() };
+        assert_eq!(rows.len(), 5000);
+
+        let data = column(&[3.0, 1.0, 3.0, 2.0]);
+        let only_data = [data.clone()];
+        assert_eq!(call("SORT", &only_data), Some(Ok(column(&[1.0, 2.0, 3.0, 3.0]))));
+        assert_eq!(
+            call("SORT", &[data.clone(), Value::Number(1.0), Value::Number(-1.0)]),
+            Some(Ok(column(&[3.0, 3.0, 2.0, 1.0])))
+        );
+        assert_eq!(call("UNIQUE", &only_data), Some(Ok(column(&[3.0, 1.0, 2.0]))));
+
+        let include = Value::Array(
+            [true, false, true, false].iter().map(|b| vec![Value::Bool(*b)]).collect(),
+        );
+        assert_eq!(call("FILTER", &[data.clone(), include]), Some(Ok(column(&[3.0, 3.0]))));
+        let none = Value::Array(vec![vec![Value::Bool(false)]; 4]);
+        assert_eq!(call("FILTER", &[data.clone(), none.clone()]), Some(Err(CellError::Calc)));
+        assert_eq!(
+            call("FILTER", &[data, none, Value::Text("none".to_string())]),
+            Some(Ok(Value::Text("none".to_string())))
+        );
+    }
+}
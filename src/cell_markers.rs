@@ -1,8 +1,9 @@
-use crate::table_parser::{MarkdownTable, TableCell,TableCellPiece, TableParser};
-use comrak::nodes::{Ast, AstNode,LineColumn,NodeValue, Sourcepos};
-use crate::md_comments::{self, parse_markdown_for_comments, LocatedHtmlComment, CommentKind, HtmlComment};
+use crate::table_parser::{MarkdownTable, TableCell,TableCellPiece};
+use comrak::nodes::AstNode;
+use crate::md_comments::{LocatedHtmlComment, CommentKind, HtmlComment};
+use crate::directives::parse_directive;
+use crate::formula::{column_index, column_name};
 use comrak::{parse_document, Arena, ComrakOptions};
-use std::cell::RefCell;
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -14,18 +15,27 @@ pub enum MarkerMode {
 }
 
 
-
-
+/// A comment node to pass as the `fallback_node` of [`apply_marker_mode`].
+#[deprecated(note = "only needed for `apply_marker_mode`; use `apply_markers` instead")]
 pub fn make_html_comment_node<'a>(arena: &'a Arena<AstNode<'a>>) -> &'a AstNode<'a> {
     let root = parse_document(arena, "<!-- test -->", &ComrakOptions::default());
     root.first_child().unwrap()
 }
 
+/// [`apply_markers`] with the old signature. Comments no longer point at
+/// AST nodes, so `fallback_node` is not used.
+#[deprecated(note = "use `apply_markers`, which needs no comrak node")]
 pub fn apply_marker_mode<'a>(
-    table: &mut MarkdownTable<'a>,
+    table: &mut MarkdownTable,
     mode: MarkerMode,
-    fallback_node: &'a AstNode<'a>,
+    _fallback_node: &'a AstNode<'a>,
 ) {
+    apply_markers(table, mode)
+}
+
+/// Adds, updates or removes the `!A1`-style markers of every cell of `table`
+/// as `mode` says.
+pub fn apply_markers(table: &mut MarkdownTable, mode: MarkerMode) {
     let row_count = table.rows.len();
     if row_count == 0 {
         return;
@@ -37,31 +47,25 @@ pub fn apply_marker_mode<'a>(
             let is_header_col = col_idx == 0;
 
             match mode {
-                MarkerMode::DeleteAll => {
-                    cell.pieces.retain(|p| {
-                        !matches!(p, TableCellPiece::Comment(c) if c.comment.kind == CommentKind::Marker)
-                    });
-                }
+                MarkerMode::DeleteAll => remove_markers(cell),
                 MarkerMode::UpdateExisting => {
                     for piece in &mut cell.pieces {
                         if let TableCellPiece::Comment(c) = piece {
-                            if c.comment.kind == CommentKind::Marker {
-                                c.comment.content = compute_marker(row_idx, col_idx);
+                            if marker_of(&c.comment).is_some() {
+                                set_marker(&mut c.comment, &compute_marker(row_idx, col_idx));
                             }
                         }
                     }
                 }
                 MarkerMode::OnlyRowAndColumn => {
                     if is_header_row || is_header_col {
-                        update_or_insert_marker(cell, row_idx, col_idx, fallback_node);
+                        update_or_insert_marker(cell, row_idx, col_idx);
                     } else {
-                        cell.pieces.retain(|p| {
-                            !matches!(p, TableCellPiece::Comment(c) if c.comment.kind == CommentKind::Marker)
-                        });
+                        remove_markers(cell);
                     }
                 }
                 MarkerMode::AllMarkers => {
-                    update_or_insert_marker(cell, row_idx, col_idx, fallback_node);
+                    update_or_insert_marker(cell, row_idx, col_idx);
                 }
             }
         }
@@ -70,18 +74,13 @@ pub fn apply_marker_mode<'a>(
 
 
 
-fn update_or_insert_marker<'a>(
-    cell: &mut TableCell<'a>,
-    row: usize,
-    col: usize,
-    fallback_node: &'a AstNode<'a>,
-) {
+fn update_or_insert_marker(cell: &mut TableCell, row: usize, col: usize) {
     let marker = compute_marker(row, col);
 
     for piece in cell.pieces.iter_mut() {
         if let TableCellPiece::Comment(comment) = piece {
-            if comment.comment.kind == CommentKind::Marker {
-                comment.comment.content = marker;
+            if marker_of(&comment.comment).is_some() {
+                set_marker(&mut comment.comment, &marker);
                 return;
             }
         }
@@ -89,7 +88,7 @@ fn update_or_insert_marker<'a>(
 
     // If no existing marker found, insert a new one
     cell.pieces.push(TableCellPiece::Comment(LocatedHtmlComment {
-        node: fallback_node,
+        span: None,
         comment: HtmlComment {
             content: marker,
             kind: CommentKind::Marker,
@@ -99,79 +98,162 @@ fn update_or_insert_marker<'a>(
     }));
 }
 
+/// The marker of a comment: the whole of a marker comment, or the leading
+/// marker of a directive comment that combines both (`!D !col=B*C`).
+pub fn marker_of(comment: &HtmlComment) -> Option<&str> {
+    let content = comment.content.trim();
+    match comment.kind {
+        CommentKind::Marker => Some(content),
+        CommentKind::Directive if parse_directive(content).is_none() => content
+            .split_whitespace()
+            .next()
+            .filter(|word| word.len() < content.len() && parse_marker(word) != (None, None)),
+        _ => None,
+    }
+}
+
+fn set_marker(comment: &mut HtmlComment, marker: &str) {
+    let Some(old) = marker_of(comment) else {
+        return;
+    };
+    let rest = comment.content.trim()[old.len()..].to_string();
+    comment.content = format!("{}{}", marker, rest);
+}
+
+/// Removes marker comments from a cell and the leading marker from combined
+/// directive comments.
+fn remove_markers(cell: &mut TableCell) {
+    cell.pieces.retain_mut(|piece| match piece {
+        TableCellPiece::Comment(c) if c.comment.kind == CommentKind::Marker => false,
+        TableCellPiece::Comment(c) => {
+            if let Some(old) = marker_of(&c.comment) {
+                c.comment.content = c.comment.content.trim()[old.len()..].trim_start().to_string();
+            }
+            true
+        }
+        TableCellPiece::Text(_) => true,
+    });
+}
+
+/// Reads a marker such as `!D`, `!3` or `!B3` as its zero based column and
+/// row, each present only if the marker names it.
+pub fn parse_marker(marker: &str) -> (Option<usize>, Option<usize>) {
+    let body = marker.trim().strip_prefix('!').unwrap_or(marker);
+    let digits = body.find(|c: char| c.is_ascii_digit()).unwrap_or(body.len());
+    let (letters, number) = body.split_at(digits);
+    let col = column_index(letters);
+    let row = number.parse::<usize>().ok().and_then(|n| n.checked_sub(1));
+    if (col.is_none() && !letters.is_empty()) || (row.is_none() && !number.is_empty()) {
+        return (None, None);
+    }
+    (col, row)
+}
+
 fn compute_marker(row: usize, col: usize) -> String {
-    let col_char = (b'A' + (col as u8)) as char;
     let row_number = row + 1;
-    format!("!{}{}", col_char, row_number)
+    format!("!{}{}", column_name(col), row_number)
 }
 
-pub fn print_table<'a>(table: &MarkdownTable<'a>) {
-    for row in &table.rows {
-        let rendered_cells: Vec<String> = row
-            .cells
-            .iter()
-            .map(|cell| {
-                cell.pieces
-                    .iter()
-                    .map(|piece| match piece {
-                        TableCellPiece::Text(t) => t.clone(),
-                        TableCellPiece::Comment(c) => format!("<!-- {} -->", c.comment.content.trim()),
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            })
-            .collect();
-
-        println!("| {} |", rendered_cells.join(" | "));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md_comments;
+    use crate::table_parser::TableParser;
+
+    fn print_table(table: &MarkdownTable) {
+        for row in &table.rows {
+            let rendered_cells: Vec<String> = row
+                .cells
+                .iter()
+                .map(|cell| {
+                    cell.pieces
+                        .iter()
+                        .map(|piece| match piece {
+                            TableCellPiece::Text(t) => t.clone(),
+                            TableCellPiece::Comment(c) => format!("<!-- {} -->", c.comment.content.trim()),
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect();
+
+            println!("| {} |", rendered_cells.join(" | "));
+        }
     }
-}
 
-#[test]
-fn test_only_row_and_column_markers() {
-    let markdown = "\
+    #[test]
+    #[allow(deprecated)]
+    fn test_only_row_and_column_markers() {
+        let markdown = "\
 | A | B | C |
 |---|---|---|
 | 1 | 2 | 3 |
 | 4 | 5 | 6 |
 ";
 
-    let arena = Arena::new();
-    let root = parse_document(&arena, markdown, &ComrakOptions::default());
-    let comments = md_comments::parse_markdown_for_comments(&arena, markdown);
-    let tables = TableParser::extract_tables_from_ast(root, Some(&comments), markdown);
+        let arena = Arena::new();
+        let root = parse_document(&arena, markdown, &ComrakOptions::default());
+        let comments = md_comments::parse_markdown_for_comments(&arena, markdown);
+        let tables = TableParser::extract_tables_from_ast(root, Some(&comments), markdown);
 
-    assert_eq!(tables.len(), 1);
-    let mut table = tables[0].clone(); // Clone to allow mutation
+        assert_eq!(tables.len(), 1);
+        let mut table = tables[0].clone(); // Clone to allow mutation
 
 
 
-    let fallback_node = make_html_comment_node(&arena);
+        let fallback_node = make_html_comment_node(&arena);
 
-    apply_marker_mode(&mut table, MarkerMode::OnlyRowAndColumn, fallback_node);
+        apply_marker_mode(&mut table, MarkerMode::OnlyRowAndColumn, fallback_node);
 
-    print_table(&table);
+        print_table(&table);
 
-    for (row_idx, row) in table.rows.iter().enumerate() {
-        for (col_idx, cell) in row.cells.iter().enumerate() {
-            let is_header_row = row_idx == 0;
-            let is_header_col = col_idx == 0;
-            let expected_marker = format!("!{}{}", (b'A' + col_idx as u8) as char, row_idx + 1);
-
-            let cell_comments = cell.comments();
-
-            if is_header_row || is_header_col {
-                assert!(
-                    cell_comments.iter().any(|c| c.comment.content == expected_marker),
-                    "Expected marker '{}' in cell ({}, {})",
-                    expected_marker, row_idx, col_idx
-                );
-            } else {
-                assert!(
-                    cell_comments.iter().all(|c| c.comment.kind != md_comments::CommentKind::Marker),
-                    "Expected no markers in cell ({}, {})",
-                    row_idx, col_idx
-                );
+        for (row_idx, row) in table.rows.iter().enumerate() {
+            for (col_idx, cell) in row.cells.iter().enumerate() {
+                let is_header_row = row_idx == 0;
+                let is_header_col = col_idx == 0;
+                let expected_marker = format!("!{}{}", (b'A' + col_idx as u8) as char, row_idx + 1);
+
+                let cell_comments = cell.comments();
+
+                if is_header_row || is_header_col {
+                    assert!(
+                        cell_comments.iter().any(|c| c.comment.content == expected_marker),
+                        "Expected marker '{}' in cell ({}, {})",
+                        expected_marker, row_idx, col_idx
+                    );
+                } else {
+                    assert!(
+                        cell_comments.iter().all(|c| c.comment.kind != md_comments::CommentKind::Marker),
+                        "Expected no markers in cell ({}, {})",
+                        row_idx, col_idx
+                    );
+                }
             }
         }
     }
+
+    #[test]
+    fn test_markers_in_directive_comments() {
+        let comment = |content: &str| HtmlComment {
+            content: content.to_string(),
+            kind: md_comments::extract_html_comments(&format!("<!--{}-->", content))[0].kind.clone(),
+            offset: 0,
+            length: 0,
+        };
+        assert_eq!(marker_of(&comment(" !D !col=B*C ")), Some("!D"));
+        assert_eq!(marker_of(&comment("!B3")), Some("!B3"));
+        assert_eq!(marker_of(&comment("!col=B*C")), None);
+        assert_eq!(marker_of(&comment("!table: rates")), None);
+        assert_eq!(marker_of(&comment("!col B*C")), None);
+        assert_eq!(marker_of(&comment("!total sales")), None);
+
+        let mut combined = comment(" !D !col=B*C ");
+        set_marker(&mut combined, "!E1");
+        assert_eq!(combined.content, "!E1 !col=B*C");
+
+        assert_eq!(parse_marker("!D"), (Some(3), None));
+        assert_eq!(parse_marker("!3"), (None, Some(2)));
+        assert_eq!(parse_marker("!AB12"), (Some(27), Some(11)));
+        assert_eq!(parse_marker("!3D"), (None, None));
+    }
 }
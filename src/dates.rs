@@ -0,0 +1,459 @@
+// dates.rs
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::evaluator::{CellError, Value};
+
+// Dates are spreadsheet serial numbers: whole days since 1899-12-30, with the
+// time of day as the fractional part. This matches spreadsheet serials for
+// every date from 1900-03-01 on.
+const UNIX_EPOCH_SERIAL: i64 = 25569;
+
+/// Serial of 9999-12-31, the last date spreadsheets accept. Keeping serials
+/// at or below it keeps the calendar arithmetic far from overflowing.
+const MAX_SERIAL: f64 = 2958465.0;
+
+/// Largest number of months `EDATE` and `EOMONTH` move a date by, or `DATE`
+/// accepts, more than spans the whole calendar.
+const MAX_MONTHS: i64 = 12 * 10000;
+
+const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October",
+    "November", "December",
+];
+
+const DAY_NAMES: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    days_from_civil(next_year, next_month, 1) - days_from_civil(year, month, 1)
+}
+
+/// Serial for a date, normalizing out-of-range months and days the way the
+/// DATE function does (`DATE(2026, 14, 1)` is 2027-02-01).
+pub fn serial_from_ymd(year: i64, month: i64, day: i64) -> f64 {
+    let year = year + (month - 1).div_euclid(12);
+    let month = (month - 1).rem_euclid(12) + 1;
+    (days_from_civil(year, month, 1) + day - 1 + UNIX_EPOCH_SERIAL) as f64
+}
+
+/// Whether `serial` is a date from 1899-12-30 up to the end of 9999-12-31.
+pub fn is_valid_serial(serial: f64) -> bool {
+    (0.0..MAX_SERIAL + 1.0).contains(&serial)
+}
+
+/// `serial_from_ymd` for function arguments: `#NUM!` unless the year is in
+/// 0..=9999 and the date it makes is a valid serial.
+fn checked_serial(year: i64, month: i64, day: i64) -> Result<f64, CellError> {
+    let day_limit = MAX_SERIAL as i64;
+    if !(0..=9999).contains(&year) || month.abs() > MAX_MONTHS || day.abs() > day_limit {
+        return Err(CellError::Num);
+    }
+    let serial = serial_from_ymd(year, month, day);
+    if is_valid_serial(serial) {
+        Ok(serial)
+    } else {
+        Err(CellError::Num)
+    }
+}
+
+pub fn ymd_from_serial(serial: f64) -> (i64, i64, i64) {
+    civil_from_days(serial.floor() as i64 - UNIX_EPOCH_SERIAL)
+}
+
+/// Today's serial in UTC.
+pub fn today() -> f64 {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    (seconds.div_euclid(86400) + UNIX_EPOCH_SERIAL) as f64
+}
+
+fn parse_fixed(text: &str, digits: usize) -> Option<i64> {
+    if text.len() == digits && text.chars().all(|c| c.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parses an ISO date (`2026-10-17`), returning its serial.
+pub fn parse_date(text: &str) -> Option<f64> {
+    let mut parts = text.split('-');
+    let year = parse_fixed(parts.next()?, 4)?;
+    let month = parse_fixed(parts.next()?, 2)?;
+    let day = parse_fixed(parts.next()?, 2)?;
+    if parts.next().is_some() || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some(serial_from_ymd(year, month, day))
+}
+
+/// Parses `HH:MM` or `HH:MM:SS`, returning the fraction of a day.
+pub fn parse_time(text: &str) -> Option<f64> {
+    let mut parts = text.split(':');
+    let hours: i64 = parts.next().filter(|p| (1..=2).contains(&p.len()))?.parse().ok()?;
+    let minutes = parse_fixed(parts.next()?, 2)?;
+    let seconds = match parts.next() {
+        Some(s) => parse_fixed(s, 2)?,
+        None => 0,
+    };
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+    Some((hours * 3600 + minutes * 60 + seconds) as f64 / 86400.0)
+}
+
+/// Parses a date, a time, or a date and time separated by `T` or a space.
+pub fn parse_date_time(text: &str) -> Option<f64> {
+    if let Some(serial) = parse_date(text) {
+        return Some(serial);
+    }
+    if let Some(fraction) = parse_time(text) {
+        return Some(fraction);
+    }
+    let (date, time) = text.split_once(['T', ' '])?;
+    Some(parse_date(date)? + parse_time(time.trim())?)
+}
+
+fn seconds_of_day(serial: f64) -> i64 {
+    ((serial - serial.floor()) * 86400.0).round() as i64
+}
+
+/// Default display: `2026-10-17`, `14:30` or `2026-10-17 14:30`.
+pub fn format_date_default(serial: f64) -> String {
+    let seconds = seconds_of_day(serial);
+    let time = if seconds % 60 == 0 {
+        format!("{:02}:{:02}", seconds / 3600, seconds / 60 % 60)
+    } else {
+        format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    };
+    if serial.floor() == 0.0 && seconds > 0 {
+        return time;
+    }
+    let (y, m, d) = ymd_from_serial(serial);
+    if seconds == 0 {
+        format!("{:04}-{:02}-{:02}", y, m, d)
+    } else {
+        format!("{:04}-{:02}-{:02} {}", y, m, d, time)
+    }
+}
+
+/// Whether a formatting comment has a `strftime`-style date or time field,
+/// e.g. `%Y`, as opposed to some other formatting such as `$`.
+pub fn is_date_pattern(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' && matches!(chars.next(), Some('Y' | 'y' | 'm' | 'd' | 'e' | 'b' | 'B' | 'a' | 'A' | 'j' | 'H' | 'I' | 'p' | 'M' | 'S')) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Formats a serial with a `strftime`-style pattern from a `%` formatting
+/// comment, e.g. `%d %b %Y`.
+pub fn format_date(serial: f64, pattern: &str) -> String {
+    let (y, m, d) = ymd_from_serial(serial);
+    let seconds = seconds_of_day(serial);
+    let (hour, minute, second) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let weekday = (serial.floor() as i64 - 1).rem_euclid(7) as usize; // serial 1 was a Sunday
+    let month_name = MONTH_NAMES[(m - 1) as usize];
+
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", y)),
+            Some('y') => out.push_str(&format!("{:02}", y.rem_euclid(100))),
+            Some('m') => out.push_str(&format!("{:02}", m)),
+            Some('d') => out.push_str(&format!("{:02}", d)),
+            Some('e') => out.push_str(&d.to_string()),
+            Some('b') => out.push_str(&month_name[..3]),
+            Some('B') => out.push_str(month_name),
+            Some('a') => out.push_str(&DAY_NAMES[weekday][..3]),
+            Some('A') => out.push_str(DAY_NAMES[weekday]),
+            Some('j') => out.push_str(&format!("{:03}", serial.floor() - serial_from_ymd(y, 1, 1) + 1.0)),
+            Some('H') => out.push_str(&format!("{:02}", hour)),
+            Some('I') => out.push_str(&format!("{:02}", (hour + 11) % 12 + 1)),
+            Some('p') => out.push_str(if hour < 12 { "AM" } else { "PM" }),
+            Some('M') => out.push_str(&format!("{:02}", minute)),
+            Some('S') => out.push_str(&format!("{:02}", second)),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+fn date_arg(value: &Value) -> Result<f64, CellError> {
+    let serial = match value.clone().scalar() {
+        Value::Text(text) => parse_date_time(text.trim()).ok_or(CellError::Value)?,
+        other => other.as_number()?,
+    };
+    if !is_valid_serial(serial) {
+        return Err(CellError::Num);
+    }
+    Ok(serial)
+}
+
+/// A number of months for `EDATE` and `EOMONTH`.
+fn months_arg(value: &Value) -> Result<i64, CellError> {
+    let months = int_arg(value)?;
+    if months.abs() > MAX_MONTHS {
+        return Err(CellError::Num);
+    }
+    Ok(months)
+}
+
+fn int_arg(value: &Value) -> Result<i64, CellError> {
+    Ok(value.as_number()?.trunc() as i64)
+}
+
+fn months_between(start: (i64, i64, i64), end: (i64, i64, i64)) -> i64 {
+    let mut months = (end.0 - start.0) * 12 + (end.1 - start.1);
+    if end.2 < start.2 {
+        months -= 1;
+    }
+    months
+}
+
+fn add_months(serial: f64, months: i64) -> (i64, i64, i64) {
+    let (y, m, d) = ymd_from_serial(serial);
+    let total = y * 12 + (m - 1) + months;
+    let (year, month) = (total.div_euclid(12), total.rem_euclid(12) + 1);
+    (year, month, d.min(days_in_month(year, month)))
+}
+
+fn is_weekend(serial: i64) -> bool {
+    // Serial 1 was a Sunday, so serial % 7 is 0 on Saturdays and 1 on Sundays.
+    matches!(serial.rem_euclid(7), 0 | 1)
+}
+
+/// The number of weekdays among the serials `0..serial`. Serial 0 was a
+/// Saturday, so each week from it starts with the two weekend days.
+fn weekdays_before(serial: i64) -> i64 {
+    serial.div_euclid(7) * 5 + (serial.rem_euclid(7) - 2).max(0)
+}
+
+/// Date functions. Returns `None` for names that are not date functions.
+pub fn call(name: &str, args: &[Value]) -> Option<Result<Value, CellError>> {
+    let arity = |min: usize, max: usize| {
+        if args.len() < min || args.len() > max {
+            Err(CellError::Value)
+        } else {
+            Ok(())
+        }
+    };
+
+    let result = match name {
+        "DATE" => arity(3, 3).and_then(|_| {
+            Ok(Value::Date(checked_serial(int_arg(&args[0])?, int_arg(&args[1])?, int_arg(&args[2])?)?))
+        }),
+        "YEAR" | "MONTH" | "DAY" => arity(1, 1).and_then(|_| {
+            let (y, m, d) = ymd_from_serial(date_arg(&args[0])?);
+            let part = match name {
+                "YEAR" => y,
+                "MONTH" => m,
+                _ => d,
+            };
+            Ok(Value::Number(part as f64))
+        }),
+        "DATEDIF" => arity(3, 3).and_then(|_| {
+            let (start, end) = (date_arg(&args[0])?.floor(), date_arg(&args[1])?.floor());
+            if start > end {
+                return Err(CellError::Num);
+            }
+            let (s, e) = (ymd_from_serial(start), ymd_from_serial(end));
+            let months = months_between(s, e);
+            let unit = args[2].as_text()?.to_ascii_uppercase();
+            let value = match unit.as_str() {
+                "D" => (end - start) as i64,
+                "M" => months,
+                "Y" => months / 12,
+                "YM" => months % 12,
+                "MD" => {
+                    let (y, m, _) = add_months(start, months);
+                    let anchor = serial_from_ymd(y, m, s.2.min(days_in_month(y, m)));
+                    (end - anchor) as i64
+                }
+                "YD" => {
+                    let (y, m, d) = add_months(start, months / 12 * 12);
+                    (end - serial_from_ymd(y, m, d)) as i64
+                }
+                _ => return Err(CellError::Num),
+            };
+            Ok(Value::Number(value as f64))
+        }),
+        "EDATE" => arity(2, 2).and_then(|_| {
+            let (y, m, d) = add_months(date_arg(&args[0])?, months_arg(&args[1])?);
+            Ok(Value::Date(checked_serial(y, m, d)?))
+        }),
+        "EOMONTH" => arity(2, 2).and_then(|_| {
+            let (y, m, _) = add_months(date_arg(&args[0])?, months_arg(&args[1])?);
+            Ok(Value::Date(checked_serial(y, m, days_in_month(y, m))?))
+        }),
+        "WEEKDAY" => arity(1, 2).and_then(|_| {
+            let serial = date_arg(&args[0])?.floor() as i64;
+            let sunday_based = (serial - 1).rem_euclid(7); // 0 = Sunday
+            let kind = match args.get(1) {
+                Some(v) => int_arg(v)?,
+                None => 1,
+            };
+            let value = match kind {
+                1 => sunday_based + 1,
+                2 => (sunday_based + 6) % 7 + 1,
+                3 => (sunday_based + 6) % 7,
+                _ => return Err(CellError::Num),
+            };
+            Ok(Value::Number(value as f64))
+        }),
+        "NETWORKDAYS" => arity(2, 3).and_then(|_| {
+            let start = date_arg(&args[0])?.floor() as i64;
+            let end = date_arg(&args[1])?.floor() as i64;
+            let holidays = match args.get(2) {
+                Some(h) => h
+                    .clone()
+                    .into_rows()
+                    .iter()
+                    .flatten()
+                    .filter(|v| !matches!(v, Value::Empty))
+                    .map(|v| date_arg(v).map(|s| s.floor() as i64))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => Vec::new(),
+            };
+            let (low, high, sign) = if start <= end { (start, end, 1) } else { (end, start, -1) };
+            let mut holidays: Vec<i64> = holidays
+                .into_iter()
+                .filter(|d| (low..=high).contains(d) && !is_weekend(*d))
+                .collect();
+            holidays.sort_unstable();
+            holidays.dedup();
+            let days = weekdays_before(high + 1) - weekdays_before(low) - holidays.len() as i64;
+            Ok(Value::Number((sign * days) as f64))
+        }),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> Value {
+        Value::Date(parse_date(text).unwrap())
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(parse_date("2026-10-17"), Some(46312.0));
+        assert_eq!(parse_date("1900-03-01"), Some(61.0));
+        assert_eq!(parse_date("2026-02-30"), None);
+        assert_eq!(parse_date_time("2026-10-17T12:00"), Some(46312.5));
+        assert_eq!(format_date_default(46312.5), "2026-10-17 12:00");
+        assert_eq!(format_date_default(parse_time("09:15").unwrap()), "09:15");
+        assert_eq!(format_date(46312.0, "%a %e %B %Y"), "Sat 17 October 2026");
+    }
+
+    #[test]
+    fn test_date_functions() {
+        let call = |name: &str, args: &[Value]| call(name, args).unwrap().unwrap();
+        let n = |x: f64| Value::Number(x);
+
+        assert_eq!(call("DATE", &[n(2026.0), n(14.0), n(1.0)]), date("2027-02-01"));
+        assert_eq!(call("EDATE", &[date("2026-01-31"), n(1.0)]), date("2026-02-28"));
+        assert_eq!(call("EOMONTH", &[date("2026-10-17"), n(-1.0)]), date("2026-09-30"));
+        assert_eq!(call("DATEDIF", &[date("2025-03-15"), date("2026-10-17"), Value::Text("M".into())]), n(19.0));
+        assert_eq!(call("DATEDIF", &[date("2025-03-15"), date("2026-10-17"), Value::Text("MD".into())]), n(2.0));
+        assert_eq!(call("DATEDIF", &[date("2025-03-15"), date("2026-10-17"), Value::Text("YD".into())]), n(216.0));
+        assert_eq!(call("WEEKDAY", &[date("2026-10-17")]), n(7.0));
+        assert_eq!(call("WEEKDAY", &[date("2026-10-17"), n(2.0)]), n(6.0));
+        assert_eq!(call("NETWORKDAYS", &[date("2026-10-12"), date("2026-10-25")]), n(10.0));
+        assert_eq!(
+            call(
+                "NETWORKDAYS",
+                &[date("2026-10-12"), date("2026-10-25"), Value::Array(vec![vec![date("2026-10-14")]])]
+            ),
+            n(9.0)
+        );
+        assert_eq!(call("YEAR", &[Value::Text("2026-10-17".into())]), n(2026.0));
+    }
+
+    #[test]
+    fn test_dates_outside_the_calendar() {
+        let call = |name: &str, args: &[Value]| call(name, args).unwrap();
+        let n = |x: f64| Value::Number(x);
+
+        assert_eq!(call("YEAR", &[n(1e300)]), Err(CellError::Num));
+        assert_eq!(call("YEAR", &[n(-1.0)]), Err(CellError::Num));
+        assert_eq!(call("YEAR", &[n(2958465.5)]), Ok(n(9999.0)));
+        assert_eq!(call("DATE", &[n(1e18), n(1.0), n(1.0)]), Err(CellError::Num));
+        assert_eq!(call("DATE", &[n(2026.0), n(1e18), n(1.0)]), Err(CellError::Num));
+        assert_eq!(call("DATE", &[n(2026.0), n(1.0), n(-1e18)]), Err(CellError::Num));
+        assert_eq!(call("DATE", &[n(9999.0), n(12.0), n(32.0)]), Err(CellError::Num));
+        assert_eq!(call("DATE", &[n(9999.0), n(12.0), n(31.0)]), Ok(Value::Date(2958465.0)));
+        assert_eq!(call("EDATE", &[n(1.0), n(1e18)]), Err(CellError::Num));
+        assert_eq!(call("EDATE", &[date("9999-12-01"), n(1.0)]), Err(CellError::Num));
+        assert_eq!(call("EOMONTH", &[n(1.0), n(-1e18)]), Err(CellError::Num));
+        assert_eq!(call("NETWORKDAYS", &[n(0.0), n(1e12)]), Err(CellError::Num));
+    }
+
+    #[test]
+    fn test_networkdays_counts_without_walking() {
+        let n = |x: f64| Value::Number(x);
+        let networkdays = |args: &[Value]| call("NETWORKDAYS", args).unwrap().unwrap();
+        for low in 0..15 {
+            for high in low..low + 30 {
+                let walked = (low..=high).filter(|d| !is_weekend(*d)).count() as f64;
+                assert_eq!(networkdays(&[n(low as f64), n(high as f64)]), n(walked));
+                if high > low {
+                    assert_eq!(networkdays(&[n(high as f64), n(low as f64)]), n(-walked));
+                }
+            }
+        }
+        // Holidays count once, and only on weekdays within the range.
+        let holidays = Value::Array(vec![
+            vec![date("2026-10-14")],
+            vec![date("2026-10-14")],
+            vec![date("2026-10-17")],
+            vec![date("2026-11-02")],
+        ]);
+        assert_eq!(networkdays(&[date("2026-10-12"), date("2026-10-25"), holidays]), n(9.0));
+        assert_eq!(networkdays(&[n(0.0), n(2958465.0)]), n(2113190.0));
+    }
+}
@@ -0,0 +1,304 @@
+// directives.rs
+
+use crate::md_comments::{extract_html_comments, CommentKind};
+use crate::table_parser::{MarkdownTable, TableCell};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Directive {
+    /// `<!-- !table: rates -->` on the line before a table names it.
+    Table { name: String },
+    /// `<!-- !col=B*C -->` in a header cell computes every body cell of that
+    /// column; bare column letters refer to the cell's own row.
+    Column { formula: String },
+    /// `<!-- !filter orders: C > 2 -->` on its own line generates the table
+    /// below it from the rows of `source` (by default the nearest table above)
+    /// for which `condition` is true.
+    Filter { source: Option<String>, condition: String },
+    /// `<!-- !pivot source=orders rows=Category values=SUM(Total) -->`
+    /// generates a table with one row per distinct value of the `rows`
+    /// column and one aggregate column per `(function, column)` value.
+    Pivot {
+        source: Option<String>,
+        rows: String,
+        values: Vec<(String, String)>,
+    },
+}
+
+impl Directive {
+    /// Whether the directive generates the table that follows it.
+    pub fn is_generator(&self) -> bool {
+        matches!(self, Directive::Filter { .. } | Directive::Pivot { .. })
+    }
+}
+
+/// Parses the content of a directive comment (`!keyword: arguments` or
+/// `!keyword arguments`).
+pub fn parse_directive(content: &str) -> Option<Directive> {
+    let body = content.trim().strip_prefix('!')?;
+    let keyword_end = body
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .unwrap_or(body.len());
+    let keyword = &body[..keyword_end];
+    let after_keyword = body[keyword_end..].trim_start();
+    let rest = after_keyword.strip_prefix([':', '=']).unwrap_or(after_keyword).trim();
+
+    match keyword.to_ascii_lowercase().as_str() {
+        "table" if is_table_name(rest) => Some(Directive::Table {
+            name: rest.to_string(),
+        }),
+        "col" if !rest.is_empty() => Some(Directive::Column {
+            formula: rest.to_string(),
+        }),
+        "filter" => {
+            // `!filter source: condition`, or `!filter: condition` for the
+            // nearest table above.
+            let (source, condition) = match rest.split_once(':') {
+                Some((source, condition)) if rest == after_keyword && is_table_name(source.trim()) => {
+                    (Some(source.trim().to_string()), condition.trim())
+                }
+                _ => (None, rest),
+            };
+            (!condition.is_empty()).then(|| Directive::Filter {
+                source,
+                condition: condition.to_string(),
+            })
+        }
+        "pivot" => parse_pivot(rest),
+        _ => None,
+    }
+}
+
+/// Splits `key=value key=value` arguments. A value runs until the next
+/// `key=`, so it may contain spaces.
+fn parse_arguments(text: &str) -> Vec<(String, String)> {
+    let mut arguments: Vec<(String, String)> = Vec::new();
+    for word in text.split_whitespace() {
+        match word.split_once('=') {
+            Some((key, value)) if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphabetic()) => {
+                arguments.push((key.to_ascii_lowercase(), value.to_string()));
+            }
+            _ => match arguments.last_mut() {
+                Some((_, value)) => {
+                    value.push(' ');
+                    value.push_str(word);
+                }
+                None => arguments.push((String::new(), word.to_string())),
+            },
+        }
+    }
+    arguments
+}
+
+fn parse_pivot(rest: &str) -> Option<Directive> {
+    let (mut source, mut rows, mut values) = (None, None, Vec::new());
+    for (key, value) in parse_arguments(rest) {
+        match key.as_str() {
+            "source" if is_table_name(&value) => source = Some(value),
+            "rows" if !value.is_empty() => rows = Some(value),
+            "values" => {
+                for part in value.split(')').map(|p| p.trim_start_matches([',', ' '])) {
+                    if part.is_empty() {
+                        continue;
+                    }
+                    let (function, column) = part.split_once('(')?;
+                    values.push((function.trim().to_ascii_uppercase(), column.trim().to_string()));
+                }
+            }
+            _ => return None,
+        }
+    }
+    if values.is_empty() {
+        return None;
+    }
+    Some(Directive::Pivot {
+        source,
+        rows: rows?,
+        values,
+    })
+}
+
+/// Parses every directive in a comment that may combine several `!` parts,
+/// such as a marker and a directive (`!D !col=B*C`). Parts start at a `!`
+/// at the beginning of the comment or after whitespace.
+pub fn parse_directives(content: &str) -> Vec<Directive> {
+    let content = content.trim();
+    let mut starts: Vec<usize> = content
+        .char_indices()
+        .filter(|&(i, c)| c == '!' && (i == 0 || content[..i].ends_with(char::is_whitespace)))
+        .map(|(i, _)| i)
+        .collect();
+    starts.push(content.len());
+    starts
+        .windows(2)
+        .filter_map(|w| parse_directive(&content[w[0]..w[1]]))
+        .collect()
+}
+
+/// The column formula declared in a header cell, if any.
+pub fn column_formula(cell: &TableCell) -> Option<String> {
+    cell.comments()
+        .into_iter()
+        .filter(|c| c.comment.kind == CommentKind::Directive)
+        .flat_map(|c| parse_directives(&c.comment.content))
+        .find_map(|d| match d {
+            Directive::Column { formula } => Some(formula),
+            _ => None,
+        })
+}
+
+fn is_table_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Start offset of the nearest non-blank line above `offset`, provided that
+/// line holds nothing but HTML comments.
+pub fn comment_line_before(markdown: &str, offset: usize) -> Option<usize> {
+    let mut end = offset;
+    loop {
+        let start = markdown[..end].rfind('\n').map_or(0, |i| i + 1);
+        if !markdown[start..end].trim().is_empty() {
+            return only_comments(&markdown[start..end]).then_some(start);
+        }
+        if start == 0 {
+            return None;
+        }
+        end = start - 1;
+    }
+}
+
+/// Whether the line right above the table starting at `offset` holds a
+/// generator directive, which makes the table a generated one. A blank line
+/// in between makes it the user's.
+pub fn follows_generator(markdown: &str, offset: usize) -> bool {
+    let line_start = markdown[..offset].rfind('\n').map_or(0, |i| i + 1);
+    if line_start == 0 {
+        return false;
+    }
+    let above = markdown[..line_start - 1].rfind('\n').map_or(0, |i| i + 1);
+    directives_on_line(&markdown[above..line_start - 1]).iter().any(Directive::is_generator)
+}
+
+/// The directives of a line that holds nothing but HTML comments.
+pub fn directives_on_line(line: &str) -> Vec<Directive> {
+    if !only_comments(line) {
+        return Vec::new();
+    }
+    extract_html_comments(line)
+        .iter()
+        .filter(|c| c.kind == CommentKind::Directive)
+        .flat_map(|c| parse_directives(&c.content))
+        .collect()
+}
+
+/// Directives found on the nearest non-blank line above `offset`, provided
+/// that line holds nothing but HTML comments.
+pub fn directives_before(markdown: &str, offset: usize) -> Vec<Directive> {
+    match comment_line_before(markdown, offset) {
+        Some(start) => directives_on_line(markdown[start..].lines().next().unwrap_or("")),
+        None => Vec::new(),
+    }
+}
+
+fn only_comments(line: &str) -> bool {
+    let mut rest = line.trim();
+    while let Some(after_open) = rest.strip_prefix("<!--") {
+        match after_open.find("-->") {
+            Some(end) => rest = after_open[end + 3..].trim_start(),
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+/// Names every table: the name from a preceding `!table` directive, or
+/// `Table1`, `Table2`, ... by position in the document. Generated tables
+/// are left out of the numbering, so that adding one does not rename the
+/// tables after it, and have no name unless they are given one.
+pub fn table_names(markdown: &str, tables: &[MarkdownTable]) -> Vec<String> {
+    let mut number = 0;
+    tables
+        .iter()
+        .map(|table| {
+            let directives = directives_before(markdown, table.start_offset);
+            let named = directives.iter().find_map(|d| match d {
+                Directive::Table { name } => Some(name.clone()),
+                _ => None,
+            });
+            named.unwrap_or_else(|| {
+                if follows_generator(markdown, table.start_offset) {
+                    return String::new();
+                }
+                number += 1;
+                format!("Table{}", number)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_table_directive() {
+        assert_eq!(
+            parse_directive(" !table: rates "),
+            Some(Directive::Table { name: "rates".to_string() })
+        );
+        assert_eq!(
+            parse_directive("!table orders"),
+            Some(Directive::Table { name: "orders".to_string() })
+        );
+        assert_eq!(parse_directive("!table: two words"), None);
+
+        let markdown = "Intro <!-- !table: nope -->\n\n<!-- !table: rates -->\n| a |\n";
+        let offset = markdown.find("| a").unwrap();
+        assert_eq!(
+            directives_before(markdown, offset),
+            vec![Directive::Table { name: "rates".to_string() }]
+        );
+        assert!(directives_before(markdown, markdown.find("\n\n").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_parse_column_directive() {
+        let column = |formula: &str| Directive::Column { formula: formula.to_string() };
+        assert_eq!(parse_directive("!col=B*C"), Some(column("B*C")));
+        assert_eq!(parse_directive("!col: B * C "), Some(column("B * C")));
+        assert_eq!(parse_directives("!D !col=rates!B2 * C"), vec![column("rates!B2 * C")]);
+        assert!(parse_directives("!D").is_empty());
+    }
+
+    #[test]
+    fn test_parse_filter_directive() {
+        let filter = |source: Option<&str>, condition: &str| Directive::Filter {
+            source: source.map(str::to_string),
+            condition: condition.to_string(),
+        };
+        assert_eq!(parse_directive("!filter: C > 2"), Some(filter(None, "C > 2")));
+        assert_eq!(parse_directive("!filter orders: [@Qty] > 2"), Some(filter(Some("orders"), "[@Qty] > 2")));
+        assert_eq!(parse_directive("!filter: SUM(B2:B3) > A"), Some(filter(None, "SUM(B2:B3) > A")));
+        assert_eq!(parse_directive("!filter:"), None);
+    }
+
+    #[test]
+    fn test_parse_pivot_directive() {
+        let value = |f: &str, c: &str| (f.to_string(), c.to_string());
+        assert_eq!(
+            parse_directive("!pivot source=orders rows=Category values=SUM(Total), count(Unit Price)"),
+            Some(Directive::Pivot {
+                source: Some("orders".to_string()),
+                rows: "Category".to_string(),
+                values: vec![value("SUM", "Total"), value("COUNT", "Unit Price")],
+            })
+        );
+        assert!(matches!(
+            parse_directive("!pivot rows=Fruit Type values=AVERAGE(Price)"),
+            Some(Directive::Pivot { source: None, ref rows, .. }) if rows == "Fruit Type"
+        ));
+        assert_eq!(parse_directive("!pivot rows=Category"), None);
+        assert_eq!(parse_directive("!pivot rows=Category values=SUM"), None);
+        assert_eq!(parse_directive("!pivot colour=red rows=A values=SUM(B)"), None);
+    }
+}
@@ -0,0 +1,252 @@
+// document.rs
+
+use crate::dates;
+use crate::directives::table_names;
+use crate::evaluator::Workbook;
+use crate::export::{export_formulas, export_table, ExportFormat, ExportOptions};
+use crate::fix_refs::fix_references;
+use crate::formula::parse_cell_ref;
+use crate::html::render_html;
+use crate::import::{import_sheet, import_table, replace_table, replace_table_from_sheet, ImportOptions, SheetImportOptions};
+use crate::json::document_json;
+use crate::recalc::{recalculate_markdown, set_cell_value, RecalcOptions};
+use crate::sort::{find_table, sort_markdown, SortOptions};
+use crate::spreadsheet::{export_workbook, SpreadsheetFormat};
+use crate::table_parser::{MarkdownTable, TableParser};
+use crate::table_writer::render_document;
+
+/// A Markdown document holding spreadsheet tables.
+///
+/// ```
+/// use mdcalc::{Document, RecalcOptions};
+///
+/// let mut doc = Document::parse("| a | b |\n|---|---|\n| 2 | <!-- =A2*3 --> |\n");
+/// doc.recalculate(&RecalcOptions::default());
+/// assert_eq!(doc.render(), "| a | b |\n|---|---|\n| 2 | 6 <!-- =A2*3 --> |\n");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    markdown: String,
+}
+
+/// A snapshot of one table of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    /// The name from a `!table` directive, `Table1`, `Table2`, ... otherwise,
+    /// or empty for an unnamed generated table.
+    pub name: String,
+    /// The cells row by row, the header row first.
+    pub rows: Vec<Vec<Cell>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    /// The visible text, trimmed, without comments.
+    pub text: String,
+    /// The formula computing the cell, without the leading `=`.
+    pub formula: Option<String>,
+}
+
+impl Document {
+    pub fn parse(markdown: &str) -> Document {
+        Document {
+            markdown: markdown.to_string(),
+        }
+    }
+
+    /// A document holding one table with the rows of CSV or TSV data, the
+    /// first row being the header.
+    pub fn from_delimited(data: &str, options: &ImportOptions) -> Result<Document, String> {
+        Ok(Document {
+            markdown: format!("{}\n", import_table(data, options)?),
+        })
+    }
+
+    /// A document holding one table with a range of a worksheet of an xlsx,
+    /// xls or ods file. Formula cells keep their value as text and their
+    /// formula in a comment. Also returns the functions those formulas use
+    /// that mdcalc does not support.
+    pub fn from_sheet(data: &[u8], options: &SheetImportOptions) -> Result<(Document, Vec<String>), String> {
+        let (table, unsupported) = import_sheet(data, options)?;
+        Ok((Document { markdown: format!("{}\n", table) }, unsupported))
+    }
+
+    /// The tables of the document in order of appearance.
+    pub fn tables(&self) -> Vec<Table> {
+        let tables = TableParser::parse_tables(&self.markdown);
+        let names = table_names(&self.markdown, &tables);
+        Workbook::from_tables(&tables, &names)
+            .sheets
+            .into_iter()
+            .map(|sheet| Table {
+                name: sheet.name,
+                rows: sheet
+                    .cells
+                    .into_iter()
+                    .map(|row| {
+                        row.into_iter()
+                            .map(|cell| Cell {
+                                text: cell.text,
+                                formula: cell.formula,
+                            })
+                            .collect()
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// The parsed tables of the document, with every cell split into text
+    /// and comments. The tables own their data, so they can be kept after
+    /// the document is dropped, cached or sent to other threads.
+    pub fn markdown_tables(&self) -> Vec<MarkdownTable> {
+        TableParser::parse_tables(&self.markdown)
+    }
+
+    /// Recalculates every formula and regenerates generated tables.
+    pub fn recalculate(&mut self, options: &RecalcOptions) {
+        self.markdown = recalculate_markdown(&self.markdown, options);
+    }
+
+    /// Points formula references back at the cells recorded by the markers,
+    /// after rows or columns were inserted, deleted or moved.
+    pub fn fix_references(&mut self) {
+        self.markdown = fix_references(&self.markdown);
+    }
+
+    /// Sorts the body rows of a table, keeping formulas pointing at the same
+    /// cells. Formula cells sort by their values as of `today` (a serial
+    /// date; the current date when `None`).
+    pub fn sort(&mut self, options: &SortOptions, today: Option<f64>) -> Result<(), String> {
+        let today = today.unwrap_or_else(dates::today);
+        self.markdown = sort_markdown(&self.markdown, options, today)?;
+        Ok(())
+    }
+
+    /// Replaces the visible text of a cell, keeping its comments. `table` is
+    /// a one based table number or a table name, and `cell` an A1 address
+    /// such as `B2`. Formulas are not recalculated.
+    pub fn set_cell(&mut self, table: &str, cell: &str, text: &str) -> Result<(), String> {
+        let originals = TableParser::parse_tables(&self.markdown);
+        let names = table_names(&self.markdown, &originals);
+
+        let index = find_table(table, &names).ok_or_else(|| format!("no table '{}'", table))?;
+        let address = parse_cell_ref(cell).ok_or_else(|| format!("invalid cell '{}'", cell))?;
+        let mut tables = originals.clone();
+        let target = tables[index]
+            .rows
+            .get_mut(address.row)
+            .and_then(|r| r.cells.get_mut(address.col))
+            .ok_or_else(|| format!("no cell {} in table '{}'", cell, table))?;
+        // Keep the text on one line and its pipes out of the table syntax.
+        let text = text.replace(['\r', '\n'], " ").replace('|', "\\|");
+        set_cell_value(target, text.trim());
+
+        self.markdown = render_document(&self.markdown, &originals, &tables);
+        Ok(())
+    }
+
+    /// Replaces the rows of a table with CSV or TSV data, keeping the columns
+    /// computed by a `!col=` formula. `table` is a one based table number or
+    /// a table name.
+    pub fn replace_table(&mut self, table: &str, data: &str, options: &ImportOptions) -> Result<(), String> {
+        self.markdown = replace_table(&self.markdown, table, data, options)?;
+        Ok(())
+    }
+
+    /// Replaces the rows of a table with a range of a worksheet, like
+    /// `replace_table`. Returns the functions the imported formulas use that
+    /// mdcalc does not support.
+    pub fn replace_table_from_sheet(
+        &mut self,
+        table: &str,
+        data: &[u8],
+        options: &SheetImportOptions,
+    ) -> Result<Vec<String>, String> {
+        let (markdown, unsupported) = replace_table_from_sheet(&self.markdown, table, data, options)?;
+        self.markdown = markdown;
+        Ok(unsupported)
+    }
+
+    /// The values of a table as CSV or TSV, as they currently appear in the
+    /// document; call `recalculate` first for up to date values.
+    pub fn export(&self, options: &ExportOptions) -> Result<String, String> {
+        export_table(&self.markdown, options)
+    }
+
+    /// The formulas of a table as CSV or TSV, laid out like the table.
+    pub fn export_formulas(&self, table: &str, format: ExportFormat) -> Result<String, String> {
+        export_formulas(&self.markdown, table, format)
+    }
+
+    /// Every table as a worksheet of an xlsx or ods file, with formulas
+    /// translated for the spreadsheet and the values they compute to as of
+    /// `today` (a serial date; the current date when `None`).
+    pub fn export_workbook(&self, format: SpreadsheetFormat, today: Option<f64>) -> Result<Vec<u8>, String> {
+        export_workbook(&self.markdown, format, today.unwrap_or_else(dates::today))
+    }
+
+    /// Every table as JSON: cell addresses, source text, comments with their
+    /// kinds and spans, formulas, and the values they compute to as of
+    /// `today` (a serial date; the current date when `None`). See the README
+    /// for the layout.
+    pub fn to_json(&self, today: Option<f64>) -> String {
+        document_json(&self.markdown, today.unwrap_or_else(dates::today))
+    }
+
+    /// The document as HTML, with the formula of every computed cell in the
+    /// `title` and `data-formula` attributes of a `<span>` around its value
+    /// and mdcalc's comments left out; call `recalculate` first for up to
+    /// date values.
+    pub fn to_html(&self) -> String {
+        render_html(&self.markdown)
+    }
+
+    /// The Markdown text of the document.
+    pub fn render(&self) -> String {
+        self.markdown.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_edit_and_recalculate() {
+        let markdown = "\
+<!-- !table: fruit -->
+| Item | Price | Quantity | Total <!-- !col=B*C --> |
+|------|-------|----------|-------------------------|
+| Apples | 2 | 3 | 6 |
+| Bananas | 1 | 5 | 5 |
+
+Done.
+";
+        let mut doc = Document::parse(markdown);
+        let tables = doc.tables();
+        assert_eq!(tables.len(), 1);
+        assert_eq!(tables[0].name, "fruit");
+        assert_eq!(tables[0].rows[1][0].text, "Apples");
+        assert_eq!(tables[0].rows[1][3].formula.as_deref(), Some("B*C"));
+
+        doc.set_cell("fruit", "B3", "4").unwrap();
+        doc.recalculate(&RecalcOptions::default());
+        assert!(doc.render().contains("| Bananas | 4 | 5 | 20 |"), "{}", doc.render());
+        assert!(doc.render().ends_with("\nDone.\n"));
+
+        doc.set_cell("1", "A2", "Red | Green").unwrap();
+        assert_eq!(doc.tables()[0].rows[1][0].text, "Red \\| Green");
+        assert!(doc.set_cell("fruit", "E2", "x").is_err());
+        assert!(doc.set_cell("Table1", "A2", "x").is_err());
+        assert!(doc.set_cell("fruit", "2A", "x").is_err());
+
+        let options = SortOptions {
+            table: "fruit".to_string(),
+            by: "Total".to_string(),
+            descending: true,
+        };
+        doc.sort(&options, Some(0.0)).unwrap();
+        assert_eq!(doc.tables()[0].rows[1][0].text, "Bananas");
+    }
+}
@@ -0,0 +1,894 @@
+// evaluator.rs
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::dates;
+use crate::directives::column_formula;
+use crate::formula::{formula_source, parse_column_formula, parse_formula_at, BinaryOp, CellRef, Expr, Reference, UnaryOp};
+use crate::functions;
+use crate::md_comments::CommentKind;
+use crate::table_parser::MarkdownTable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellError {
+    Div0,
+    Value,
+    Ref,
+    Name,
+    NA,
+    Num,
+    Circular,
+    Parse,
+    Spill,
+    Calc,
+}
+
+impl CellError {
+    const ALL: [CellError; 10] = [
+        CellError::Div0,
+        CellError::Value,
+        CellError::Ref,
+        CellError::Name,
+        CellError::NA,
+        CellError::Num,
+        CellError::Circular,
+        CellError::Parse,
+        CellError::Spill,
+        CellError::Calc,
+    ];
+
+    /// The error written as `code` (`#REF!`), ignoring case.
+    pub fn from_code(code: &str) -> Option<CellError> {
+        CellError::ALL.into_iter().find(|e| e.to_string().eq_ignore_ascii_case(code))
+    }
+}
+
+impl fmt::Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = match self {
+            CellError::Div0 => "#DIV/0!",
+            CellError::Value => "#VALUE!",
+            CellError::Ref => "#REF!",
+            CellError::Name => "#NAME?",
+            CellError::NA => "#N/A",
+            CellError::Num => "#NUM!",
+            CellError::Circular => "#CIRC!",
+            CellError::Parse => "#ERROR!",
+            CellError::Spill => "#SPILL!",
+            CellError::Calc => "#CALC!",
+        };
+        f.write_str(code)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Empty,
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    /// A date and/or time as a spreadsheet serial number (see `dates`).
+    Date(f64),
+    Error(CellError),
+    Array(Vec<Vec<Value>>),
+}
+
+impl Value {
+    /// Interprets the visible text of a cell the way a spreadsheet would
+    /// interpret typed input.
+    pub fn from_cell_text(text: &str) -> Value {
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            return Value::Empty;
+        }
+        if let Some(n) = parse_number(trimmed) {
+            return Value::Number(n);
+        }
+        if let Some(serial) = dates::parse_date_time(trimmed) {
+            return Value::Date(serial);
+        }
+        if trimmed.eq_ignore_ascii_case("TRUE") {
+            return Value::Bool(true);
+        }
+        if trimmed.eq_ignore_ascii_case("FALSE") {
+            return Value::Bool(false);
+        }
+        Value::Text(trimmed.to_string())
+    }
+
+    /// Collapses an array to its top-left element; other values are returned
+    /// unchanged.
+    pub fn scalar(self) -> Value {
+        match self {
+            Value::Array(rows) => rows
+                .into_iter()
+                .next()
+                .and_then(|r| r.into_iter().next())
+                .unwrap_or(Value::Empty),
+            other => other,
+        }
+    }
+
+    pub fn as_number(&self) -> Result<f64, CellError> {
+        match self {
+            Value::Empty => Ok(0.0),
+            Value::Number(n) | Value::Date(n) => Ok(*n),
+            Value::Bool(b) => Ok(if *b { 1.0 } else { 0.0 }),
+            Value::Text(s) => parse_number(s.trim()).ok_or(CellError::Value),
+            Value::Error(e) => Err(*e),
+            Value::Array(_) => self.clone().scalar().as_number(),
+        }
+    }
+
+    pub fn as_text(&self) -> Result<String, CellError> {
+        match self {
+            Value::Error(e) => Err(*e),
+            Value::Array(_) => self.clone().scalar().as_text(),
+            other => Ok(other.to_string()),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, CellError> {
+        match self {
+            Value::Empty => Ok(false),
+            Value::Bool(b) => Ok(*b),
+            Value::Number(n) | Value::Date(n) => Ok(*n != 0.0),
+            Value::Text(s) if s.eq_ignore_ascii_case("TRUE") => Ok(true),
+            Value::Text(s) if s.eq_ignore_ascii_case("FALSE") => Ok(false),
+            Value::Text(_) => Err(CellError::Value),
+            Value::Error(e) => Err(*e),
+            Value::Array(_) => self.clone().scalar().as_bool(),
+        }
+    }
+
+    /// Rows of the value viewed as a 2D range; scalars are a 1x1 range.
+    pub fn into_rows(self) -> Vec<Vec<Value>> {
+        match self {
+            Value::Array(rows) => rows,
+            other => vec![vec![other]],
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Empty => Ok(()),
+            Value::Number(n) => f.write_str(&format_number(*n)),
+            Value::Date(serial) => f.write_str(&dates::format_date_default(*serial)),
+            Value::Text(s) => f.write_str(s),
+            Value::Bool(b) => f.write_str(if *b { "TRUE" } else { "FALSE" }),
+            Value::Error(e) => write!(f, "{}", e),
+            Value::Array(_) => write!(f, "{}", self.clone().scalar()),
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (body, scale) = match text.strip_suffix('%') {
+        Some(body) => (body.trim_end(), 0.01),
+        None => (text, 1.0),
+    };
+    let first = body.chars().next()?;
+    if !(first.is_ascii_digit() || matches!(first, '-' | '+' | '.')) {
+        return None;
+    }
+    body.replace(',', "").parse::<f64>().ok().map(|n| n * scale)
+}
+
+/// Formats a number without trailing zeros, rounding away floating point
+/// noise such as `0.30000000000000004`.
+pub fn format_number(n: f64) -> String {
+    if n.is_finite() && n.fract() == 0.0 && n.abs() < 1e15 {
+        return format!("{}", n as i64);
+    }
+    let formatted = format!("{:.10}", n);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    if formatted == "-0" {
+        "0".to_string()
+    } else {
+        formatted.to_string()
+    }
+}
+
+/// Formats a number with the printf-style fields of a `%` formatting
+/// comment: `%.2f` for two decimals, `%,.2f` with thousands separators and
+/// `%f` for six decimals. `%%` is a percent sign and other text is kept.
+/// `None` if the pattern has no number field or an unknown one.
+pub fn format_number_pattern(n: f64, pattern: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut found = false;
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            out.push('%');
+            continue;
+        }
+        let grouped = chars.next_if_eq(&',').is_some();
+        let decimals = match chars.next_if_eq(&'.') {
+            Some(_) => {
+                let mut digits = String::new();
+                while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(d);
+                }
+                digits.parse::<usize>().ok().filter(|d| *d <= 15)?
+            }
+            None => 6,
+        };
+        if chars.next() != Some('f') {
+            return None;
+        }
+        found = true;
+        let text = format!("{:.*}", decimals, n);
+        // Rounding a small negative number to zero leaves no sign.
+        let text = match text.strip_prefix('-') {
+            Some(rest) if rest.chars().all(|c| c == '0' || c == '.') => rest.to_string(),
+            _ => text,
+        };
+        out.push_str(&if grouped { group_thousands(&text) } else { text });
+    }
+    found.then_some(out)
+}
+
+/// Inserts a `,` between each group of three digits before the decimal point.
+fn group_thousands(text: &str) -> String {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text),
+    };
+    let (whole, fraction) = match digits.find('.') {
+        Some(dot) => digits.split_at(dot),
+        None => (digits, ""),
+    };
+    let mut grouped = String::new();
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    format!("{}{}{}", sign, grouped, fraction)
+}
+
+/// Renders a computed value for a cell, applying the cell's `%` formatting
+/// comment. Patterns with date fields such as `%Y` format dates, and numbers
+/// that are valid serials; numbers outside the calendar stay numbers. Other
+/// patterns format numbers and dates with number fields such as `%.2f`, and
+/// show `#VALUE!` when they have none.
+pub fn format_value(value: &Value, format: Option<&str>) -> String {
+    match (value.clone().scalar(), format) {
+        (Value::Date(serial), _) if !dates::is_valid_serial(serial) => CellError::Num.to_string(),
+        (Value::Date(serial), Some(pattern)) if dates::is_date_pattern(pattern) => dates::format_date(serial, pattern),
+        (Value::Number(serial), Some(pattern)) if dates::is_date_pattern(pattern) => {
+            if dates::is_valid_serial(serial) {
+                dates::format_date(serial, pattern)
+            } else {
+                format_number(serial)
+            }
+        }
+        (Value::Number(n) | Value::Date(n), Some(pattern)) => {
+            format_number_pattern(n, pattern).unwrap_or_else(|| CellError::Value.to_string())
+        }
+        (other, _) => other.to_string(),
+    }
+}
+
+/// Spreadsheet ordering: numbers < text < booleans, text compared without
+/// regard to case. Empty compares as zero or as the empty string.
+pub fn compare_values(a: &Value, b: &Value) -> Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Number(_) | Value::Date(_) | Value::Empty => 0,
+            Value::Text(_) => 1,
+            Value::Bool(_) => 2,
+            Value::Error(_) | Value::Array(_) => 3,
+        }
+    }
+    match (a, b) {
+        (Value::Empty, Value::Text(t)) => "".cmp(&t.to_lowercase()[..]),
+        (Value::Text(t), Value::Empty) => t.to_lowercase()[..].cmp(""),
+        (
+            Value::Number(_) | Value::Date(_) | Value::Empty,
+            Value::Number(_) | Value::Date(_) | Value::Empty,
+        ) => {
+            let x = a.as_number().unwrap_or(0.0);
+            let y = b.as_number().unwrap_or(0.0);
+            x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+        }
+        (Value::Text(x), Value::Text(y)) => x.to_lowercase().cmp(&y.to_lowercase()),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CellAddr {
+    pub sheet: usize,
+    pub row: usize,
+    pub col: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SheetCell {
+    pub text: String,
+    pub formula: Option<String>,
+    /// Whether `formula` comes from a `!col=` directive in the header.
+    pub column_formula: bool,
+    /// Whether the text is a value spilled by an array formula on an
+    /// earlier run, which a spill may overwrite.
+    pub spilled: bool,
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sheet {
+    pub name: String,
+    pub cells: Vec<Vec<SheetCell>>,
+}
+
+impl Sheet {
+    /// Builds a sheet from a table. Body cells without a formula of their own
+    /// take the column formula declared in their header cell, if any.
+    pub fn from_table(name: &str, table: &MarkdownTable) -> Sheet {
+        let column_formulas: Vec<Option<String>> = table
+            .rows
+            .first()
+            .map(|header| header.cells.iter().map(column_formula).collect())
+            .unwrap_or_default();
+
+        let cells = table
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(row_idx, row)| {
+                row.cells
+                    .iter()
+                    .enumerate()
+                    .map(|(col_idx, cell)| {
+                        let own = cell
+                            .comments()
+                            .into_iter()
+                            .filter(|c| c.comment.kind == CommentKind::Formula)
+                            .find_map(|c| formula_source(&c.comment.content))
+                            .map(str::to_string);
+                        let from_column = match (&own, row_idx) {
+                            (None, 1..) => column_formulas.get(col_idx).cloned().flatten(),
+                            _ => None,
+                        };
+                        SheetCell {
+                            text: cell.text_content().trim().to_string(),
+                            column_formula: from_column.is_some(),
+                            formula: own.or(from_column),
+                            spilled: cell.comments().iter().any(|c| c.comment.kind == CommentKind::Spill),
+                            format: cell
+                                .comments()
+                                .into_iter()
+                                .find(|c| c.comment.kind == CommentKind::Formatting)
+                                .map(|c| c.comment.content.trim().to_string()),
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Sheet {
+            name: name.to_string(),
+            cells,
+        }
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn col_count(&self) -> usize {
+        self.cells.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    pub fn cell(&self, row: usize, col: usize) -> Option<&SheetCell> {
+        self.cells.get(row).and_then(|r| r.get(col))
+    }
+
+    /// Index of the first of the trailing summary rows: rows holding a
+    /// formula of their own that aggregates rows of the table, such as
+    /// `SUM(D:above)`. The row count if there are none.
+    pub fn summary_start(&self) -> usize {
+        let is_summary = |row: usize| {
+            self.cells[row].iter().enumerate().any(|(col, cell)| {
+                !cell.column_formula
+                    && cell
+                        .formula
+                        .as_deref()
+                        .and_then(|source| parse_formula_at(source, row, col).ok())
+                        .is_some_and(|expr| summarizes(&expr))
+            })
+        };
+        let mut start = self.row_count();
+        while start > 1 && is_summary(start - 1) {
+            start -= 1;
+        }
+        start
+    }
+
+    /// The column whose header text matches `name`, ignoring case.
+    pub fn column_by_name(&self, name: &str) -> Option<usize> {
+        self.cells.first()?.iter().position(|c| c.text.eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// Whether a formula aggregates several rows of its own table.
+fn summarizes(expr: &Expr) -> bool {
+    match expr {
+        Expr::Reference { table: None, reference } => match reference {
+            Reference::Range(start, end) => start.row != end.row,
+            Reference::Columns(..) => true,
+            Reference::Structured { row, .. } => row.is_none(),
+            Reference::Cell(_) => false,
+        },
+        Expr::Unary(_, inner) | Expr::Percent(inner) => summarizes(inner),
+        Expr::Binary(_, left, right) => summarizes(left) || summarizes(right),
+        Expr::Call(_, args) => args.iter().any(summarizes),
+        Expr::Array(rows) => rows.iter().flatten().any(summarizes),
+        _ => false,
+    }
+}
+
+/// All tables of one document, addressable by name from formulas.
+#[derive(Debug, Clone, Default)]
+pub struct Workbook {
+    pub sheets: Vec<Sheet>,
+}
+
+impl Workbook {
+    pub fn from_tables(tables: &[MarkdownTable], names: &[String]) -> Workbook {
+        Workbook {
+            sheets: tables
+                .iter()
+                .zip(names)
+                .map(|(table, name)| Sheet::from_table(name, table))
+                .collect(),
+        }
+    }
+
+    pub fn sheet_index(&self, name: &str) -> Option<usize> {
+        self.sheets.iter().position(|s| s.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Evaluates every formula cell, with `TODAY()` returning `today`. The
+    /// result mirrors the sheet layout with `Some(value)` for formula cells
+    /// and for the cells that array results spill into, and `None` elsewhere.
+    /// Spills past the edge of a table make its rows longer or add rows.
+    pub fn evaluate(&self, today: f64) -> Vec<Vec<Vec<Option<Value>>>> {
+        // Formulas that read spilled cells see the spills of the previous
+        // pass, so repeat until the spills settle.
+        let mut spills = HashMap::new();
+        let mut results = Vec::new();
+        for _ in 0..3 {
+            let mut evaluator = Evaluator::new(self, today);
+            evaluator.spills = spills.clone();
+            let (next, next_spills) = self.evaluate_pass(&mut evaluator);
+            results = next;
+            if next_spills == spills {
+                break;
+            }
+            spills = next_spills;
+        }
+        results
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn evaluate_pass(&self, evaluator: &mut Evaluator) -> (Vec<Vec<Vec<Option<Value>>>>, HashMap<CellAddr, Value>) {
+        let mut results: Vec<Vec<Vec<Option<Value>>>> = self
+            .sheets
+            .iter()
+            .map(|s| s.cells.iter().map(|r| vec![None; r.len()]).collect())
+            .collect();
+        let mut spills = HashMap::new();
+
+        for (sheet, s) in self.sheets.iter().enumerate() {
+            for (row, cells) in s.cells.iter().enumerate() {
+                for (col, cell) in cells.iter().enumerate() {
+                    if cell.formula.is_none() {
+                        continue;
+                    }
+                    let value = evaluator.cell_value(CellAddr { sheet, row, col });
+                    let rows = match value {
+                        Value::Array(rows) if rows.len() * rows.first().map_or(0, Vec::len) > 1 => rows,
+                        other => {
+                            results[sheet][row][col] = Some(other.scalar());
+                            continue;
+                        }
+                    };
+
+                    // Every cell the array covers must be free: without a
+                    // formula or text of its own, and not taken by another
+                    // spill. Cells past the edge of the table are free, up to
+                    // a limit; the table grows to hold them.
+                    let targets: Vec<(CellAddr, Value)> = rows
+                        .into_iter()
+                        .enumerate()
+                        .flat_map(|(i, r)| {
+                            r.into_iter()
+                                .enumerate()
+                                .map(move |(j, v)| (CellAddr { sheet, row: row + i, col: col + j }, v))
+                        })
+                        .collect();
+                    let free = targets.iter().skip(1).all(|(addr, _)| {
+                        !spills.contains_key(addr)
+                            && match s.cell(addr.row, addr.col) {
+                                Some(c) => c.formula.is_none() && (c.text.is_empty() || c.spilled),
+                                None => {
+                                    addr.row < s.cells.len() + MAX_GROWTH && addr.col < s.col_count() + MAX_GROWTH
+                                }
+                            }
+                    });
+                    if !free {
+                        results[sheet][row][col] = Some(Value::Error(CellError::Spill));
+                        continue;
+                    }
+                    for (i, (addr, v)) in targets.into_iter().enumerate() {
+                        let rows = &mut results[sheet];
+                        if rows.len() <= addr.row {
+                            rows.resize(addr.row + 1, Vec::new());
+                        }
+                        if rows[addr.row].len() <= addr.col {
+                            rows[addr.row].resize(addr.col + 1, None);
+                        }
+                        rows[addr.row][addr.col] = Some(v.clone());
+                        if i > 0 {
+                            spills.insert(addr, v);
+                        }
+                    }
+                }
+            }
+        }
+        (results, spills)
+    }
+}
+
+/// How many rows or columns an array result may add to its table.
+pub const MAX_GROWTH: usize = 1000;
+
+enum CellState {
+    InProgress,
+    Done(Value),
+}
+
+pub struct Evaluator<'w> {
+    workbook: &'w Workbook,
+    cache: HashMap<CellAddr, CellState>,
+    today: f64,
+    /// Values spilled into cells without a formula.
+    spills: HashMap<CellAddr, Value>,
+}
+
+impl<'w> Evaluator<'w> {
+    pub fn new(workbook: &'w Workbook, today: f64) -> Self {
+        Evaluator {
+            workbook,
+            cache: HashMap::new(),
+            today,
+            spills: HashMap::new(),
+        }
+    }
+
+    pub fn cell_value(&mut self, addr: CellAddr) -> Value {
+        match self.cache.get(&addr) {
+            Some(CellState::Done(v)) => return v.clone(),
+            Some(CellState::InProgress) => return Value::Error(CellError::Circular),
+            None => {}
+        }
+
+        let Some(cell) = self.workbook.sheets.get(addr.sheet).and_then(|s| s.cell(addr.row, addr.col)) else {
+            return self.spills.get(&addr).cloned().unwrap_or(Value::Empty);
+        };
+
+        let value = match &cell.formula {
+            Some(source) => {
+                self.cache.insert(addr, CellState::InProgress);
+                let parsed = if cell.column_formula {
+                    parse_column_formula(source, addr.row, addr.col)
+                } else {
+                    parse_formula_at(source, addr.row, addr.col)
+                };
+                match parsed {
+                    Ok(expr) => self.eval(&expr, addr.sheet),
+                    Err(_) => Value::Error(CellError::Parse),
+                }
+            }
+            None => match self.spills.get(&addr) {
+                Some(value) => value.clone(),
+                None if cell.spilled => Value::Empty,
+                None => Value::from_cell_text(&cell.text),
+            },
+        };
+
+        self.cache.insert(addr, CellState::Done(value.clone()));
+        value
+    }
+
+    pub fn eval(&mut self, expr: &Expr, sheet: usize) -> Value {
+        match expr {
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Text(s) => Value::Text(s.clone()),
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Array(rows) => Value::Array(
+                rows.iter()
+                    .map(|r| r.iter().map(|e| self.eval(e, sheet).scalar()).collect())
+                    .collect(),
+            ),
+            Expr::Reference { table, reference } => {
+                let sheet = match table {
+                    Some(name) => match self.workbook.sheet_index(name) {
+                        Some(index) => index,
+                        None => return Value::Error(CellError::Ref),
+                    },
+                    None => sheet,
+                };
+                self.eval_reference(reference, sheet)
+            }
+            Expr::Name(_) => Value::Error(CellError::Name),
+            Expr::Error(code) => Value::Error(CellError::from_code(code).unwrap_or(CellError::Parse)),
+            Expr::Unary(op, inner) => map_values(self.eval(inner, sheet), &|value| match value.as_number() {
+                Ok(n) => Value::Number(match op {
+                    UnaryOp::Neg => -n,
+                    UnaryOp::Plus => n,
+                }),
+                Err(e) => Value::Error(e),
+            }),
+            Expr::Percent(inner) => map_values(self.eval(inner, sheet), &|value| match value.as_number() {
+                Ok(n) => Value::Number(n / 100.0),
+                Err(e) => Value::Error(e),
+            }),
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.eval(lhs, sheet);
+                let rhs = self.eval(rhs, sheet);
+                broadcast(lhs, rhs, &|l, r| binary_op(*op, l, r))
+            }
+            Expr::Call(name, args) => self.eval_call(name, args, sheet),
+        }
+    }
+
+    fn eval_reference(&mut self, reference: &Reference, sheet: usize) -> Value {
+        let s = &self.workbook.sheets[sheet];
+        match reference {
+            Reference::Cell(CellRef { row, col, .. }) => {
+                if *row >= s.row_count() || *col >= s.col_count() {
+                    return Value::Error(CellError::Ref);
+                }
+                self.cell_value(CellAddr { sheet, row: *row, col: *col }).scalar()
+            }
+            Reference::Range(start, end) => {
+                let (rows, cols) = (s.row_count(), s.col_count());
+                let row_range = start.row.min(end.row)..=start.row.max(end.row).min(rows.saturating_sub(1));
+                let col_range = start.col.min(end.col)..=start.col.max(end.col).min(cols.saturating_sub(1));
+                self.range_values(sheet, row_range, col_range)
+            }
+            Reference::Columns(first, last) => {
+                let (rows, cols) = (s.row_count(), s.col_count());
+                if *first >= cols {
+                    return Value::Error(CellError::Ref);
+                }
+                // Whole-column ranges skip the header row.
+                self.range_values(sheet, 1..=rows.saturating_sub(1), *first..=(*last).min(cols - 1))
+            }
+            Reference::Structured { column, row } => {
+                let Some(col) = s.column_by_name(column) else {
+                    return Value::Error(CellError::Ref);
+                };
+                match row {
+                    Some(row) if *row < s.row_count() => self.cell_value(CellAddr { sheet, row: *row, col }),
+                    Some(_) => Value::Error(CellError::Ref),
+                    None => self.range_values(sheet, 1..=s.row_count().saturating_sub(1), col..=col),
+                }
+            }
+        }
+    }
+
+    fn range_values(
+        &mut self,
+        sheet: usize,
+        rows: std::ops::RangeInclusive<usize>,
+        cols: std::ops::RangeInclusive<usize>,
+    ) -> Value {
+        if rows.is_empty() || cols.is_empty() {
+            return Value::Error(CellError::Ref);
+        }
+        Value::Array(
+            rows.map(|row| {
+                cols.clone()
+                    .map(|col| self.cell_value(CellAddr { sheet, row, col }))
+                    .map(Value::scalar)
+                    .collect()
+            })
+            .collect(),
+        )
+    }
+
+    fn eval_call(&mut self, name: &str, args: &[Expr], sheet: usize) -> Value {
+        // Functions that must not evaluate every argument.
+        match name {
+            "IF" => {
+                if args.len() < 2 || args.len() > 3 {
+                    return Value::Error(CellError::Value);
+                }
+                return match self.eval(&args[0], sheet).scalar().as_bool() {
+                    Ok(true) => self.eval(&args[1], sheet),
+                    Ok(false) => args.get(2).map_or(Value::Bool(false), |e| self.eval(e, sheet)),
+                    Err(e) => Value::Error(e),
+                };
+            }
+            "TODAY" => {
+                return if args.is_empty() {
+                    Value::Date(self.today)
+                } else {
+                    Value::Error(CellError::Value)
+                };
+            }
+            "IFERROR" | "IFNA" => {
+                if args.len() != 2 {
+                    return Value::Error(CellError::Value);
+                }
+                let value = self.eval(&args[0], sheet);
+                return match value {
+                    Value::Error(CellError::NA) => self.eval(&args[1], sheet),
+                    Value::Error(_) if name == "IFERROR" => self.eval(&args[1], sheet),
+                    other => other,
+                };
+            }
+            _ => {}
+        }
+
+        let values: Vec<Value> = args.iter().map(|a| self.eval(a, sheet)).collect();
+        functions::call(name, &values)
+    }
+}
+
+/// Applies `f` to a value, or to every element of an array.
+fn map_values(value: Value, f: &dyn Fn(&Value) -> Value) -> Value {
+    match value {
+        Value::Array(rows) => Value::Array(rows.iter().map(|r| r.iter().map(f).collect()).collect()),
+        other => f(&other),
+    }
+}
+
+/// Applies `f` element by element when either side is an array. A single
+/// row or column is repeated to match the other side; elements past the
+/// end of a shorter side are `#N/A`.
+fn broadcast(lhs: Value, rhs: Value, f: &dyn Fn(&Value, &Value) -> Value) -> Value {
+    if !matches!(lhs, Value::Array(_)) && !matches!(rhs, Value::Array(_)) {
+        return f(&lhs, &rhs);
+    }
+    let (lhs, rhs) = (lhs.into_rows(), rhs.into_rows());
+    let height = lhs.len().max(rhs.len());
+    let width = lhs.iter().chain(&rhs).map(Vec::len).max().unwrap_or(0);
+    fn pick(rows: &[Vec<Value>], i: usize, j: usize) -> Option<&Value> {
+        let row = rows.get(if rows.len() == 1 { 0 } else { i })?;
+        row.get(if row.len() == 1 { 0 } else { j })
+    }
+    Value::Array(
+        (0..height)
+            .map(|i| {
+                (0..width)
+                    .map(|j| match (pick(&lhs, i, j), pick(&rhs, i, j)) {
+                        (Some(l), Some(r)) => f(l, r),
+                        _ => Value::Error(CellError::NA),
+                    })
+                    .collect()
+            })
+            .collect(),
+    )
+}
+
+fn binary_op(op: BinaryOp, lhs: &Value, rhs: &Value) -> Value {
+    if let Value::Error(e) = lhs {
+        return Value::Error(*e);
+    }
+    if let Value::Error(e) = rhs {
+        return Value::Error(*e);
+    }
+
+    match op {
+        BinaryOp::Concat => Value::Text(format!("{}{}", lhs, rhs)),
+        BinaryOp::Eq => Value::Bool(compare_values(lhs, rhs) == Ordering::Equal),
+        BinaryOp::Ne => Value::Bool(compare_values(lhs, rhs) != Ordering::Equal),
+        BinaryOp::Lt => Value::Bool(compare_values(lhs, rhs) == Ordering::Less),
+        BinaryOp::Le => Value::Bool(compare_values(lhs, rhs) != Ordering::Greater),
+        BinaryOp::Gt => Value::Bool(compare_values(lhs, rhs) == Ordering::Greater),
+        BinaryOp::Ge => Value::Bool(compare_values(lhs, rhs) != Ordering::Less),
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Pow => {
+            let (x, y) = match (lhs.as_number(), rhs.as_number()) {
+                (Ok(x), Ok(y)) => (x, y),
+                (Err(e), _) | (_, Err(e)) => return Value::Error(e),
+            };
+            // A date moved by a number of days stays a date; the difference
+            // of two dates is a number of days.
+            let is_date = match op {
+                BinaryOp::Add => matches!(lhs, Value::Date(_)) != matches!(rhs, Value::Date(_)),
+                BinaryOp::Sub => matches!(lhs, Value::Date(_)) && !matches!(rhs, Value::Date(_)),
+                _ => false,
+            };
+            let result = match op {
+                BinaryOp::Add => x + y,
+                BinaryOp::Sub => x - y,
+                BinaryOp::Mul => x * y,
+                BinaryOp::Div if y == 0.0 => return Value::Error(CellError::Div0),
+                BinaryOp::Div => x / y,
+                _ => x.powf(y),
+            };
+            if !result.is_finite() || (is_date && !dates::is_valid_serial(result)) {
+                Value::Error(CellError::Num)
+            } else if is_date {
+                Value::Date(result)
+            } else {
+                Value::Number(result)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet(name: &str, rows: &[&[(&str, Option<&str>)]]) -> Sheet {
+        Sheet {
+            name: name.to_string(),
+            cells: rows
+                .iter()
+                .map(|r| {
+                    r.iter()
+                        .map(|(text, formula)| SheetCell {
+                            text: text.to_string(),
+                            formula: formula.map(str::to_string),
+                            ..SheetCell::default()
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_fruit_table() {
+        let workbook = Workbook {
+            sheets: vec![sheet(
+                "Table1",
+                &[
+                    &[("Item", None), ("Price", None), ("Quantity", None), ("Total", None)],
+                    &[("Apples", None), ("2", None), ("3", None), ("", Some("B2*C2"))],
+                    &[("Bananas", None), ("1", None), ("5", None), ("", Some("B3*C3"))],
+                    &[("Total", None), ("", None), ("", None), ("", Some("D2+D3"))],
+                ],
+            )],
+        };
+        let results = workbook.evaluate(0.0);
+        assert_eq!(results[0][1][3], Some(Value::Number(6.0)));
+        assert_eq!(results[0][3][3], Some(Value::Number(11.0)));
+        assert_eq!(results[0][0][0], None);
+    }
+
+    #[test]
+    fn test_errors_and_cycles() {
+        let workbook = Workbook {
+            sheets: vec![sheet(
+                "Table1",
+                &[
+                    &[("A", None), ("B", None)],
+                    &[("", Some("B2")), ("", Some("A2"))],
+                    &[("", Some("1/0")), ("", Some("missing!A1"))],
+                ],
+            )],
+        };
+        let results = workbook.evaluate(0.0);
+        assert_eq!(results[0][1][0], Some(Value::Error(CellError::Circular)));
+        assert_eq!(results[0][2][0], Some(Value::Error(CellError::Div0)));
+        assert_eq!(results[0][2][1], Some(Value::Error(CellError::Ref)));
+        assert_eq!(format_number(0.1 + 0.2), "0.3");
+    }
+}
@@ -0,0 +1,188 @@
+// export.rs
+
+use crate::directives::table_names;
+use crate::evaluator::Workbook;
+use crate::sort::find_table;
+use crate::table_parser::TableParser;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma separated values as in RFC 4180.
+    Csv,
+    /// Tab separated values.
+    Tsv,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportOptions {
+    /// One based table number, or a table name.
+    pub table: String,
+    pub format: ExportFormat,
+    /// Add a column holding the formulas after every column that has any.
+    pub formula_columns: bool,
+}
+
+/// The cell texts and formulas of one table. Formulas get their leading
+/// `=`; cells without one are `None`.
+#[allow(clippy::type_complexity)]
+fn table_cells(markdown: &str, table: &str) -> Result<(Vec<Vec<String>>, Vec<Vec<Option<String>>>), String> {
+    let tables = TableParser::parse_tables(markdown);
+    let names = table_names(markdown, &tables);
+    let index = find_table(table, &names).ok_or_else(|| format!("no table '{}'", table))?;
+    let workbook = Workbook::from_tables(&tables, &names);
+    let sheet = &workbook.sheets[index];
+    let width = sheet.col_count();
+    let texts = sheet
+        .cells
+        .iter()
+        .map(|row| {
+            (0..width)
+                .map(|c| row.get(c).map_or(String::new(), |cell| unescape(&cell.text)))
+                .collect()
+        })
+        .collect();
+    let formulas = sheet
+        .cells
+        .iter()
+        .map(|row| {
+            (0..width)
+                .map(|c| row.get(c).and_then(|cell| cell.formula.as_ref()).map(|f| format!("={}", f)))
+                .collect()
+        })
+        .collect();
+    Ok((texts, formulas))
+}
+
+/// Cell text without the escaping Markdown tables need for pipes.
+fn unescape(text: &str) -> String {
+    text.replace("\\|", "|")
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes rows as CSV (quoting fields as needed, CRLF line ends) or TSV (tabs
+/// and line breaks in fields replaced by spaces).
+pub fn write_delimited(rows: &[Vec<String>], format: ExportFormat) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let line: Vec<String> = match format {
+            ExportFormat::Csv => row.iter().map(|f| csv_field(f)).collect(),
+            ExportFormat::Tsv => row.iter().map(|f| f.replace(['\t', '\r', '\n'], " ")).collect(),
+        };
+        match format {
+            ExportFormat::Csv => {
+                out.push_str(&line.join(","));
+                out.push_str("\r\n");
+            }
+            ExportFormat::Tsv => {
+                out.push_str(&line.join("\t"));
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+/// Exports the values of a table as they appear in `markdown`, which should
+/// be recalculated first. With `formula_columns`, every column holding a
+/// formula is followed by a column of its formulas.
+pub fn export_table(markdown: &str, options: &ExportOptions) -> Result<String, String> {
+    let (texts, formulas) = table_cells(markdown, &options.table)?;
+    if !options.formula_columns {
+        return Ok(write_delimited(&texts, options.format));
+    }
+
+    let width = texts.first().map_or(0, Vec::len);
+    let has_formulas: Vec<bool> = (0..width)
+        .map(|c| formulas.iter().any(|row| row[c].is_some()))
+        .collect();
+    let rows: Vec<Vec<String>> = texts
+        .iter()
+        .zip(&formulas)
+        .enumerate()
+        .map(|(r, (values, formulas))| {
+            let mut out = Vec::new();
+            for c in 0..width {
+                out.push(values[c].clone());
+                if has_formulas[c] {
+                    out.push(match (&formulas[c], r) {
+                        (_, 0) => format!("{} (formula)", values[c]),
+                        (Some(formula), _) => formula.clone(),
+                        (None, _) => String::new(),
+                    });
+                }
+            }
+            out
+        })
+        .collect();
+    Ok(write_delimited(&rows, options.format))
+}
+
+/// Exports the formulas of a table in the shape of the table, with empty
+/// fields for cells without one. The header row is kept as is.
+pub fn export_formulas(markdown: &str, table: &str, format: ExportFormat) -> Result<String, String> {
+    let (texts, formulas) = table_cells(markdown, table)?;
+    let rows: Vec<Vec<String>> = formulas
+        .into_iter()
+        .enumerate()
+        .map(|(r, row)| match r {
+            0 => texts[0].clone(),
+            _ => row.into_iter().map(Option::unwrap_or_default).collect(),
+        })
+        .collect();
+    Ok(write_delimited(&rows, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKDOWN: &str = "\
+<!-- !table: fruit -->
+| Item | Price | Total <!-- !col=B*2 --> |
+|------|-------|-------|
+| Apples, red | 2 | 4 |
+| \"Best\" \\| pears | 1 | 2 |
+| Sum | | 6 <!-- =SUM(C2:C3) --> |
+";
+
+    fn options(format: ExportFormat, formula_columns: bool) -> ExportOptions {
+        ExportOptions {
+            table: "fruit".to_string(),
+            format,
+            formula_columns,
+        }
+    }
+
+    #[test]
+    fn test_export_values() {
+        assert_eq!(
+            export_table(MARKDOWN, &options(ExportFormat::Csv, false)).unwrap(),
+            "Item,Price,Total\r\n\"Apples, red\",2,4\r\n\"\"\"Best\"\" | pears\",1,2\r\nSum,,6\r\n"
+        );
+        assert_eq!(
+            export_table(MARKDOWN, &options(ExportFormat::Tsv, false)).unwrap(),
+            "Item\tPrice\tTotal\nApples, red\t2\t4\n\"Best\" | pears\t1\t2\nSum\t\t6\n"
+        );
+        let missing = ExportOptions { table: "3".to_string(), ..options(ExportFormat::Csv, false) };
+        assert!(export_table(MARKDOWN, &missing).is_err());
+    }
+
+    #[test]
+    fn test_export_formulas() {
+        assert_eq!(
+            export_table(MARKDOWN, &options(ExportFormat::Tsv, true)).unwrap(),
+            "Item\tPrice\tTotal\tTotal (formula)\nApples, red\t2\t4\t=B*2\n\"Best\" | pears\t1\t2\t=B*2\nSum\t\t6\t=SUM(C2:C3)\n"
+        );
+        assert_eq!(
+            export_formulas(MARKDOWN, "1", ExportFormat::Csv).unwrap(),
+            "Item,Price,Total\r\n,,=B*2\r\n,,=B*2\r\n,,=SUM(C2:C3)\r\n"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+// finance.rs
+
+use crate::evaluator::{CellError, Value};
+use crate::functions::{arity, numbers};
+
+// Cash flows follow the spreadsheet sign convention: money paid out is
+// negative, money received is positive. `due` is the spreadsheet `type`
+// argument: 0 for payments at the end of each period, 1 for the start.
+
+fn optional(args: &[Value], index: usize) -> Result<f64, CellError> {
+    args.get(index).map_or(Ok(0.0), Value::as_number)
+}
+
+/// Value at the end of the term of the present value and the payments,
+/// plus `fv`; the rate solvers look for where this is zero.
+fn balance(rate: f64, nper: f64, pmt: f64, pv: f64, fv: f64, due: f64) -> f64 {
+    if rate == 0.0 {
+        return pv + pmt * nper + fv;
+    }
+    let growth = (1.0 + rate).powf(nper);
+    pv * growth + pmt * (1.0 + rate * due) * (growth - 1.0) / rate + fv
+}
+
+fn pmt(rate: f64, nper: f64, pv: f64, fv: f64, due: f64) -> Result<f64, CellError> {
+    if nper == 0.0 {
+        return Err(CellError::Num);
+    }
+    if rate == 0.0 {
+        return Ok(-(pv + fv) / nper);
+    }
+    let growth = (1.0 + rate).powf(nper);
+    Ok(-(fv + pv * growth) * rate / ((1.0 + rate * due) * (growth - 1.0)))
+}
+
+fn fv(rate: f64, nper: f64, pmt: f64, pv: f64, due: f64) -> f64 {
+    -balance(rate, nper, pmt, pv, 0.0, due)
+}
+
+fn pv(rate: f64, nper: f64, pmt: f64, fv: f64, due: f64) -> f64 {
+    if rate == 0.0 {
+        return -(fv + pmt * nper);
+    }
+    let growth = (1.0 + rate).powf(nper);
+    -(fv + pmt * (1.0 + rate * due) * (growth - 1.0) / rate) / growth
+}
+
+fn npv(rate: f64, flows: &[f64]) -> f64 {
+    flows
+        .iter()
+        .enumerate()
+        .map(|(i, flow)| flow / (1.0 + rate).powi(i as i32 + 1))
+        .sum()
+}
+
+/// Finds a root of `f` near `guess` with Newton's method, using a numeric
+/// derivative.
+fn solve(f: impl Fn(f64) -> f64, guess: f64) -> Result<f64, CellError> {
+    let mut x = guess;
+    for _ in 0..100 {
+        let y = f(x);
+        if y.abs() < 1e-10 {
+            return Ok(x);
+        }
+        let h = 1e-7 * x.abs().max(1.0);
+        let slope = (f(x + h) - y) / h;
+        if slope == 0.0 || !slope.is_finite() {
+            break;
+        }
+        let next = x - y / slope;
+        if !next.is_finite() || next <= -1.0 {
+            break;
+        }
+        if (next - x).abs() < 1e-12 {
+            return Ok(next);
+        }
+        x = next;
+    }
+    Err(CellError::Num)
+}
+
+fn irr(flows: &[f64], guess: f64) -> Result<f64, CellError> {
+    if !flows.iter().any(|f| *f > 0.0) || !flows.iter().any(|f| *f < 0.0) {
+        return Err(CellError::Num);
+    }
+    // NPV of the flows with the first one at time zero.
+    solve(|rate| flows[0] + npv(rate, &flows[1..]), guess)
+}
+
+/// Financial functions. Returns `None` for names that are not financial
+/// functions.
+pub fn call(name: &str, args: &[Value]) -> Option<Result<Value, CellError>> {
+    let result = match name {
+        "PMT" => arity(args, 3, 5).and_then(|_| {
+            pmt(
+                args[0].as_number()?,
+                args[1].as_number()?,
+                args[2].as_number()?,
+                optional(args, 3)?,
+                optional(args, 4)?,
+            )
+        }),
+        "FV" => arity(args, 3, 5).and_then(|_| {
+            Ok(fv(
+                args[0].as_number()?,
+                args[1].as_number()?,
+                args[2].as_number()?,
+                optional(args, 3)?,
+                optional(args, 4)?,
+            ))
+        }),
+        "PV" => arity(args, 3, 5).and_then(|_| {
+            Ok(pv(
+                args[0].as_number()?,
+                args[1].as_number()?,
+                args[2].as_number()?,
+                optional(args, 3)?,
+                optional(args, 4)?,
+            ))
+        }),
+        "NPV" => arity(args, 2, usize::MAX).and_then(|_| Ok(npv(args[0].as_number()?, &numbers(&args[1..])?))),
+        "IRR" => arity(args, 1, 2).and_then(|_| {
+            let guess = args.get(1).map_or(Ok(0.1), Value::as_number)?;
+            irr(&numbers(&args[..1])?, guess)
+        }),
+        "RATE" => arity(args, 3, 6).and_then(|_| {
+            let (nper, payment, present) = (args[0].as_number()?, args[1].as_number()?, args[2].as_number()?);
+            let (future, due) = (optional(args, 3)?, optional(args, 4)?);
+            let guess = args.get(5).map_or(Ok(0.1), Value::as_number)?;
+            solve(|rate| balance(rate, nper, payment, present, future, due), guess)
+        }),
+        _ => return None,
+    };
+    Some(result.and_then(|n| if n.is_finite() { Ok(Value::Number(n)) } else { Err(CellError::Num) }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(name: &str, args: &[f64]) -> f64 {
+        let args: Vec<Value> = args.iter().map(|a| Value::Number(*a)).collect();
+        match call(name, &args) {
+            Some(Ok(Value::Number(n))) => n,
+            other => panic!("{} returned {:?}", name, other),
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn test_time_value_of_money() {
+        // A 200,000 loan over 30 years at 6% a year, paid monthly.
+        let payment = number("PMT", &[0.005, 360.0, 200000.0]);
+        assert!(close(payment, -1199.101050305), "{}", payment);
+        assert!(close(number("PV", &[0.005, 360.0, payment]), 200000.0));
+        assert!(close(number("FV", &[0.005, 360.0, payment, 200000.0]), 0.0));
+        assert!(close(number("RATE", &[360.0, payment, 200000.0]), 0.005));
+        assert!(close(number("FV", &[0.0, 12.0, -100.0]), 1200.0));
+        assert!(close(number("PMT", &[0.01, 10.0, 0.0, 1000.0, 1.0]), -94.635719358));
+    }
+
+    #[test]
+    fn test_npv_and_irr() {
+        let flows = Value::Array(vec![vec![Value::Number(-1000.0)], vec![Value::Number(600.0)], vec![Value::Number(600.0)]]);
+        let npv = call("NPV", &[Value::Number(0.1), Value::Array(vec![vec![Value::Number(600.0), Value::Number(600.0)]])]);
+        assert!(matches!(npv, Some(Ok(Value::Number(n))) if close(n, 1041.322314)));
+        let irr = call("IRR", &[flows]);
+        assert!(matches!(irr, Some(Ok(Value::Number(n))) if close(n, 0.130662386)), "{:?}", irr);
+        assert_eq!(call("IRR", &[Value::Number(5.0)]), Some(Err(CellError::Num)));
+    }
+}
@@ -0,0 +1,441 @@
+// fix_refs.rs
+
+use std::collections::HashMap;
+
+use crate::cell_markers::{apply_markers, marker_of, parse_marker, MarkerMode};
+use crate::directives::{parse_directives, table_names, Directive};
+use crate::formula::{column_index, column_name, formula_source, parse_cell_ref, parse_relative_ref, tokenize, CellRef, Token, TokenKind};
+use crate::md_comments::CommentKind;
+use crate::table_parser::{MarkdownTable, TableCell, TableCellPiece, TableParser};
+use crate::table_writer::render_document;
+
+/// How the rows and columns of a table moved since its markers were written,
+/// as maps from old to new zero based indexes. `None` means the table has no
+/// markers on that axis, so nothing is known to have moved.
+#[derive(Debug, Clone, Default)]
+pub struct Moves {
+    rows: Option<HashMap<usize, usize>>,
+    cols: Option<HashMap<usize, usize>>,
+}
+
+impl Moves {
+    /// Reads the row markers of the first column and the column markers of
+    /// the header row.
+    pub fn from_table(table: &MarkdownTable) -> Moves {
+        let marker = |cell: &TableCell| {
+            cell.comments()
+                .into_iter()
+                .find_map(|c| marker_of(&c.comment).map(parse_marker))
+                .unwrap_or((None, None))
+        };
+
+        let mut rows = HashMap::new();
+        for (new, row) in table.rows.iter().enumerate().skip(1) {
+            if let Some((_, Some(old))) = row.cells.first().map(marker) {
+                rows.entry(old).or_insert(new);
+            }
+        }
+        let mut cols = HashMap::new();
+        for (new, cell) in table.rows.first().map_or(&[][..], |r| &r.cells[..]).iter().enumerate() {
+            if let (Some(old), _) = marker(cell) {
+                cols.entry(old).or_insert(new);
+            }
+        }
+
+        Moves {
+            rows: (!rows.is_empty()).then(|| {
+                rows.insert(0, 0);
+                rows
+            }),
+            cols: (!cols.is_empty()).then_some(cols),
+        }
+    }
+
+    /// Rows moved as in `rows` (old to new body row indexes); the header and
+    /// the columns stay in place.
+    pub fn from_rows(mut rows: HashMap<usize, usize>) -> Moves {
+        rows.insert(0, 0);
+        Moves {
+            rows: Some(rows),
+            cols: None,
+        }
+    }
+
+    pub fn row(&self, old: usize) -> Option<usize> {
+        lookup(&self.rows, old)
+    }
+
+    pub fn col(&self, old: usize) -> Option<usize> {
+        lookup(&self.cols, old)
+    }
+
+    fn old_row(&self, new: usize) -> Option<usize> {
+        reverse(&self.rows, new)
+    }
+
+    fn old_col(&self, new: usize) -> Option<usize> {
+        reverse(&self.cols, new)
+    }
+
+    /// Whether a row at `new` was there when the markers were written.
+    fn tracks_row(&self, new: usize) -> bool {
+        self.old_row(new).is_some()
+    }
+
+    /// Maps an old range `first..=last` to the smallest range holding all of
+    /// its surviving rows or columns, so it grows over inserted ones and
+    /// shrinks past deleted ends. `None` if nothing in the range survived.
+    fn span(map: &Option<HashMap<usize, usize>>, first: usize, last: usize) -> Option<(usize, usize)> {
+        let (first, last) = (first.min(last), first.max(last));
+        if map.is_none() {
+            return Some((first, last));
+        }
+        let mapped = || (first..=last).filter_map(|old| lookup(map, old));
+        Some((mapped().min()?, mapped().max()?))
+    }
+}
+
+fn lookup(map: &Option<HashMap<usize, usize>>, old: usize) -> Option<usize> {
+    match map {
+        Some(map) => map.get(&old).copied(),
+        None => Some(old),
+    }
+}
+
+fn reverse(map: &Option<HashMap<usize, usize>>, new: usize) -> Option<usize> {
+    match map {
+        Some(map) => map.iter().find(|(_, n)| **n == new).map(|(old, _)| *old),
+        None => Some(new),
+    }
+}
+
+const DELETED: &str = "#REF!";
+
+/// Rewrites the references of one formula so that they point at the same
+/// logical cells after rows and columns moved.
+struct Rewriter<'a> {
+    moves: &'a [Moves],
+    names: &'a [String],
+    sheet: usize,
+    /// New position of the formula cell; the row is `None` for header
+    /// column formulas, which apply to every row.
+    origin: (Option<usize>, usize),
+    bare_columns: bool,
+}
+
+impl Rewriter<'_> {
+    fn rewrite(&self, source: &str) -> String {
+        let Ok(tokens) = tokenize(source) else {
+            return source.to_string();
+        };
+
+        let mut out = String::new();
+        let mut last = 0;
+        let mut i = 0;
+        while i < tokens.len() {
+            let (replacement, used) = self.rewrite_at(&tokens, i);
+            if let Some(text) = replacement {
+                // A deleted cell of another table drops the `table!` prefix.
+                let qualified = i >= 2 && tokens[i - 1].kind == TokenKind::Bang;
+                let start = if qualified && text == DELETED { tokens[i - 2].start } else { tokens[i].start };
+                out.push_str(&source[last..start]);
+                out.push_str(&text);
+                last = tokens[i + used - 1].end;
+            }
+            i += used;
+        }
+        out.push_str(&source[last..]);
+        out
+    }
+
+    /// The replacement for the reference starting at token `i`, if any, and
+    /// the number of tokens it covers.
+    fn rewrite_at(&self, tokens: &[Token], i: usize) -> (Option<String>, usize) {
+        let TokenKind::Word(word) = &tokens[i].kind else {
+            return (None, 1);
+        };
+        let kind_at = |k: usize| tokens.get(k).map(|t| &t.kind);
+        let adjacent = |k: usize| tokens.get(k).is_some_and(|t| t.start == tokens[k - 1].end);
+
+        // Function names and table names are not references.
+        if matches!(kind_at(i + 1), Some(TokenKind::LParen | TokenKind::Bang))
+            || (matches!(kind_at(i + 1), Some(TokenKind::Structured(_))) && adjacent(i + 1))
+        {
+            return (None, 1);
+        }
+
+        let sheet = match (i.checked_sub(2).and_then(kind_at), i.checked_sub(1).and_then(kind_at)) {
+            (Some(TokenKind::Word(table)), Some(TokenKind::Bang)) => {
+                match self.names.iter().position(|n| n.eq_ignore_ascii_case(table)) {
+                    Some(sheet) => sheet,
+                    None => return (None, 1),
+                }
+            }
+            _ => self.sheet,
+        };
+        let moves = &self.moves[sheet];
+        let local = sheet == self.sheet;
+
+        if let (Some(TokenKind::Colon), Some(TokenKind::Word(end))) = (kind_at(i + 1), kind_at(i + 2)) {
+            if let Some(text) = self.rewrite_range(word, end, moves) {
+                return (Some(text), 3);
+            }
+        }
+
+        let text = if let Some(cell) = parse_cell_ref(word) {
+            Some(match (moves.col(cell.col), moves.row(cell.row)) {
+                (Some(col), Some(row)) => CellRef { col, row, ..cell }.to_string(),
+                _ => DELETED.to_string(),
+            })
+        } else if let Some(column) = word.strip_suffix('@') {
+            self.rewrite_column(column, moves).map(|c| format!("{}@", c))
+        } else if local && parse_relative_ref(word, (0, 0)).is_some() {
+            self.rewrite_r1c1(word, moves)
+        } else if self.bare_columns && !word.eq_ignore_ascii_case("TRUE") && !word.eq_ignore_ascii_case("FALSE") {
+            self.rewrite_column(word, moves)
+        } else {
+            None
+        };
+        (text, 1)
+    }
+
+    /// Column letters with an optional `$`, mapped to their new column.
+    fn rewrite_column(&self, word: &str, moves: &Moves) -> Option<String> {
+        let (dollar, letters) = match word.strip_prefix('$') {
+            Some(letters) => ("$", letters),
+            None => ("", word),
+        };
+        let old = column_index(letters)?;
+        Some(match moves.col(old) {
+            Some(new) => format!("{}{}", dollar, column_name(new)),
+            None => DELETED.to_string(),
+        })
+    }
+
+    /// `A2:B5` or `B:C`, shrunk when rows or columns at its ends were deleted.
+    fn rewrite_range(&self, start: &str, end: &str, moves: &Moves) -> Option<String> {
+        if let (Some(a), Some(b)) = (parse_cell_ref(start), parse_cell_ref(end)) {
+            let text = match (Moves::span(&moves.cols, a.col, b.col), Moves::span(&moves.rows, a.row, b.row)) {
+                (Some((c1, c2)), Some((r1, r2))) => {
+                    // Keep each `$` with the end it was written on.
+                    let (first, last) = if (a.col, a.row) <= (b.col, b.row) { (a, b) } else { (b, a) };
+                    format!(
+                        "{}:{}",
+                        CellRef { col: c1, row: r1, ..first },
+                        CellRef { col: c2, row: r2, ..last }
+                    )
+                }
+                _ => DELETED.to_string(),
+            };
+            return Some(text);
+        }
+
+        if end.eq_ignore_ascii_case("above") {
+            return self.rewrite_column(start, moves).map(|c| format!("{}:{}", c, end));
+        }
+        let first = column_index(start.trim_start_matches('$'))?;
+        let last = column_index(end.trim_start_matches('$'))?;
+        let dollar = |word: &str| if word.starts_with('$') { "$" } else { "" };
+        Some(match Moves::span(&moves.cols, first, last) {
+            Some((c1, c2)) => format!("{}{}:{}{}", dollar(start), column_name(c1), dollar(end), column_name(c2)),
+            None => DELETED.to_string(),
+        })
+    }
+
+    /// R1C1 references: absolute parts are mapped, relative offsets are
+    /// recomputed from the old and new positions of the formula cell.
+    fn rewrite_r1c1(&self, word: &str, moves: &Moves) -> Option<String> {
+        let old_origin = (
+            self.origin.0.and_then(|row| moves.old_row(row)),
+            moves.old_col(self.origin.1),
+        );
+        let target = parse_relative_ref(word, (old_origin.0.unwrap_or(0), old_origin.1.unwrap_or(0)))?;
+        let (row_part, col_part) = split_r1c1(word)?;
+
+        let row_origin = self.origin.0.filter(|_| old_origin.0.is_some());
+        let col_origin = Some(self.origin.1).filter(|_| old_origin.1.is_some());
+        let row = r1c1_part(row_part, target.row_absolute, moves.row(target.row), row_origin);
+        let col = r1c1_part(col_part, target.col_absolute, moves.col(target.col), col_origin);
+        Some(match (row, col) {
+            (Some(row), Some(col)) => format!("{}{}{}{}", &word[..1], row, &word[1 + row_part.len()..2 + row_part.len()], col),
+            _ => DELETED.to_string(),
+        })
+    }
+}
+
+/// Renders one R1C1 part for a target now at `new`: a one based index when
+/// absolute, otherwise an offset from the new `origin`. Without a known
+/// origin the part is kept as written.
+fn r1c1_part(text: &str, absolute: bool, new: Option<usize>, origin: Option<usize>) -> Option<String> {
+    match (absolute, origin) {
+        (true, _) => new.map(|n| format!("{}", n + 1)),
+        (false, Some(origin)) => new.map(|n| match n as i64 - origin as i64 {
+            0 => String::new(),
+            offset => format!("[{}]", offset),
+        }),
+        (false, None) => Some(text.to_string()),
+    }
+}
+
+/// Splits `R[-1]C2` into its row and column parts (`[-1]` and `2`).
+fn split_r1c1(word: &str) -> Option<(&str, &str)> {
+    let rest = word.get(1..)?;
+    let c = rest.find(['C', 'c'])?;
+    Some((&rest[..c], &rest[c + 1..]))
+}
+
+fn rewrite_cell(cell: &mut TableCell, rewriter: &Rewriter) {
+    for piece in &mut cell.pieces {
+        let TableCellPiece::Comment(c) = piece else {
+            continue;
+        };
+        let content = &c.comment.content;
+        let updated = match c.comment.kind {
+            CommentKind::Formula => formula_source(content).map(|source| {
+                let at = content.len() - content.trim_start().len() + content.trim().len() - source.len();
+                format!("{}{}", &content[..at], rewriter.rewrite(source))
+            }),
+            CommentKind::Directive if rewriter.bare_columns => {
+                let mut content = content.trim().to_string();
+                for directive in parse_directives(&content.clone()) {
+                    if let Directive::Column { formula } = directive {
+                        if let Some(at) = content.rfind(&formula) {
+                            content.replace_range(at..at + formula.len(), &rewriter.rewrite(&formula));
+                        }
+                    }
+                }
+                Some(content)
+            }
+            _ => None,
+        };
+        if let Some(updated) = updated {
+            c.comment.content = updated;
+        }
+    }
+}
+
+/// Rewrites the formulas of every table for the rows and columns that moved,
+/// given per table. Formulas in rows that did not exist before keep their
+/// references as typed.
+pub fn rewrite_tables(tables: &mut [MarkdownTable], names: &[String], moves: &[Moves]) {
+    for (sheet, table) in tables.iter_mut().enumerate() {
+        for (row_idx, row) in table.rows.iter_mut().enumerate() {
+            if row_idx > 0 && !moves[sheet].tracks_row(row_idx) {
+                continue;
+            }
+            for (col_idx, cell) in row.cells.iter_mut().enumerate() {
+                let rewriter = Rewriter {
+                    moves,
+                    names,
+                    sheet,
+                    origin: ((row_idx > 0).then_some(row_idx), col_idx),
+                    bare_columns: row_idx == 0,
+                };
+                rewrite_cell(cell, &rewriter);
+            }
+        }
+    }
+}
+
+/// Renumbers the markers of a table after its rows or columns moved: every
+/// cell if any body cell had a marker, the header row and first column if
+/// only those did, and nothing for tables without markers.
+pub fn refresh_markers(table: &mut MarkdownTable) {
+    if let Some(mode) = refresh_mode(table) {
+        apply_markers(table, mode);
+    }
+}
+
+fn refresh_mode(table: &MarkdownTable) -> Option<MarkerMode> {
+    let has_marker = |row: usize, col: usize| {
+        table.rows[row].cells[col]
+            .comments()
+            .into_iter()
+            .any(|c| marker_of(&c.comment).is_some())
+    };
+    let cells = || {
+        table
+            .rows
+            .iter()
+            .enumerate()
+            .flat_map(|(r, row)| (0..row.cells.len()).map(move |c| (r, c)))
+    };
+    if cells().any(|(r, c)| r > 0 && c > 0 && has_marker(r, c)) {
+        Some(MarkerMode::AllMarkers)
+    } else if cells().any(|(r, c)| has_marker(r, c)) {
+        Some(MarkerMode::OnlyRowAndColumn)
+    } else {
+        None
+    }
+}
+
+/// Fixes the references of every formula in a Markdown document after rows
+/// or columns were inserted, deleted or moved, using the cell markers as the
+/// record of the old positions, then renumbers the markers.
+pub fn fix_references(markdown: &str) -> String {
+    let originals = TableParser::parse_tables(markdown);
+    let names = table_names(markdown, &originals);
+    let mut tables = originals.clone();
+
+    let moves: Vec<Moves> = tables.iter().map(Moves::from_table).collect();
+    rewrite_tables(&mut tables, &names, &moves);
+    for table in tables.iter_mut() {
+        refresh_markers(table);
+    }
+
+    render_document(markdown, &originals, &tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inserted_row_and_column() {
+        // A row was inserted after Apples and a Weight column before Total.
+        let markdown = "\
+| Item <!-- !A --> | Price <!-- !B --> | Quantity <!-- !C --> | Weight | Total <!-- !D --> |
+|---|---|---|---|---|
+| Apples <!-- !2 --> | 2 | 3 | 1 | 6 <!-- !=B2*C2 --> |
+| Kiwis | 3 | 1 | 1 | 3 <!-- =B3*C3 --> |
+| Bananas <!-- !3 --> | 1 | 5 | 1 | 5 <!-- !=$B$3*C3 --> |
+| Total <!-- !4 --> | | | ? <!-- =SUM(D:above) --> | 11 <!-- !=SUM(D2:D3)+RC[-1] --> |
+";
+        assert_eq!(
+            fix_references(markdown),
+            "\
+| Item <!-- !A1 --> | Price <!-- !B1 --> | Quantity <!-- !C1 --> | Weight <!-- !D1 --> | Total <!-- !E1 --> |
+|---|---|---|---|---|
+| Apples <!-- !A2 --> | 2 | 3 | 1 | 6 <!-- !=B2*C2 --> |
+| Kiwis <!-- !A3 --> | 3 | 1 | 1 | 3 <!-- =B3*C3 --> |
+| Bananas <!-- !A4 --> | 1 | 5 | 1 | 5 <!-- !=$B$4*C4 --> |
+| Total <!-- !A5 --> | | | ? <!-- =SUM(E:above) --> | 11 <!-- !=SUM(E2:E4)+RC[-2] --> |
+"
+        );
+    }
+
+    #[test]
+    fn test_deleted_rows_and_columns() {
+        let markdown = "\
+<!-- !table: t -->
+| Item <!-- !A --> | Total <!-- !C --> |
+|---|---|
+| A <!-- !2 --> | 1 |
+| C <!-- !4 --> | 3 |
+
+| Ref | Value <!-- !col=t!C2 + t!B2 --> |
+|---|---|
+| x | ? <!-- =SUM(t!A2:A4) + t!A3 + SUM(t!B:C) --> |
+";
+        let fixed = fix_references(markdown);
+        assert!(fixed.contains("| Item <!-- !A1 --> | Total <!-- !B1 --> |"), "{}", fixed);
+        assert!(fixed.contains("| Value <!-- !col=t!B2 + #REF! --> |"));
+        assert!(fixed.contains("<!-- =SUM(t!A2:A3) + #REF! + SUM(t!B:B) -->"));
+    }
+
+    #[test]
+    fn test_tables_without_markers_are_unchanged() {
+        let markdown = "| a | b |\n|---|---|\n| 1 | 2 <!-- =A2 --> |\n";
+        assert_eq!(fix_references(markdown), markdown);
+    }
+}
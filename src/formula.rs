@@ -0,0 +1,896 @@
+// formula.rs
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Number(f64),
+    Text(String),
+    Word(String), // function names, references, booleans and table names
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    Ampersand,
+    Percent,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semicolon,
+    Colon,
+    Bang,
+    /// An error literal such as `#REF!`.
+    Error(String),
+    /// Contents of a structured reference in brackets, e.g. `@Price` for
+    /// `[@Price]`.
+    Structured(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+/// A single cell address. `row` and `col` are zero based, with row 0 being
+/// the header row (`A1` is the first header cell).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRef {
+    pub col: usize,
+    pub row: usize,
+    pub col_absolute: bool,
+    pub row_absolute: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reference {
+    Cell(CellRef),
+    Range(CellRef, CellRef),
+    Columns(usize, usize),
+    /// A column found by its header text (`Table[Total]`): the body of the
+    /// column, or the cell in `row` for `[@Total]`.
+    Structured { column: String, row: Option<usize> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Plus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    Array(Vec<Vec<Expr>>),
+    Reference {
+        table: Option<String>,
+        reference: Reference,
+    },
+    Name(String),
+    /// An error literal, e.g. `#REF!` left by `fix-refs` for a deleted cell.
+    Error(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Percent(Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+/// Returns the formula text of a formula comment (`!=B2*C2` or `=B2*C2`),
+/// without the leading markers.
+pub fn formula_source(content: &str) -> Option<&str> {
+    let trimmed = content.trim();
+    let trimmed = trimmed.strip_prefix('!').unwrap_or(trimmed);
+    trimmed.strip_prefix('=').map(str::trim)
+}
+
+/// Converts column letters (`A`, `AB`) to a zero based index.
+pub fn column_index(letters: &str) -> Option<usize> {
+    if letters.is_empty() || letters.len() > 3 || !letters.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let mut index = 0usize;
+    for c in letters.chars() {
+        index = index * 26 + (c.to_ascii_uppercase() as usize - 'A' as usize + 1);
+    }
+    Some(index - 1)
+}
+
+/// Converts a zero based column index to spreadsheet letters.
+pub fn column_name(mut index: usize) -> String {
+    let mut letters = Vec::new();
+    loop {
+        letters.push((b'A' + (index % 26) as u8) as char);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    letters.iter().rev().collect()
+}
+
+/// Parses `B2`, `$B$2`, `b2` into a cell reference.
+pub fn parse_cell_ref(word: &str) -> Option<CellRef> {
+    let (col_absolute, rest) = match word.strip_prefix('$') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+    let letters_end = rest.find(|c: char| !c.is_ascii_alphabetic())?;
+    let col = column_index(&rest[..letters_end])?;
+    let rest = &rest[letters_end..];
+    let (row_absolute, digits) = match rest.strip_prefix('$') {
+        Some(digits) => (true, digits),
+        None => (false, rest),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let row: usize = digits.parse().ok()?;
+    if row == 0 {
+        return None;
+    }
+    Some(CellRef {
+        col,
+        row: row - 1,
+        col_absolute,
+        row_absolute,
+    })
+}
+
+/// Resolves a reference relative to the cell at `origin` (zero based
+/// `(row, col)`): `B@` is column B in the same row, and R1C1 references such
+/// as `RC[-2]` or `R[-1]C` are offsets from the cell (`R2C3` is absolute).
+/// References before the first row or column resolve to an index past any
+/// table, which evaluates to `#REF!`.
+pub fn parse_relative_ref(word: &str, origin: (usize, usize)) -> Option<CellRef> {
+    if let Some(column) = word.strip_suffix('@') {
+        let (col_absolute, letters) = match column.strip_prefix('$') {
+            Some(letters) => (true, letters),
+            None => (false, column),
+        };
+        return Some(CellRef {
+            col: column_index(letters)?,
+            row: origin.0,
+            col_absolute,
+            row_absolute: false,
+        });
+    }
+
+    let rest = word.strip_prefix(['R', 'r'])?;
+    // A bare `RC` could only point at the cell itself; leave it as a name.
+    if rest.eq_ignore_ascii_case("C") {
+        return None;
+    }
+    let (row, rest) = r1c1_part(rest, origin.0)?;
+    let rest = rest.strip_prefix(['C', 'c'])?;
+    let (col, rest) = r1c1_part(rest, origin.1)?;
+    if !rest.is_empty() {
+        return None;
+    }
+    Some(CellRef {
+        col: col.0,
+        row: row.0,
+        col_absolute: col.1,
+        row_absolute: row.1,
+    })
+}
+
+/// One R1C1 component: nothing (same index), `[offset]` or an absolute
+/// one-based index. Returns `((index, absolute), rest)`.
+fn r1c1_part(text: &str, current: usize) -> Option<((usize, bool), &str)> {
+    if let Some(bracketed) = text.strip_prefix('[') {
+        let close = bracketed.find(']')?;
+        let offset: i64 = bracketed[..close].trim_start_matches('+').parse().ok()?;
+        let index = usize::try_from(current as i64 + offset).unwrap_or(usize::MAX);
+        return Some(((index, false), &bracketed[close + 1..]));
+    }
+    let digits_end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    if digits_end == 0 {
+        return Some(((current, false), text));
+    }
+    let index: usize = text[..digits_end].parse().ok()?;
+    Some(((index.checked_sub(1)?, true), &text[digits_end..]))
+}
+
+impl fmt::Display for CellRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}{}",
+            if self.col_absolute { "$" } else { "" },
+            column_name(self.col),
+            if self.row_absolute { "$" } else { "" },
+            self.row + 1
+        )
+    }
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let kind = if c.is_ascii_digit() || (c == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)) {
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                let mut j = i + 1;
+                if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+                    j += 1;
+                }
+                if j < bytes.len() && bytes[j].is_ascii_digit() {
+                    i = j;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text = &source[start..i];
+            let value = text.parse::<f64>().map_err(|_| ParseError {
+                message: format!("invalid number '{}'", text),
+                position: start,
+            })?;
+            TokenKind::Number(value)
+        } else if c == b'"' {
+            // Strings use spreadsheet quoting: "" inside a string is a quote.
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match source[i..].chars().next() {
+                    None => {
+                        return Err(ParseError {
+                            message: "unterminated string".to_string(),
+                            position: start,
+                        })
+                    }
+                    Some('"') if bytes.get(i + 1) == Some(&b'"') => {
+                        text.push('"');
+                        i += 2;
+                    }
+                    Some('"') => {
+                        i += 1;
+                        break;
+                    }
+                    Some(ch) => {
+                        text.push(ch);
+                        i += ch.len_utf8();
+                    }
+                }
+            }
+            TokenKind::Text(text)
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' {
+            loop {
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric() || matches!(bytes[i], b'_' | b'.' | b'$' | b'@'))
+                {
+                    i += 1;
+                }
+                // Keep the bracketed offsets of R1C1 references in the word.
+                let word = &source[start..i];
+                let is_r1c1 = word.eq_ignore_ascii_case("R")
+                    || word.eq_ignore_ascii_case("RC")
+                    || (word.ends_with(['C', 'c']) && parse_relative_ref(word, (0, 0)).is_some());
+                match source[i..].strip_prefix('[').and_then(|rest| rest.find(']')) {
+                    Some(close) if is_r1c1 => i += close + 2,
+                    _ => break,
+                }
+            }
+            TokenKind::Word(source[start..i].to_string())
+        } else if c == b'#' {
+            i += 1;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'/') {
+                i += 1;
+            }
+            if i < bytes.len() && matches!(bytes[i], b'!' | b'?') {
+                i += 1;
+            }
+            TokenKind::Error(source[start..i].to_ascii_uppercase())
+        } else if c == b'[' {
+            let close = source[i..].find(']').ok_or_else(|| ParseError {
+                message: "unterminated '['".to_string(),
+                position: start,
+            })?;
+            i += close + 1;
+            TokenKind::Structured(source[start + 1..i - 1].trim().to_string())
+        } else {
+            i += 1;
+            match c {
+                b'+' => TokenKind::Plus,
+                b'-' => TokenKind::Minus,
+                b'*' => TokenKind::Star,
+                b'/' => TokenKind::Slash,
+                b'^' => TokenKind::Caret,
+                b'&' => TokenKind::Ampersand,
+                b'%' => TokenKind::Percent,
+                b'=' => TokenKind::Eq,
+                b'(' => TokenKind::LParen,
+                b')' => TokenKind::RParen,
+                b'{' => TokenKind::LBrace,
+                b'}' => TokenKind::RBrace,
+                b',' => TokenKind::Comma,
+                b';' => TokenKind::Semicolon,
+                b':' => TokenKind::Colon,
+                b'!' => TokenKind::Bang,
+                b'<' => match bytes.get(i) {
+                    Some(b'=') => {
+                        i += 1;
+                        TokenKind::Le
+                    }
+                    Some(b'>') => {
+                        i += 1;
+                        TokenKind::Ne
+                    }
+                    _ => TokenKind::Lt,
+                },
+                b'>' => match bytes.get(i) {
+                    Some(b'=') => {
+                        i += 1;
+                        TokenKind::Ge
+                    }
+                    _ => TokenKind::Gt,
+                },
+                _ => {
+                    return Err(ParseError {
+                        message: format!("unexpected character '{}'", source[start..].chars().next().unwrap_or('?')),
+                        position: start,
+                    })
+                }
+            }
+        };
+
+        tokens.push(Token { kind, start, end: i });
+    }
+
+    Ok(tokens)
+}
+
+/// Parses the formula of the cell at zero based `(row, col)`, resolving
+/// relative references such as `B@` and `RC[-1]` against it.
+pub fn parse_formula_at(source: &str, row: usize, col: usize) -> Result<Expr, ParseError> {
+    parse(source, (row, col), false)
+}
+
+/// Parses a column formula declared in a header for the cell at zero based
+/// `(row, col)`. Bare column letters such as `B` mean the same row, like `B@`.
+pub fn parse_column_formula(source: &str, row: usize, col: usize) -> Result<Expr, ParseError> {
+    parse(source, (row, col), true)
+}
+
+fn parse(source: &str, origin: (usize, usize), bare_columns: bool) -> Result<Expr, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        source_len: source.len(),
+        origin,
+        bare_columns,
+    };
+    let expr = parser.parse_expr()?;
+    if let Some(token) = parser.peek_token() {
+        return Err(ParseError {
+            message: "unexpected trailing input".to_string(),
+            position: token.start,
+        });
+    }
+    Ok(expr)
+}
+
+struct Parser<'t> {
+    tokens: &'t [Token],
+    pos: usize,
+    source_len: usize,
+    origin: (usize, usize),
+    /// Whether a lone column name is a reference into the origin row.
+    bare_columns: bool,
+}
+
+impl<'t> Parser<'t> {
+    fn peek_token(&self) -> Option<&'t Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek(&self) -> Option<&'t TokenKind> {
+        self.peek_token().map(|t| &t.kind)
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<&'t TokenKind> {
+        self.tokens.get(self.pos + ahead).map(|t| &t.kind)
+    }
+
+    fn next(&mut self) -> Option<&'t TokenKind> {
+        let kind = self.peek();
+        if kind.is_some() {
+            self.pos += 1;
+        }
+        kind
+    }
+
+    fn position(&self) -> usize {
+        self.peek_token().map(|t| t.start).unwrap_or(self.source_len)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        Err(ParseError {
+            message: message.to_string(),
+            position: self.position(),
+        })
+    }
+
+    fn expect(&mut self, kind: TokenKind, message: &str) -> Result<(), ParseError> {
+        if self.peek() == Some(&kind) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(message)
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_concat()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Eq) => BinaryOp::Eq,
+                Some(TokenKind::Ne) => BinaryOp::Ne,
+                Some(TokenKind::Lt) => BinaryOp::Lt,
+                Some(TokenKind::Le) => BinaryOp::Le,
+                Some(TokenKind::Gt) => BinaryOp::Gt,
+                Some(TokenKind::Ge) => BinaryOp::Ge,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.parse_concat()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_additive()?;
+        while self.peek() == Some(&TokenKind::Ampersand) {
+            self.pos += 1;
+            let rhs = self.parse_additive()?;
+            lhs = Expr::Binary(BinaryOp::Concat, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Plus) => BinaryOp::Add,
+                Some(TokenKind::Minus) => BinaryOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.parse_multiplicative()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_power()?;
+        loop {
+            let op = match self.peek() {
+                Some(TokenKind::Star) => BinaryOp::Mul,
+                Some(TokenKind::Slash) => BinaryOp::Div,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.parse_power()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_power(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.parse_unary()?;
+        while self.peek() == Some(&TokenKind::Caret) {
+            self.pos += 1;
+            let rhs = self.parse_unary()?;
+            lhs = Expr::Binary(BinaryOp::Pow, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    // Like spreadsheets, unary minus binds tighter than `^`: -2^2 is 4.
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Some(TokenKind::Minus) => {
+                self.pos += 1;
+                Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.parse_unary()?)))
+            }
+            Some(TokenKind::Plus) => {
+                self.pos += 1;
+                Ok(Expr::Unary(UnaryOp::Plus, Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_primary()?;
+        while self.peek() == Some(&TokenKind::Percent) {
+            self.pos += 1;
+            expr = Expr::Percent(Box::new(expr));
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        match self.next() {
+            Some(TokenKind::Number(n)) => Ok(Expr::Number(*n)),
+            Some(TokenKind::Text(s)) => Ok(Expr::Text(s.clone())),
+            Some(TokenKind::Error(code)) => Ok(Expr::Error(code.clone())),
+            Some(TokenKind::LParen) => {
+                let expr = self.parse_expr()?;
+                self.expect(TokenKind::RParen, "expected ')'")?;
+                Ok(expr)
+            }
+            Some(TokenKind::LBrace) => self.parse_array(),
+            Some(TokenKind::Word(word)) => self.parse_word(word),
+            Some(TokenKind::Structured(content)) => Ok(Expr::Reference {
+                table: None,
+                reference: self.structured(content)?,
+            }),
+            Some(_) => {
+                self.pos -= 1;
+                self.error("unexpected token")
+            }
+            None => self.error("unexpected end of formula"),
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Expr, ParseError> {
+        let mut rows = vec![Vec::new()];
+        loop {
+            let element = self.parse_expr()?;
+            rows.last_mut().unwrap().push(element);
+            match self.next() {
+                Some(TokenKind::Comma) => {}
+                Some(TokenKind::Semicolon) => rows.push(Vec::new()),
+                Some(TokenKind::RBrace) => break,
+                _ => {
+                    self.pos = self.pos.saturating_sub(1);
+                    return self.error("expected ',', ';' or '}' in array");
+                }
+            }
+        }
+        if rows.iter().any(|r| r.len() != rows[0].len()) {
+            return self.error("array rows must have the same length");
+        }
+        Ok(Expr::Array(rows))
+    }
+
+    fn parse_word(&mut self, word: &str) -> Result<Expr, ParseError> {
+        // `Table[Column]`, with the bracket right after the table name.
+        if let Some(Token { kind: TokenKind::Structured(content), start, .. }) = self.peek_token() {
+            if self.tokens[self.pos - 1].end == *start {
+                self.pos += 1;
+                return Ok(Expr::Reference {
+                    table: Some(word.to_string()),
+                    reference: self.structured(content)?,
+                });
+            }
+        }
+
+        match self.peek() {
+            Some(TokenKind::LParen) => {
+                self.pos += 1;
+                return self.parse_call(word);
+            }
+            Some(TokenKind::Bang) => {
+                self.pos += 1;
+                let table = Some(word.to_string());
+                return match self.next() {
+                    Some(TokenKind::Word(target)) => match self.parse_reference(target)? {
+                        Some(reference) => Ok(Expr::Reference { table, reference }),
+                        None => self.error("expected a cell reference after '!'"),
+                    },
+                    _ => self.error("expected a cell reference after '!'"),
+                };
+            }
+            _ => {}
+        }
+
+        if let Some(reference) = self.parse_reference(word)? {
+            return Ok(Expr::Reference {
+                table: None,
+                reference,
+            });
+        }
+
+        if word.eq_ignore_ascii_case("TRUE") {
+            Ok(Expr::Bool(true))
+        } else if word.eq_ignore_ascii_case("FALSE") {
+            Ok(Expr::Bool(false))
+        } else if let Some(col) = column_index(word).filter(|_| self.bare_columns) {
+            Ok(Expr::Reference {
+                table: None,
+                reference: Reference::Cell(CellRef {
+                    col,
+                    row: self.origin.0,
+                    col_absolute: false,
+                    row_absolute: false,
+                }),
+            })
+        } else {
+            Ok(Expr::Name(word.to_string()))
+        }
+    }
+
+    fn cell_ref(&self, word: &str) -> Option<CellRef> {
+        parse_cell_ref(word).or_else(|| parse_relative_ref(word, self.origin))
+    }
+
+    fn parse_reference(&mut self, word: &str) -> Result<Option<Reference>, ParseError> {
+        if let Some(start) = self.cell_ref(word) {
+            if self.peek() == Some(&TokenKind::Colon) {
+                if let Some(TokenKind::Word(end_word)) = self.peek_at(1) {
+                    if let Some(end) = self.cell_ref(end_word) {
+                        self.pos += 2;
+                        return Ok(Some(Reference::Range(start, end)));
+                    }
+                }
+                return self.error("expected a cell reference after ':'");
+            }
+            return Ok(Some(Reference::Cell(start)));
+        }
+
+        if word.eq_ignore_ascii_case("above") {
+            return Ok(Some(self.above(self.origin.1)));
+        }
+
+        let start_col = column_index(word.trim_start_matches('$'));
+        if let (Some(start_col), Some(TokenKind::Colon), Some(TokenKind::Word(end_word))) =
+            (start_col, self.peek(), self.peek_at(1))
+        {
+            if end_word.eq_ignore_ascii_case("above") {
+                self.pos += 2;
+                return Ok(Some(self.above(start_col)));
+            }
+            if let Some(end_col) = column_index(end_word.trim_start_matches('$')) {
+                self.pos += 2;
+                return Ok(Some(Reference::Columns(start_col.min(end_col), start_col.max(end_col))));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// A structured reference from the contents of `[...]`: `Total` for the
+    /// whole column, `@Total` for the cell in the origin row.
+    fn structured(&self, content: &str) -> Result<Reference, ParseError> {
+        let (column, row) = match content.strip_prefix('@') {
+            Some(column) => (column.trim(), Some(self.origin.0)),
+            None => (content, None),
+        };
+        if column.is_empty() {
+            return self.error("expected a column name in '[...]'");
+        }
+        Ok(Reference::Structured {
+            column: column.to_string(),
+            row,
+        })
+    }
+
+    /// The body rows of `col` between the header and the origin row, as in
+    /// `SUM(D:above)`. With no rows in between the range is out of bounds.
+    fn above(&self, col: usize) -> Reference {
+        let (first, last) = match self.origin.0 {
+            0 | 1 => (usize::MAX, usize::MAX),
+            row => (1, row - 1),
+        };
+        Reference::Range(
+            CellRef { col, row: first, col_absolute: false, row_absolute: true },
+            CellRef { col, row: last, col_absolute: false, row_absolute: false },
+        )
+    }
+
+    fn parse_call(&mut self, name: &str) -> Result<Expr, ParseError> {
+        let mut args = Vec::new();
+        if self.peek() == Some(&TokenKind::RParen) {
+            self.pos += 1;
+            return Ok(Expr::Call(name.to_ascii_uppercase(), args));
+        }
+        loop {
+            args.push(self.parse_expr()?);
+            match self.next() {
+                Some(TokenKind::Comma) => {}
+                Some(TokenKind::RParen) => break,
+                _ => {
+                    self.pos = self.pos.saturating_sub(1);
+                    return self.error("expected ',' or ')' in function call");
+                }
+            }
+        }
+        Ok(Expr::Call(name.to_ascii_uppercase(), args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(col: usize, row: usize) -> Expr {
+        Expr::Reference {
+            table: None,
+            reference: Reference::Cell(CellRef {
+                col,
+                row,
+                col_absolute: false,
+                row_absolute: false,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_parse_arithmetic_precedence() {
+        let expr = parse_formula_at("D2+D3*2", 0, 0).unwrap();
+        assert_eq!(
+            expr,
+            Expr::Binary(
+                BinaryOp::Add,
+                Box::new(cell(3, 1)),
+                Box::new(Expr::Binary(BinaryOp::Mul, Box::new(cell(3, 2)), Box::new(Expr::Number(2.0)))),
+            )
+        );
+        assert_eq!(formula_source(" !=B2*C2 "), Some("B2*C2"));
+        assert_eq!(formula_source(" !A "), None);
+    }
+
+    #[test]
+    fn test_parse_ranges_and_calls() {
+        let expr = parse_formula_at("VLOOKUP(A2, rates!$A$2:B9, 2, FALSE)", 0, 0).unwrap();
+        let Expr::Call(name, args) = expr else {
+            panic!("expected a call");
+        };
+        assert_eq!(name, "VLOOKUP");
+        assert_eq!(args.len(), 4);
+        assert_eq!(
+            args[1],
+            Expr::Reference {
+                table: Some("rates".to_string()),
+                reference: Reference::Range(
+                    CellRef { col: 0, row: 1, col_absolute: true, row_absolute: true },
+                    CellRef { col: 1, row: 8, col_absolute: false, row_absolute: false },
+                ),
+            }
+        );
+        assert_eq!(args[3], Expr::Bool(false));
+
+        assert!(matches!(
+            parse_formula_at("SUM(B:C)", 0, 0).unwrap(),
+            Expr::Call(_, ref a) if a[0] == Expr::Reference { table: None, reference: Reference::Columns(1, 2) }
+        ));
+        assert!(parse_formula_at("SUM(B2", 0, 0).is_err());
+        assert_eq!(parse_formula_at("#ref!", 0, 0).unwrap(), Expr::Error("#REF!".to_string()));
+        assert_eq!(parse_formula_at("#N/A", 0, 0).unwrap(), Expr::Error("#N/A".to_string()));
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(column_index("AB"), Some(27));
+    }
+
+    #[test]
+    fn test_relative_references() {
+        let at = |source: &str| parse_formula_at(source, 3, 3).unwrap();
+        assert_eq!(at("B@*C@"), Expr::Binary(BinaryOp::Mul, Box::new(cell(1, 3)), Box::new(cell(2, 3))));
+        assert_eq!(at("RC[-2]*RC[-1]"), at("B@*C@"));
+        assert_eq!(at("R[-1]C+1"), Expr::Binary(BinaryOp::Add, Box::new(cell(3, 2)), Box::new(Expr::Number(1.0))));
+        assert_eq!(
+            at("SUM(R2C:R[-1]C)"),
+            Expr::Call(
+                "SUM".to_string(),
+                vec![Expr::Reference {
+                    table: None,
+                    reference: Reference::Range(
+                        CellRef { col: 3, row: 1, col_absolute: false, row_absolute: true },
+                        CellRef { col: 3, row: 2, col_absolute: false, row_absolute: false },
+                    ),
+                }]
+            )
+        );
+        assert!(matches!(
+            at("R[-9]C"),
+            Expr::Reference { reference: Reference::Cell(CellRef { row: usize::MAX, .. }), .. }
+        ));
+        assert_eq!(at("RC"), Expr::Name("RC".to_string()));
+        assert_eq!(at("rc[-1]"), at("C@"));
+    }
+
+    #[test]
+    fn test_column_formula_bare_columns() {
+        let expr = parse_column_formula("B*C + TRUE", 4, 3).unwrap();
+        assert_eq!(expr, parse_formula_at("B@*C@ + TRUE", 4, 3).unwrap());
+        assert_eq!(parse_formula_at("B", 4, 3).unwrap(), Expr::Name("B".to_string()));
+        assert_eq!(
+            parse_column_formula("SUM(B:C)", 4, 3).unwrap(),
+            parse_formula_at("SUM(B:C)", 4, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_above_ranges() {
+        let range = |col, first, last| Expr::Reference {
+            table: None,
+            reference: Reference::Range(
+                CellRef { col, row: first, col_absolute: false, row_absolute: true },
+                CellRef { col, row: last, col_absolute: false, row_absolute: false },
+            ),
+        };
+        let sum = |arg| Expr::Call("SUM".to_string(), vec![arg]);
+        assert_eq!(parse_formula_at("SUM(D:above)", 4, 0).unwrap(), sum(range(3, 1, 3)));
+        assert_eq!(parse_formula_at("SUM(ABOVE)", 4, 2).unwrap(), sum(range(2, 1, 3)));
+        assert_eq!(parse_formula_at("SUM(above)", 1, 2).unwrap(), sum(range(2, usize::MAX, usize::MAX)));
+    }
+
+    #[test]
+    fn test_structured_references() {
+        let structured = |table: Option<&str>, column: &str, row| Expr::Reference {
+            table: table.map(str::to_string),
+            reference: Reference::Structured { column: column.to_string(), row },
+        };
+        assert_eq!(
+            parse_formula_at("SUM(Orders[Unit Price])", 2, 0).unwrap(),
+            Expr::Call("SUM".to_string(), vec![structured(Some("Orders"), "Unit Price", None)])
+        );
+        assert_eq!(
+            parse_formula_at("[@Price]*[ @Quantity ]", 2, 0).unwrap(),
+            Expr::Binary(
+                BinaryOp::Mul,
+                Box::new(structured(None, "Price", Some(2))),
+                Box::new(structured(None, "Quantity", Some(2)))
+            )
+        );
+        assert_eq!(parse_formula_at("Roster[Name]", 2, 0).unwrap(), structured(Some("Roster"), "Name", None));
+        assert_eq!(parse_formula_at("R[-1]C", 2, 0).unwrap(), cell(0, 1));
+        assert!(parse_formula_at("[@]", 2, 0).is_err());
+        assert!(parse_formula_at("[Price", 2, 0).is_err());
+    }
+}
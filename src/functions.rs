@@ -0,0 +1,633 @@
+// functions.rs
+
+use std::cmp::Ordering;
+
+use crate::arrays;
+use crate::dates;
+use crate::finance;
+use crate::statistics;
+use crate::evaluator::{compare_values, CellError, Value};
+
+/// Calls a built-in function with already evaluated arguments. Ranges arrive
+/// as `Value::Array`.
+pub fn call(name: &str, args: &[Value]) -> Value {
+    if let Some(result) = arrays::call(name, args)
+        .or_else(|| dates::call(name, args))
+        .or_else(|| statistics::call(name, args))
+        .or_else(|| finance::call(name, args))
+    {
+        return result.unwrap_or_else(Value::Error);
+    }
+
+    let result = match name {
+        "SUM" => numbers(args).map(|n| Value::Number(n.iter().sum())),
+        "AVERAGE" => numbers(args).and_then(|n| {
+            if n.is_empty() {
+                Err(CellError::Div0)
+            } else {
+                Ok(Value::Number(n.iter().sum::<f64>() / n.len() as f64))
+            }
+        }),
+        "MIN" => numbers(args).map(|n| keep_dates(args, n.into_iter().reduce(f64::min).unwrap_or(0.0))),
+        "MAX" => numbers(args).map(|n| keep_dates(args, n.into_iter().reduce(f64::max).unwrap_or(0.0))),
+        "COUNT" => Ok(Value::Number(
+            flatten(args).filter(|v| matches!(v, Value::Number(_) | Value::Date(_))).count() as f64,
+        )),
+        "COUNTA" => Ok(Value::Number(
+            flatten(args).filter(|v| !matches!(v, Value::Empty)).count() as f64,
+        )),
+        "ABS" => unary_number(args, f64::abs),
+        "ROUND" => round(args),
+        "AND" => logical(args, true),
+        "OR" => logical(args, false),
+        "NOT" => arity(args, 1, 1).and_then(|_| args[0].as_bool()).map(|b| Value::Bool(!b)),
+        "CONCAT" | "CONCATENATE" => flatten(args)
+            .map(|v| v.as_text())
+            .collect::<Result<String, _>>()
+            .map(Value::Text),
+        "NA" => Err(CellError::NA),
+        "VLOOKUP" => vlookup(args, false),
+        "HLOOKUP" => vlookup(args, true),
+        "MATCH" => match_fn(args),
+        "INDEX" => index(args),
+        "XLOOKUP" => xlookup(args),
+        "SUMIF" => aggregate_if(args, Aggregate::Sum),
+        "AVERAGEIF" => aggregate_if(args, Aggregate::Average),
+        "COUNTIF" => arity(args, 2, 2).and_then(|_| aggregate_ifs(None, args, Aggregate::Count)),
+        "SUMIFS" => aggregate_ifs_with_target(args, Aggregate::Sum),
+        "AVERAGEIFS" => aggregate_ifs_with_target(args, Aggregate::Average),
+        "MAXIFS" => aggregate_ifs_with_target(args, Aggregate::Max),
+        "MINIFS" => aggregate_ifs_with_target(args, Aggregate::Min),
+        "COUNTIFS" => aggregate_ifs(None, args, Aggregate::Count),
+        _ => Err(CellError::Name),
+    };
+    result.unwrap_or_else(Value::Error)
+}
+
+/// Every function formulas may call, including those the evaluator handles
+/// itself because they do not evaluate all their arguments.
+const NAMES: [&str; 69] = [
+    "ABS", "AND", "AVERAGE", "AVERAGEIF", "AVERAGEIFS", "CONCAT", "CONCATENATE", "COUNT", "COUNTA", "COUNTIF",
+    "COUNTIFS", "DATE", "DATEDIF", "DAY", "EDATE", "EOMONTH", "FILTER", "FV", "HLOOKUP", "IF", "IFERROR", "IFNA",
+    "INDEX", "IRR", "LARGE", "MATCH", "MAX", "MAXIFS", "MEDIAN", "MIN", "MINIFS", "MODE", "MODE.SNGL", "MONTH",
+    "NA", "NETWORKDAYS", "NOT", "NPV", "OR", "PERCENTILE", "PERCENTILE.INC", "PMT", "PV", "QUARTILE",
+    "QUARTILE.INC", "RANK", "RANK.EQ", "RATE", "ROUND", "SEQUENCE", "SMALL", "SORT", "STDEV", "STDEV.P", "STDEV.S",
+    "STDEVP", "SUM", "SUMIF", "SUMIFS", "TODAY", "UNIQUE", "VAR", "VAR.P", "VAR.S", "VARP", "VLOOKUP", "WEEKDAY",
+    "XLOOKUP", "YEAR",
+];
+
+/// Whether `name` (in any case) is a function mdcalc can evaluate.
+pub fn is_supported(name: &str) -> bool {
+    NAMES.iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+pub fn arity(args: &[Value], min: usize, max: usize) -> Result<(), CellError> {
+    if args.len() < min || args.len() > max {
+        Err(CellError::Value)
+    } else {
+        Ok(())
+    }
+}
+
+/// Every value of every argument, with ranges flattened row by row.
+pub fn flatten(args: &[Value]) -> impl Iterator<Item = &Value> {
+    args.iter().flat_map(|a| match a {
+        Value::Array(rows) => rows.iter().flatten().collect::<Vec<_>>(),
+        other => vec![other],
+    })
+}
+
+/// Numeric arguments for aggregates: numbers inside ranges are used and other
+/// range values skipped, while direct arguments must convert to numbers.
+pub fn numbers(args: &[Value]) -> Result<Vec<f64>, CellError> {
+    let mut out = Vec::new();
+    for arg in args {
+        match arg {
+            Value::Array(rows) => {
+                for v in rows.iter().flatten() {
+                    match v {
+                        Value::Number(n) | Value::Date(n) => out.push(*n),
+                        Value::Error(e) => return Err(*e),
+                        _ => {}
+                    }
+                }
+            }
+            Value::Empty => {}
+            other => out.push(other.as_number()?),
+        }
+    }
+    Ok(out)
+}
+
+/// The result of MIN or MAX is a date when every argument is.
+fn keep_dates(args: &[Value], n: f64) -> Value {
+    let mut values = flatten(args).filter(|v| !matches!(v, Value::Empty)).peekable();
+    if values.peek().is_some() && values.all(|v| matches!(v, Value::Date(_))) {
+        Value::Date(n)
+    } else {
+        Value::Number(n)
+    }
+}
+
+fn unary_number(args: &[Value], f: fn(f64) -> f64) -> Result<Value, CellError> {
+    arity(args, 1, 1)?;
+    Ok(Value::Number(f(args[0].as_number()?)))
+}
+
+fn round(args: &[Value]) -> Result<Value, CellError> {
+    arity(args, 1, 2)?;
+    let n = args[0].as_number()?;
+    let digits = args.get(1).map(Value::as_number).transpose()?.unwrap_or(0.0).trunc();
+    let factor = 10f64.powf(digits);
+    Ok(Value::Number((n * factor).round() / factor))
+}
+
+fn logical(args: &[Value], all: bool) -> Result<Value, CellError> {
+    arity(args, 1, usize::MAX)?;
+    let mut result = all;
+    for v in flatten(args).filter(|v| !matches!(v, Value::Empty)) {
+        let b = v.as_bool()?;
+        result = if all { result && b } else { result || b };
+    }
+    Ok(Value::Bool(result))
+}
+
+/// Spreadsheet wildcard matching: `*` any run, `?` one character, `~`
+/// escapes the next character. Case-insensitive.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    fn matches(p: &[char], t: &[char]) -> bool {
+        match p.first() {
+            None => t.is_empty(),
+            Some('*') => (0..=t.len()).any(|i| matches(&p[1..], &t[i..])),
+            Some('?') => !t.is_empty() && matches(&p[1..], &t[1..]),
+            Some('~') if p.len() > 1 => t.first() == Some(&p[1]) && matches(&p[2..], &t[1..]),
+            Some(c) => t.first() == Some(c) && matches(&p[1..], &t[1..]),
+        }
+    }
+
+    matches(&pattern, &text)
+}
+
+fn values_equal(needle: &Value, candidate: &Value, wildcards: bool) -> bool {
+    match (needle, candidate) {
+        (Value::Text(pattern), Value::Text(text)) if wildcards => wildcard_match(pattern, text),
+        (Value::Text(_), Value::Text(_))
+        | (Value::Number(_) | Value::Date(_), Value::Number(_) | Value::Date(_))
+        | (Value::Bool(_), Value::Bool(_)) => compare_values(needle, candidate) == Ordering::Equal,
+        _ => false,
+    }
+}
+
+fn same_kind(a: &Value, b: &Value) -> bool {
+    matches!(
+        (a, b),
+        (Value::Number(_) | Value::Date(_), Value::Number(_) | Value::Date(_))
+            | (Value::Text(_), Value::Text(_))
+            | (Value::Bool(_), Value::Bool(_))
+    )
+}
+
+/// Position of `needle` in `items`. `mode` follows MATCH: 0 exact (with
+/// wildcards), 1 largest value <= needle in ascending data, -1 smallest value
+/// >= needle in descending data.
+fn find_position(needle: &Value, items: &[Value], mode: i32) -> Option<usize> {
+    match mode {
+        0 => items.iter().position(|v| values_equal(needle, v, true)),
+        1 => {
+            let mut found = None;
+            for (i, v) in items.iter().enumerate() {
+                if !same_kind(needle, v) {
+                    continue;
+                }
+                if compare_values(v, needle) == Ordering::Greater {
+                    break;
+                }
+                found = Some(i);
+            }
+            found
+        }
+        _ => {
+            let mut found = None;
+            for (i, v) in items.iter().enumerate() {
+                if !same_kind(needle, v) {
+                    continue;
+                }
+                if compare_values(v, needle) == Ordering::Less {
+                    break;
+                }
+                found = Some(i);
+            }
+            found
+        }
+    }
+}
+
+/// Splits a range into a single row or column of values.
+fn vector(value: &Value) -> Result<Vec<Value>, CellError> {
+    let rows = value.clone().into_rows();
+    if rows.len() == 1 {
+        Ok(rows.into_iter().next().unwrap_or_default())
+    } else if rows.iter().all(|r| r.len() == 1) {
+        Ok(rows.into_iter().flatten().collect())
+    } else {
+        Err(CellError::NA)
+    }
+}
+
+fn lookup_key(value: &Value) -> Result<Value, CellError> {
+    match value.clone().scalar() {
+        Value::Error(e) => Err(e),
+        other => Ok(other),
+    }
+}
+
+fn vlookup(args: &[Value], horizontal: bool) -> Result<Value, CellError> {
+    arity(args, 3, 4)?;
+    let needle = lookup_key(&args[0])?;
+    let mut rows = args[1].clone().into_rows();
+    if horizontal {
+        rows = transpose(rows);
+    }
+    let index = args[2].as_number()?.trunc();
+    if index < 1.0 {
+        return Err(CellError::Value);
+    }
+    let index = index as usize - 1;
+    let approximate = match args.get(3) {
+        Some(v) => v.as_bool()?,
+        None => true,
+    };
+
+    let keys: Vec<Value> = rows.iter().map(|r| r.first().cloned().unwrap_or(Value::Empty)).collect();
+    let position = find_position(&needle, &keys, if approximate { 1 } else { 0 }).ok_or(CellError::NA)?;
+    let row = &rows[position];
+    if index >= row.len() {
+        return Err(CellError::Ref);
+    }
+    Ok(row[index].clone())
+}
+
+fn transpose(rows: Vec<Vec<Value>>) -> Vec<Vec<Value>> {
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    (0..width)
+        .map(|c| rows.iter().map(|r| r.get(c).cloned().unwrap_or(Value::Empty)).collect())
+        .collect()
+}
+
+fn match_fn(args: &[Value]) -> Result<Value, CellError> {
+    arity(args, 2, 3)?;
+    let needle = lookup_key(&args[0])?;
+    let items = vector(&args[1])?;
+    let mode = match args.get(2) {
+        Some(v) => match v.as_number()? {
+            m if m > 0.0 => 1,
+            m if m < 0.0 => -1,
+            _ => 0,
+        },
+        None => 1,
+    };
+    find_position(&needle, &items, mode)
+        .map(|i| Value::Number((i + 1) as f64))
+        .ok_or(CellError::NA)
+}
+
+fn index(args: &[Value]) -> Result<Value, CellError> {
+    arity(args, 2, 3)?;
+    let rows = args[0].clone().into_rows();
+    let first = args[1].as_number()?.trunc();
+    let second = args.get(2).map(Value::as_number).transpose()?.map(f64::trunc);
+    if first < 0.0 || second.is_some_and(|c| c < 0.0) {
+        return Err(CellError::Value);
+    }
+
+    // A single-row range indexed by one number is indexed by column.
+    let (row, col) = match second {
+        None if rows.len() == 1 => (1, first as usize),
+        None => (first as usize, 1),
+        Some(c) => (first as usize, c as usize),
+    };
+
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    if row > rows.len() || col > width {
+        return Err(CellError::Ref);
+    }
+    match (row, col) {
+        (0, 0) => Ok(Value::Array(rows)),
+        (0, c) => Ok(Value::Array(
+            rows.iter().map(|r| vec![r.get(c - 1).cloned().unwrap_or(Value::Empty)]).collect(),
+        )),
+        (r, 0) => Ok(Value::Array(vec![rows[r - 1].clone()])),
+        (r, c) => Ok(rows[r - 1].get(c - 1).cloned().unwrap_or(Value::Empty)),
+    }
+}
+
+fn xlookup(args: &[Value]) -> Result<Value, CellError> {
+    arity(args, 3, 6)?;
+    let needle = lookup_key(&args[0])?;
+    let keys = vector(&args[1])?;
+    let results = args[2].clone().into_rows();
+    let match_mode = match args.get(4) {
+        Some(v) => v.as_number()? as i32,
+        None => 0,
+    };
+    let search_mode = match args.get(5) {
+        Some(v) => v.as_number()? as i32,
+        None => 1,
+    };
+
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    if search_mode < 0 {
+        order.reverse();
+    }
+
+    let mut best: Option<usize> = None;
+    for i in order {
+        let candidate = &keys[i];
+        if values_equal(&needle, candidate, match_mode == 2) {
+            best = Some(i);
+            break;
+        }
+        if match_mode == 0 || match_mode == 2 || !same_kind(&needle, candidate) {
+            continue;
+        }
+        // -1: next smaller item, 1: next larger item.
+        let wanted = if match_mode < 0 { Ordering::Less } else { Ordering::Greater };
+        if compare_values(candidate, &needle) != wanted {
+            continue;
+        }
+        let better = match best {
+            None => true,
+            Some(b) => compare_values(candidate, &keys[b]) == wanted.reverse(),
+        };
+        if better {
+            best = Some(i);
+        }
+    }
+
+    let Some(position) = best else {
+        return match args.get(3) {
+            Some(fallback) => Ok(fallback.clone()),
+            None => Err(CellError::NA),
+        };
+    };
+
+    // The return range runs parallel to the lookup range: pick the matching
+    // row of a vertical lookup or the matching column of a horizontal one.
+    let vertical = args[1].clone().into_rows().len() > 1;
+    if vertical {
+        let row = results.get(position).ok_or(CellError::Ref)?;
+        Ok(if row.len() == 1 { row[0].clone() } else { Value::Array(vec![row.clone()]) })
+    } else {
+        let column: Vec<Vec<Value>> = results
+            .iter()
+            .map(|r| vec![r.get(position).cloned().unwrap_or(Value::Empty)])
+            .collect();
+        Ok(if column.len() == 1 { column[0][0].clone() } else { Value::Array(column) })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CriteriaOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A parsed criteria argument such as `">10"`, `"<>Fruit"` or `"Fr*"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Criteria {
+    op: CriteriaOp,
+    operand: Value,
+}
+
+impl Criteria {
+    pub fn parse(value: &Value) -> Result<Criteria, CellError> {
+        let text = match value.clone().scalar() {
+            Value::Text(text) => text,
+            Value::Error(e) => return Err(e),
+            other => {
+                return Ok(Criteria {
+                    op: CriteriaOp::Eq,
+                    operand: other,
+                })
+            }
+        };
+
+        let (op, rest) = [
+            (">=", CriteriaOp::Ge),
+            ("<=", CriteriaOp::Le),
+            ("<>", CriteriaOp::Ne),
+            (">", CriteriaOp::Gt),
+            ("<", CriteriaOp::Lt),
+            ("=", CriteriaOp::Eq),
+        ]
+        .iter()
+        .find_map(|(prefix, op)| text.strip_prefix(prefix).map(|rest| (*op, rest)))
+        .unwrap_or((CriteriaOp::Eq, text.as_str()));
+
+        Ok(Criteria {
+            op,
+            operand: Value::from_cell_text(rest),
+        })
+    }
+
+    pub fn matches(&self, value: &Value) -> bool {
+        let equal = match (&self.operand, value) {
+            (Value::Empty, v) => matches!(v, Value::Empty) || matches!(v, Value::Text(t) if t.is_empty()),
+            (operand @ Value::Text(_), candidate @ Value::Text(_)) => values_equal(operand, candidate, true),
+            (operand, candidate) => values_equal(operand, candidate, false),
+        };
+        match self.op {
+            CriteriaOp::Eq => equal,
+            CriteriaOp::Ne => !equal,
+            _ if !same_kind(&self.operand, value) => false,
+            op => {
+                let ordering = compare_values(value, &self.operand);
+                match op {
+                    CriteriaOp::Lt => ordering == Ordering::Less,
+                    CriteriaOp::Le => ordering != Ordering::Greater,
+                    CriteriaOp::Gt => ordering == Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Aggregate {
+    Sum,
+    Average,
+    Count,
+    Max,
+    Min,
+}
+
+/// SUMIF and AVERAGEIF: `(range, criteria, [target_range])`.
+fn aggregate_if(args: &[Value], aggregate: Aggregate) -> Result<Value, CellError> {
+    arity(args, 2, 3)?;
+    let target = args.get(2).unwrap_or(&args[0]);
+    aggregate_ifs(Some(target), &args[..2], aggregate)
+}
+
+/// The *IFS functions that take the target range first.
+fn aggregate_ifs_with_target(args: &[Value], aggregate: Aggregate) -> Result<Value, CellError> {
+    if args.len() < 3 {
+        return Err(CellError::Value);
+    }
+    aggregate_ifs(Some(&args[0]), &args[1..], aggregate)
+}
+
+/// Aggregates the cells of `target` whose positions satisfy every
+/// `(range, criteria)` pair. Without a target, matching cells are counted.
+fn aggregate_ifs(target: Option<&Value>, pairs: &[Value], aggregate: Aggregate) -> Result<Value, CellError> {
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return Err(CellError::Value);
+    }
+
+    let conditions = pairs
+        .chunks(2)
+        .map(|pair| Ok((pair[0].clone().into_rows(), Criteria::parse(&pair[1])?)))
+        .collect::<Result<Vec<_>, CellError>>()?;
+
+    let (first_range, _) = &conditions[0];
+    let height = first_range.len();
+    let width = first_range.first().map_or(0, Vec::len);
+    if conditions
+        .iter()
+        .any(|(range, _)| range.len() != height || range.first().map_or(0, Vec::len) != width)
+    {
+        return Err(CellError::Value);
+    }
+
+    let target = target.map(|t| t.clone().into_rows());
+    let mut selected = Vec::new();
+    let mut count = 0;
+    for row in 0..height {
+        for col in 0..width {
+            let matched = conditions.iter().all(|(range, criteria)| {
+                criteria.matches(range[row].get(col).unwrap_or(&Value::Empty))
+            });
+            if !matched {
+                continue;
+            }
+            count += 1;
+            // Like spreadsheets, the target range is read at the same offset
+            // as the criteria range, whatever its own size.
+            if let Some(target) = &target {
+                match target.get(row).and_then(|r| r.get(col)) {
+                    Some(Value::Number(n) | Value::Date(n)) => selected.push(*n),
+                    Some(Value::Error(e)) => return Err(*e),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    match aggregate {
+        Aggregate::Count => Ok(Value::Number(count as f64)),
+        Aggregate::Sum => Ok(Value::Number(selected.iter().sum())),
+        Aggregate::Average if selected.is_empty() => Err(CellError::Div0),
+        Aggregate::Average => Ok(Value::Number(selected.iter().sum::<f64>() / selected.len() as f64)),
+        Aggregate::Max => Ok(Value::Number(selected.into_iter().reduce(f64::max).unwrap_or(0.0))),
+        Aggregate::Min => Ok(Value::Number(selected.into_iter().reduce(f64::min).unwrap_or(0.0))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n(x: f64) -> Value {
+        Value::Number(x)
+    }
+
+    fn t(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn rates() -> Value {
+        Value::Array(vec![
+            vec![t("Apples"), n(2.0)],
+            vec![t("Bananas"), n(1.0)],
+            vec![t("Cherries"), n(4.0)],
+        ])
+    }
+
+    #[test]
+    fn test_vlookup_and_hlookup() {
+        assert_eq!(call("VLOOKUP", &[t("bananas"), rates(), n(2.0), Value::Bool(false)]), n(1.0));
+        assert_eq!(
+            call("VLOOKUP", &[t("Kiwi"), rates(), n(2.0), Value::Bool(false)]),
+            Value::Error(CellError::NA)
+        );
+        let brackets = Value::Array(vec![vec![n(0.0), t("low")], vec![n(10.0), t("mid")], vec![n(100.0), t("high")]]);
+        assert_eq!(call("VLOOKUP", &[n(42.0), brackets.clone(), n(2.0)]), t("mid"));
+        assert_eq!(call("VLOOKUP", &[n(-1.0), brackets, n(2.0)]), Value::Error(CellError::NA));
+
+        let header = Value::Array(vec![vec![t("Q1"), t("Q2")], vec![n(10.0), n(20.0)]]);
+        assert_eq!(call("HLOOKUP", &[t("Q2"), header, n(2.0), Value::Bool(false)]), n(20.0));
+    }
+
+    #[test]
+    fn test_index_match_and_xlookup() {
+        let names = Value::Array(vec![vec![t("Apples")], vec![t("Bananas")], vec![t("Cherries")]]);
+        let prices = Value::Array(vec![vec![n(2.0)], vec![n(1.0)], vec![n(4.0)]]);
+        assert_eq!(call("MATCH", &[t("Ch*"), names.clone(), n(0.0)]), n(3.0));
+        assert_eq!(call("INDEX", &[prices.clone(), n(2.0)]), n(1.0));
+        assert_eq!(call("INDEX", &[rates(), n(3.0), n(2.0)]), n(4.0));
+
+        assert_eq!(call("XLOOKUP", &[t("Cherries"), names.clone(), prices.clone()]), n(4.0));
+        assert_eq!(call("XLOOKUP", &[t("Kiwi"), names, prices.clone(), t("none")]), t("none"));
+        let sizes = Value::Array(vec![vec![n(10.0)], vec![n(30.0)], vec![n(20.0)]]);
+        assert_eq!(call("XLOOKUP", &[n(25.0), sizes.clone(), prices.clone(), Value::Empty, n(1.0)]), n(1.0));
+        assert_eq!(call("XLOOKUP", &[n(25.0), sizes, prices, Value::Empty, n(-1.0)]), n(4.0));
+        assert!(wildcard_match("fr?it*", "Fruit salad"));
+        assert!(!wildcard_match("~*", "x"));
+    }
+
+    #[test]
+    fn test_conditional_aggregates() {
+        let category = Value::Array(vec![vec![t("Fruit")], vec![t("Veg")], vec![t("fruit")], vec![t("Frozen")]]);
+        let total = Value::Array(vec![vec![n(6.0)], vec![n(5.0)], vec![n(8.0)], vec![n(12.0)]]);
+
+        assert_eq!(call("SUMIF", &[category.clone(), t("Fruit"), total.clone()]), n(14.0));
+        assert_eq!(call("SUMIF", &[category.clone(), t("Fr*"), total.clone()]), n(26.0));
+        assert_eq!(call("SUMIF", &[total.clone(), t(">6")]), n(20.0));
+        assert_eq!(call("COUNTIF", &[category.clone(), t("<>Veg")]), n(3.0));
+        assert_eq!(call("AVERAGEIF", &[category.clone(), t("Veg"), total.clone()]), n(5.0));
+        assert_eq!(
+            call("AVERAGEIF", &[category.clone(), t("Meat"), total.clone()]),
+            Value::Error(CellError::Div0)
+        );
+
+        assert_eq!(
+            call("SUMIFS", &[total.clone(), category.clone(), t("Fr*"), total.clone(), t("<=8")]),
+            n(14.0)
+        );
+        assert_eq!(call("COUNTIFS", &[category.clone(), t("?????"), total.clone(), n(5.0)]), n(0.0));
+        assert_eq!(call("MAXIFS", &[total.clone(), category.clone(), t("fruit")]), n(8.0));
+        assert_eq!(call("MINIFS", &[total.clone(), category.clone(), t("F*")]), n(6.0));
+        assert_eq!(
+            call("SUMIFS", &[total, category, t("Fruit"), Value::Array(vec![vec![n(1.0)]]), n(1.0)]),
+            Value::Error(CellError::Value)
+        );
+    }
+
+    #[test]
+    fn test_names_are_callable() {
+        for name in NAMES {
+            if matches!(name, "IF" | "IFERROR" | "IFNA" | "TODAY") {
+                continue;
+            }
+            assert_ne!(call(name, &[n(1.0), n(1.0), n(1.0)]), Value::Error(CellError::Name), "{}", name);
+        }
+        assert!(is_supported("sum") && !is_supported("INDIRECT"));
+    }
+}
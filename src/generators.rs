@@ -0,0 +1,278 @@
+// generators.rs
+
+use std::cmp::Ordering;
+
+use comrak::{parse_document, Arena, ComrakOptions};
+
+use crate::directives::{directives_on_line, table_names, Directive};
+use crate::evaluator::{compare_values, format_value, Evaluator, Value, Workbook};
+use crate::formula::parse_column_formula;
+use crate::functions;
+use crate::md_comments::{parse_markdown_for_comments, CommentKind};
+use crate::table_parser::{MarkdownTable, TableParser};
+use crate::table_writer::render_grid;
+
+/// A line holding a generator directive. The generated table follows it.
+struct Anchor {
+    line_start: usize,
+    line_end: usize,
+    directive: Directive,
+}
+
+fn find_anchors(markdown: &str, comment_offsets: &[usize]) -> Vec<Anchor> {
+    let mut anchors: Vec<Anchor> = Vec::new();
+    for &offset in comment_offsets {
+        let line_start = markdown[..offset].rfind('\n').map_or(0, |i| i + 1);
+        if anchors.last().is_some_and(|a| a.line_start == line_start) {
+            continue;
+        }
+        let line_end = markdown[offset..].find('\n').map_or(markdown.len(), |i| offset + i);
+        if let Some(directive) = directives_on_line(&markdown[line_start..line_end])
+            .into_iter()
+            .find(Directive::is_generator)
+        {
+            anchors.push(Anchor {
+                line_start,
+                line_end,
+                directive,
+            });
+        }
+    }
+    anchors
+}
+
+/// The cell texts of a table row, trimmed.
+fn row_texts(table: &MarkdownTable, row: usize) -> Vec<String> {
+    table.rows[row]
+        .cells
+        .iter()
+        .map(|c| c.text_content().trim().to_string())
+        .collect()
+}
+
+/// The rows of `source` for which `condition` holds, as cell texts with
+/// the header first. Rows where the condition is an error are left out.
+fn filter_rows(workbook: &Workbook, today: f64, tables: &[MarkdownTable], source: usize, condition: &str) -> Option<Vec<Vec<String>>> {
+    let table = &tables[source];
+    let mut evaluator = Evaluator::new(workbook, today);
+    let mut rows = vec![row_texts(table, 0)];
+    for row in 1..table.rows.len() {
+        let expr = parse_column_formula(condition, row, 0).ok()?;
+        if evaluator.eval(&expr, source).scalar().as_bool() == Ok(true) {
+            rows.push(row_texts(table, row));
+        }
+    }
+    Some(rows)
+}
+
+/// One row per distinct value of the `rows` column of `source`, in
+/// ascending order, with each `(function, column)` aggregate of the rows in
+/// the group. Summary rows of the source are left out. `None` if a column
+/// does not exist.
+fn pivot_rows(workbook: &Workbook, source: usize, rows: &str, values: &[(String, String)]) -> Option<Vec<Vec<String>>> {
+    let sheet = &workbook.sheets[source];
+    let key_col = sheet.column_by_name(rows)?;
+    let value_cols = values
+        .iter()
+        .map(|(_, column)| sheet.column_by_name(column))
+        .collect::<Option<Vec<_>>>()?;
+    let text = |row: usize, col: usize| sheet.cell(row, col).map_or("", |c| c.text.as_str());
+
+    let mut groups: Vec<(Value, Vec<usize>)> = Vec::new();
+    for row in 1..sheet.summary_start() {
+        let key = Value::from_cell_text(text(row, key_col));
+        match groups.iter_mut().find(|(k, _)| compare_values(k, &key) == Ordering::Equal) {
+            Some((_, members)) => members.push(row),
+            None => groups.push((key, vec![row])),
+        }
+    }
+    groups.sort_by(|a, b| compare_values(&a.0, &b.0));
+
+    let mut header = vec![text(0, key_col).to_string()];
+    header.extend(values.iter().map(|(function, column)| format!("{}({})", function, column)));
+    let mut out = vec![header];
+    for (key, members) in groups {
+        let mut line = vec![key.to_string()];
+        for ((function, _), col) in values.iter().zip(&value_cols) {
+            let column = Value::Array(members.iter().map(|r| vec![Value::from_cell_text(text(*r, *col))]).collect());
+            line.push(format_value(&functions::call(function, &[column]), None));
+        }
+        out.push(line);
+    }
+    Some(out)
+}
+
+/// Regenerates the table after every generator directive (`!filter`,
+/// `!pivot`) from the current contents of the document, inserting the
+/// table when it does not exist yet. Directives whose source table cannot
+/// be found or whose condition does not parse leave the document unchanged.
+pub fn generate_tables(markdown: &str, today: f64) -> String {
+    let arena = Arena::new();
+    let root = parse_document(&arena, markdown, &ComrakOptions::default());
+    let comments = parse_markdown_for_comments(&arena, markdown);
+    let tables = TableParser::extract_tables_from_ast(root, Some(&comments), markdown);
+    let names = table_names(markdown, &tables);
+    let workbook = Workbook::from_tables(&tables, &names);
+
+    let offsets: Vec<usize> = comments
+        .iter()
+        .filter(|c| c.comment.kind == CommentKind::Directive)
+        .map(|c| c.comment.offset)
+        .collect();
+    let anchors = find_anchors(markdown, &offsets);
+
+    // The table generated by each anchor starts on the line right after it.
+    // A table further down, even past a blank line only, is the user's.
+    let generated: Vec<Option<usize>> = anchors
+        .iter()
+        .map(|a| {
+            tables.iter().position(|t| {
+                let line_start = markdown[..t.start_offset].rfind('\n').map_or(0, |i| i + 1);
+                line_start == a.line_end + 1
+            })
+        })
+        .collect();
+
+    let mut edits = Vec::new();
+    for (anchor, existing) in anchors.iter().zip(&generated) {
+        // A named table, or the nearest table above that is not generated.
+        let source_table = |source: &Option<String>| match source {
+            Some(name) => names.iter().position(|n| n.eq_ignore_ascii_case(name)),
+            None => (0..tables.len())
+                .rev()
+                .find(|t| tables[*t].end_offset <= anchor.line_start && !generated.contains(&Some(*t))),
+        };
+        let rows = match &anchor.directive {
+            Directive::Filter { source, condition } => source_table(source)
+                .and_then(|s| filter_rows(&workbook, today, &tables, s, condition)),
+            Directive::Pivot { source, rows, values } => source_table(source)
+                .and_then(|s| pivot_rows(&workbook, s, rows, values)),
+            _ => None,
+        };
+        let Some(rows) = rows else {
+            continue;
+        };
+        let text = render_grid(&rows);
+        match existing {
+            Some(t) => edits.push((tables[*t].start_offset, tables[*t].end_offset, text)),
+            None => edits.push((anchor.line_end, anchor.line_end, format!("\n{}", text))),
+        }
+    }
+
+    let mut out = markdown.to_string();
+    for (start, end, text) in edits.into_iter().rev() {
+        out.replace_range(start..end, &text);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_generates_and_updates_table() {
+        let markdown = "\
+<!-- !table: fruit -->
+| Item | Price | Quantity |
+|------|-------|----------|
+| Apples | 2 | 3 |
+| Bananas | 1 | 5 |
+| Cherries | 4 | 2 |
+
+Large orders:
+
+<!-- !filter: C > 2 -->
+
+<!-- !filter fruit: [@Price] >= 2 -->
+| stale |
+|-------|
+";
+        let generated = generate_tables(markdown, 0.0);
+        assert!(generated.contains(
+            "<!-- !filter: C > 2 -->
+| Item    | Price | Quantity |
+| ------- | ----- | -------- |
+| Apples  | 2     | 3        |
+| Bananas | 1     | 5        |
+
+"
+        ), "{}", generated);
+        assert!(generated.ends_with(
+            "<!-- !filter fruit: [@Price] >= 2 -->
+| Item     | Price | Quantity |
+| -------- | ----- | -------- |
+| Apples   | 2     | 3        |
+| Cherries | 4     | 2        |
+"
+        ));
+        assert_eq!(generate_tables(&generated, 0.0), generated);
+
+        let edited = generated.replace("| Bananas | 1 | 5 |", "| Bananas | 1 | 1 |");
+        let regenerated = generate_tables(&edited, 0.0);
+        assert!(!regenerated.contains("| Bananas | 1     | 5        |"));
+    }
+
+    #[test]
+    fn test_pivot_groups_and_aggregates() {
+        let markdown = "\
+<!-- !table: orders -->
+| Item | Category | Total |
+|------|----------|-------|
+| Apples | Fruit | 6 |
+| Carrots | Vegetable | 2 |
+| Cherries | Fruit | 8 |
+| Total | | 16 <!-- =SUM(C:above) --> |
+
+<!-- !pivot source=orders rows=Category values=SUM(Total), COUNTA(Item), MAX(Total) -->
+";
+        let generated = generate_tables(markdown, 0.0);
+        assert!(generated.ends_with(
+            "values=SUM(Total), COUNTA(Item), MAX(Total) -->
+| Category  | SUM(Total) | COUNTA(Item) | MAX(Total) |
+| --------- | ---------- | ------------ | ---------- |
+| Fruit     | 14         | 2            | 8          |
+| Vegetable | 2          | 1            | 2          |
+"
+        ), "{}", generated);
+        let missing = markdown.replace("rows=Category", "rows=Colour");
+        assert_eq!(generate_tables(&missing, 0.0), missing);
+
+        // A table of the user's after a blank line is not the pivot's.
+        let notes = format!("{}\n| Note |\n|------|\n| keep me |\n", markdown);
+        let generated = generate_tables(&notes, 0.0);
+        assert!(generated.ends_with("| Vegetable | 2          | 1            | 2          |\n\n| Note |\n|------|\n| keep me |\n"), "{}", generated);
+        assert_eq!(generate_tables(&generated, 0.0), generated);
+    }
+
+    #[test]
+    fn test_filter_keeps_user_table_after_blank_line() {
+        let markdown = "\
+| Item | Price | Quantity |
+|------|-------|----------|
+| Apples | 2 | 3 |
+| Bananas | 1 | 5 |
+
+<!-- !filter: C > 4 -->
+
+| Budget | Amount |
+|--------|--------|
+| Rent | 900 |
+";
+        let generated = generate_tables(markdown, 0.0);
+        assert!(generated.contains(
+            "<!-- !filter: C > 4 -->
+| Item    | Price | Quantity |
+| ------- | ----- | -------- |
+| Bananas | 1     | 5        |
+
+| Budget | Amount |
+|--------|--------|
+| Rent | 900 |
+"
+        ), "{}", generated);
+        assert_eq!(generate_tables(&generated, 0.0), generated);
+        let tables = TableParser::parse_tables(&generated);
+        assert_eq!(table_names(&generated, &tables), vec!["Table1", "", "Table2"]);
+    }
+}
@@ -0,0 +1,104 @@
+// html.rs
+
+use comrak::{markdown_to_html, Arena, ComrakOptions};
+
+use crate::directives::table_names;
+use crate::evaluator::Workbook;
+use crate::md_comments::{parse_markdown_for_comments, CommentKind};
+use crate::table_parser::{TableCellPiece, TableParser};
+use crate::table_writer::render_document;
+
+/// Renders `markdown`, which should be recalculated first, as HTML. The
+/// value of every formula cell is wrapped in a `<span>` whose `title` and
+/// `data-formula` attributes hold the formula, and the comments mdcalc reads
+/// (markers, formulas, directives and so on) are left out. Other comments and
+/// inline HTML pass through.
+pub fn render_html(markdown: &str) -> String {
+    let originals = TableParser::parse_tables(markdown);
+    let names = table_names(markdown, &originals);
+    let workbook = Workbook::from_tables(&originals, &names);
+    let mut tables = originals.clone();
+    for (table, sheet) in tables.iter_mut().zip(&workbook.sheets) {
+        for (r, row) in table.rows.iter_mut().enumerate() {
+            for (c, cell) in row.cells.iter_mut().enumerate() {
+                let text = cell.text_content().trim().to_string();
+                cell.pieces
+                    .retain(|p| matches!(p, TableCellPiece::Comment(c) if c.comment.kind == CommentKind::Unknown));
+                let text = match sheet.cell(r, c).and_then(|s| s.formula.as_deref()) {
+                    Some(formula) => {
+                        let formula = attribute(&format!("={}", formula));
+                        format!("<span title=\"{}\" data-formula=\"{}\">{}</span>", formula, formula, text)
+                    }
+                    None => text,
+                };
+                cell.pieces.insert(0, TableCellPiece::Text(text));
+            }
+        }
+    }
+    let mut stripped = render_document(markdown, &originals, &tables);
+
+    // Directives and other mdcalc comments outside the tables.
+    let arena = Arena::new();
+    let mut comments = parse_markdown_for_comments(&arena, &stripped);
+    comments.sort_by_key(|c| c.span.as_ref().map_or(0, |s| s.start));
+    for c in comments.iter().rev() {
+        if let (Some(span), false) = (&c.span, c.comment.kind == CommentKind::Unknown) {
+            stripped.replace_range(span.clone(), "");
+        }
+    }
+
+    let mut options = ComrakOptions::default();
+    options.extension.table = true;
+    options.render.unsafe_ = true;
+    markdown_to_html(&stripped, &options)
+}
+
+/// Escapes text for a double quoted HTML attribute.
+fn attribute(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_html() {
+        let markdown = "\
+<!-- !table: fruit -->
+| Item <!-- !A1 --> | Price | Total <!-- !col=B*2 --> |
+|------|-------|-------|
+| Pears <!-- note --> | 3 | 6 |
+| Sum | | 6 <!-- =IF(C2>0, C2, \"none\") --> |
+";
+        assert_eq!(
+            render_html(markdown),
+            "\
+<table>
+<thead>
+<tr>
+<th>Item</th>
+<th>Price</th>
+<th>Total</th>
+</tr>
+</thead>
+<tbody>
+<tr>
+<td>Pears <!-- note --></td>
+<td>3</td>
+<td><span title=\"=B*2\" data-formula=\"=B*2\">6</span></td>
+</tr>
+<tr>
+<td>Sum</td>
+<td></td>
+<td><span title=\"=IF(C2&gt;0, C2, &quot;none&quot;)\" data-formula=\"=IF(C2&gt;0, C2, &quot;none&quot;)\">6</span></td>
+</tr>
+</tbody>
+</table>
+"
+        );
+    }
+}
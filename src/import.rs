@@ -0,0 +1,572 @@
+// import.rs
+
+use std::io::Cursor;
+
+use calamine::{open_workbook_auto_from_rs, Data, Reader};
+
+use crate::cell_markers::{apply_markers, MarkerMode};
+use crate::dates;
+use crate::directives::{column_formula, table_names};
+use crate::evaluator::format_number;
+use crate::export::ExportFormat;
+use crate::formula::{parse_cell_ref, tokenize, CellRef, TokenKind};
+use crate::functions;
+use crate::sort::find_table;
+use crate::table_parser::{TableCellPiece, TableParser};
+use crate::table_writer::{render_cell, render_grid};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportOptions {
+    pub format: ExportFormat,
+    /// Add markers to the header row and first column.
+    pub markers: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SheetImportOptions {
+    /// The worksheet to read; the first one when empty.
+    pub sheet: String,
+    /// The cells to read, such as `B2:E20`, the first row being the header;
+    /// every used cell of the sheet when empty.
+    pub range: String,
+    /// Add markers to the header row and first column.
+    pub markers: bool,
+}
+
+/// Reads CSV as in RFC 4180 (quoted fields may hold commas, quotes and line
+/// breaks) or TSV. Blank lines are skipped.
+pub fn parse_delimited(data: &str, format: ExportFormat) -> Vec<Vec<String>> {
+    let separator = match format {
+        ExportFormat::Csv => ',',
+        ExportFormat::Tsv => '\t',
+    };
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() && format == ExportFormat::Csv => quoted = true,
+            _ if quoted => field.push(c),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\r' | '\n' => {
+                row.push(std::mem::take(&mut field));
+                if row.len() > 1 || !row[0].is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            _ if c == separator => row.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+/// A field as the text of a Markdown table cell: on one line, with pipes
+/// escaped.
+fn cell_text(field: &str) -> String {
+    field
+        .replace(['\r', '\n'], " ")
+        .replace('|', "\\|")
+        .trim()
+        .to_string()
+}
+
+/// Renders cell texts as an aligned table, with markers in the header row
+/// and first column if asked for.
+fn render_rows(rows: &[Vec<String>], markers: bool) -> String {
+    let text = render_grid(rows);
+    if !markers {
+        return text;
+    }
+    let mut tables = TableParser::parse_tables(&text);
+    let Some(table) = tables.first_mut() else {
+        return text;
+    };
+    apply_markers(table, MarkerMode::OnlyRowAndColumn);
+    let rows: Vec<Vec<String>> = table
+        .rows
+        .iter_mut()
+        .map(|r| {
+            r.cells
+                .iter_mut()
+                .map(|c| {
+                    // Drop the padding so the table can be aligned again.
+                    for piece in c.pieces.iter_mut() {
+                        if let TableCellPiece::Text(text) = piece {
+                            *text = text.trim().to_string();
+                        }
+                    }
+                    render_cell(c).trim().to_string()
+                })
+                .collect()
+        })
+        .collect();
+    render_grid(&rows)
+}
+
+fn data_rows(data: &str, options: &ImportOptions) -> Result<Vec<Vec<String>>, String> {
+    let mut rows: Vec<Vec<String>> = parse_delimited(data, options.format)
+        .iter()
+        .map(|r| r.iter().map(|f| cell_text(f)).collect())
+        .collect();
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    if width == 0 {
+        return Err("no rows to import".to_string());
+    }
+    for row in rows.iter_mut() {
+        row.resize(width, String::new());
+    }
+    Ok(rows)
+}
+
+/// The cells of a worksheet range as table cell texts, formula cells holding
+/// their value and an `<!-- !=... -->` comment, and the functions used by
+/// formulas that mdcalc does not support. Reads xlsx, xls and ods files.
+fn sheet_rows(data: &[u8], options: &SheetImportOptions) -> Result<(Vec<Vec<String>>, Vec<String>), String> {
+    let mut workbook = open_workbook_auto_from_rs(Cursor::new(data)).map_err(|e| e.to_string())?;
+    let names = workbook.sheet_names();
+    let sheet = match options.sheet.as_str() {
+        "" => names.first().cloned().ok_or("the workbook has no sheets")?,
+        name => names
+            .iter()
+            .find(|n| n.eq_ignore_ascii_case(name))
+            .cloned()
+            .ok_or_else(|| format!("no sheet '{}'", name))?,
+    };
+    let values = workbook.worksheet_range(&sheet).map_err(|e| e.to_string())?;
+    let formulas = workbook.worksheet_formula(&sheet).map_err(|e| e.to_string())?;
+
+    let (start, end) = if options.range.is_empty() {
+        // The cells holding either a value or a formula.
+        let corners: Vec<((u32, u32), (u32, u32))> = [
+            values.start().zip(values.end()),
+            formulas.start().zip(formulas.end()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let first = corners.iter().map(|c| c.0).reduce(|a, b| (a.0.min(b.0), a.1.min(b.1)));
+        let last = corners.iter().map(|c| c.1).reduce(|a, b| (a.0.max(b.0), a.1.max(b.1)));
+        first.zip(last).ok_or("no cells to import")?
+    } else {
+        let (first, last) = options.range.split_once(':').unwrap_or((&options.range, &options.range));
+        match (parse_cell_ref(first.trim()), parse_cell_ref(last.trim())) {
+            (Some(a), Some(b)) => (
+                (a.row.min(b.row) as u32, a.col.min(b.col) as u32),
+                (a.row.max(b.row) as u32, a.col.max(b.col) as u32),
+            ),
+            _ => return Err(format!("invalid range '{}'", options.range)),
+        }
+    };
+
+    let mut unsupported = Vec::new();
+    let rows = (start.0..=end.0)
+        .map(|r| {
+            (start.1..=end.1)
+                .map(|c| {
+                    let text = cell_text(&data_text(values.get_value((r, c)).unwrap_or(&Data::Empty)));
+                    match formulas.get_value((r, c)).filter(|f| !f.is_empty()) {
+                        Some(formula) => {
+                            let origin = (start.0 as usize, start.1 as usize);
+                            let formula = mdcalc_formula(formula, &sheet, origin, &mut unsupported);
+                            format!("{} <!-- !={} -->", text, formula).trim().to_string()
+                        }
+                        None => text,
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    unsupported.sort();
+    unsupported.dedup();
+    Ok((rows, unsupported))
+}
+
+fn data_text(data: &Data) -> String {
+    match data {
+        Data::Empty => String::new(),
+        Data::Int(n) => n.to_string(),
+        Data::Float(n) => format_number(*n),
+        Data::String(s) => s.clone(),
+        Data::Bool(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+        Data::DateTime(date) => dates::format_date_default(date.as_f64()),
+        Data::DateTimeIso(s) => s.trim_end_matches("T00:00:00").replace('T', " "),
+        Data::DurationIso(s) => s.clone(),
+        Data::Error(e) => e.to_string(),
+    }
+}
+
+/// Rewrites a spreadsheet formula in mdcalc's syntax: OpenFormula references
+/// (`[.B2:.C3]`, `[$Sheet2.A1]`) and separators become Excel's, the prefixes
+/// of newer functions are dropped, and references are moved so that
+/// `origin`, the top-left cell of the range read, becomes A1. The names of
+/// functions mdcalc does not support are added to `unsupported`.
+fn mdcalc_formula(source: &str, sheet: &str, origin: (usize, usize), unsupported: &mut Vec<String>) -> String {
+    let source = source.trim();
+    let (source, ods) = match source.strip_prefix("of:") {
+        Some(rest) => (rest, true),
+        None => (source, false),
+    };
+    let source = source.strip_prefix('=').unwrap_or(source);
+
+    // Rewrite everything outside string literals.
+    let mut text = String::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                text.push(c);
+                while let Some(c) = chars.next() {
+                    text.push(c);
+                    match (c, chars.peek()) {
+                        ('"', Some('"')) => text.extend(chars.next()),
+                        ('"', _) => break,
+                        _ => {}
+                    }
+                }
+            }
+            '[' if ods => {
+                let mut reference = String::new();
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    reference.push(c);
+                }
+                text.push_str(&ods_reference(&reference));
+            }
+            '\'' => {
+                // A quoted sheet name, `'Sheet 1'!A1`.
+                let mut name = String::new();
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.peek() == Some(&'\'') {
+                        chars.next();
+                    } else if c == '\'' {
+                        break;
+                    }
+                    name.push(c);
+                }
+                if chars.next_if_eq(&'!').is_some() {
+                    // References into the imported sheet lose their prefix.
+                    if !name.eq_ignore_ascii_case(sheet) {
+                        text.push_str(&sheet_prefix(&name));
+                    }
+                } else {
+                    text.push_str(&format!("'{}'", name));
+                }
+            }
+            ';' if ods => text.push(','),
+            '|' if ods => text.push(';'),
+            _ => text.push(c),
+        }
+    }
+    for prefix in ["_xlfn._xlws.", "_xlfn.", "_xlws.", "COM.MICROSOFT."] {
+        text = text.replace(prefix, "");
+    }
+
+    let Ok(tokens) = tokenize(&text) else {
+        return text;
+    };
+    let mut out = String::new();
+    let mut last = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        let TokenKind::Word(word) = &token.kind else {
+            continue;
+        };
+        let next = tokens.get(i + 1).map(|t| &t.kind);
+        if next == Some(&TokenKind::Bang) && word.eq_ignore_ascii_case(sheet) {
+            out.push_str(&text[last..token.start]);
+            last = tokens[i + 1].end;
+            continue;
+        }
+        let replacement = if next == Some(&TokenKind::LParen) {
+            let name = word.to_ascii_uppercase();
+            match name.as_str() {
+                // OpenFormula writes the constants as functions.
+                "TRUE" | "FALSE" if tokens.get(i + 2).map(|t| &t.kind) == Some(&TokenKind::RParen) => {
+                    out.push_str(&text[last..token.start]);
+                    out.push_str(&name);
+                    last = tokens[i + 2].end;
+                    continue;
+                }
+                _ if !functions::is_supported(&name) => unsupported.push(name),
+                _ => {}
+            }
+            None
+        } else if i > 1 && tokens[i - 1].kind == TokenKind::Bang
+            && !matches!(&tokens[i - 2].kind, TokenKind::Word(name) if name.eq_ignore_ascii_case(sheet))
+        {
+            // Other sheets keep their own coordinates.
+            None
+        } else {
+            parse_cell_ref(word).map(|cell| match (cell.row.checked_sub(origin.0), cell.col.checked_sub(origin.1)) {
+                (Some(row), Some(col)) => CellRef { row, col, ..cell }.to_string(),
+                _ => "#REF!".to_string(),
+            })
+        };
+        if let Some(replacement) = replacement {
+            out.push_str(&text[last..token.start]);
+            out.push_str(&replacement);
+            last = token.end;
+        }
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// An OpenFormula reference without its brackets, such as `.B2:.C3` or
+/// `$'Sheet 2'.A1`, in Excel's syntax.
+fn ods_reference(reference: &str) -> String {
+    let mut sheet = None;
+    let parts: Vec<String> = reference
+        .split(':')
+        .map(|part| {
+            let part = part.trim_start_matches('$');
+            let (name, cell) = match part.strip_prefix('\'') {
+                Some(quoted) => match quoted.split_once("'.") {
+                    Some((name, cell)) => (name.replace("''", "'"), cell),
+                    None => (String::new(), part),
+                },
+                None => match part.rsplit_once('.') {
+                    Some((name, cell)) => (name.to_string(), cell),
+                    None => (String::new(), part),
+                },
+            };
+            if !name.is_empty() && sheet.is_none() {
+                sheet = Some(name);
+            }
+            cell.to_string()
+        })
+        .collect();
+    match sheet {
+        Some(name) => format!("{}{}", sheet_prefix(&name), parts.join(":")),
+        None => parts.join(":"),
+    }
+}
+
+/// `Name!` for a reference to another sheet, quoted if the name is not a
+/// plain word.
+fn sheet_prefix(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        format!("{}!", name)
+    } else {
+        format!("'{}'!", name.replace('\'', "''"))
+    }
+}
+
+/// A Markdown table holding the rows of CSV or TSV data, the first row being
+/// the header.
+pub fn import_table(data: &str, options: &ImportOptions) -> Result<String, String> {
+    Ok(render_rows(&data_rows(data, options)?, options.markers))
+}
+
+/// Replaces the body of a table in `markdown` with the rows of CSV or TSV
+/// data. Columns of the old table computed by a `!col=` formula are kept at
+/// their position, with their header, in place of any data column of the
+/// same name.
+pub fn replace_table(markdown: &str, table: &str, data: &str, options: &ImportOptions) -> Result<String, String> {
+    replace_rows(markdown, table, data_rows(data, options)?, options.markers)
+}
+
+/// A Markdown table holding a range of a worksheet, the first row being the
+/// header, and the functions its formulas use that mdcalc does not support.
+pub fn import_sheet(data: &[u8], options: &SheetImportOptions) -> Result<(String, Vec<String>), String> {
+    let (rows, unsupported) = sheet_rows(data, options)?;
+    Ok((render_rows(&rows, options.markers), unsupported))
+}
+
+/// Like `replace_table`, with the rows read from a worksheet range.
+pub fn replace_table_from_sheet(
+    markdown: &str,
+    table: &str,
+    data: &[u8],
+    options: &SheetImportOptions,
+) -> Result<(String, Vec<String>), String> {
+    let (rows, unsupported) = sheet_rows(data, options)?;
+    Ok((replace_rows(markdown, table, rows, options.markers)?, unsupported))
+}
+
+fn replace_rows(markdown: &str, table: &str, mut rows: Vec<Vec<String>>, markers: bool) -> Result<String, String> {
+    let tables = TableParser::parse_tables(markdown);
+    let names = table_names(markdown, &tables);
+    let index = find_table(table, &names).ok_or_else(|| format!("no table '{}'", table))?;
+    let old = &tables[index];
+
+    for (col, cell) in old.rows[0].cells.iter().enumerate() {
+        if column_formula(cell).is_none() {
+            continue;
+        }
+        let name = cell.text_content().trim().to_string();
+        if let Some(duplicate) = rows[0].iter().position(|h| h.eq_ignore_ascii_case(&name)) {
+            for row in rows.iter_mut() {
+                row.remove(duplicate);
+            }
+        }
+        let at = col.min(rows[0].len());
+        for (r, row) in rows.iter_mut().enumerate() {
+            let text = if r == 0 { render_cell(cell).trim().to_string() } else { String::new() };
+            row.insert(at, text);
+        }
+    }
+
+    Ok(format!(
+        "{}{}{}",
+        &markdown[..old.start_offset],
+        render_rows(&rows, markers),
+        &markdown[old.end_offset..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recalc::{recalculate_markdown, RecalcOptions};
+
+    const CSV: ImportOptions = ImportOptions {
+        format: ExportFormat::Csv,
+        markers: false,
+    };
+
+    #[test]
+    fn test_parse_delimited() {
+        let data = "Item,Note\r\n\"Apples, red\",\"said \"\"hi\"\"\nthen left\"\r\n\r\nPears,\n";
+        assert_eq!(
+            parse_delimited(data, ExportFormat::Csv),
+            vec![
+                vec!["Item".to_string(), "Note".to_string()],
+                vec!["Apples, red".to_string(), "said \"hi\"\nthen left".to_string()],
+                vec!["Pears".to_string(), String::new()],
+            ]
+        );
+        assert_eq!(
+            parse_delimited("a\t\"b\"\n1\t2", ExportFormat::Tsv),
+            vec![vec!["a".to_string(), "\"b\"".to_string()], vec!["1".to_string(), "2".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_import_table() {
+        let data = "Item,Price\nApples,2\nPipe | fruit,10\nLemons\n";
+        assert_eq!(
+            import_table(data, &CSV).unwrap(),
+            "\
+| Item          | Price |
+| ------------- | ----- |
+| Apples        | 2     |
+| Pipe \\| fruit | 10    |
+| Lemons        |       |"
+        );
+        assert_eq!(
+            import_table("Item,Price\nApples,2\n", &ImportOptions { markers: true, ..CSV }).unwrap(),
+            "\
+| Item <!-- !A1 -->   | Price <!-- !B1 --> |
+| ------------------- | ------------------ |
+| Apples <!-- !A2 --> | 2                  |"
+        );
+        assert!(import_table("\n\n", &CSV).is_err());
+    }
+
+    #[test]
+    fn test_replace_table_keeps_formula_columns() {
+        let markdown = "\
+Prices:
+
+<!-- !table: fruit -->
+| Item | Price | Quantity | Total <!-- !col=B*C --> |
+|------|-------|----------|-------------------------|
+| Apples | 2 | 3 | 6 |
+
+Done.
+";
+        let data = "Item,Price,Quantity,Total\nPears,3,2,999\nPlums,1,4,0\n";
+        let replaced = replace_table(markdown, "fruit", data, &CSV).unwrap();
+        assert_eq!(
+            recalculate_markdown(&replaced, &RecalcOptions::default()),
+            "\
+Prices:
+
+<!-- !table: fruit -->
+| Item  | Price | Quantity | Total <!-- !col=B*C --> |
+| ----- | ----- | -------- | ----------------------- |
+| Pears | 3     | 2        | 6                       |
+| Plums | 1     | 4        | 4                       |
+
+Done.
+"
+        );
+        assert!(replace_table(markdown, "veg", data, &CSV).is_err());
+    }
+
+    #[test]
+    fn test_mdcalc_formula() {
+        let mut unsupported = Vec::new();
+        let mut convert = |source: &str, origin: (usize, usize)| mdcalc_formula(source, "Data", origin, &mut unsupported);
+        assert_eq!(convert("SUM(B2:B3)*2", (0, 0)), "SUM(B2:B3)*2");
+        assert_eq!(convert("_xlfn.MAXIFS($C$3:$C$9,D3:D9,\">1\")", (1, 1)), "MAXIFS($B$2:$B$8,C2:C8,\">1\")");
+        assert_eq!(convert("A1+B2", (1, 0)), "#REF!+B1");
+        assert_eq!(convert("'Old data'!B2&\"'x'!\"", (1, 1)), "'Old data'!B2&\"'x'!\"");
+        // References into the imported sheet itself move with the range.
+        assert_eq!(convert("Data!C3+data!$B$2+'Data'!B3", (1, 1)), "B2+$A$1+A2");
+        assert_eq!(convert("of:=[$Data.C3]+[.B2]", (1, 1)), "B2+A1");
+        assert_eq!(convert("of:=IF([.B2]>[$'fruit'.D4];TRUE();\"a;b\")", (0, 0)), "IF(B2>fruit!D4,TRUE,\"a;b\")");
+        assert_eq!(convert("of:=COM.MICROSOFT.SEQUENCE(2)+SUM({1;2|3;4})+INDIRECT(\"A1\")", (0, 0)),
+            "SEQUENCE(2)+SUM({1,2;3,4})+INDIRECT(\"A1\")");
+        assert_eq!(convert("cube(A2)+Indirect(A3)", (0, 0)), "cube(A2)+Indirect(A3)");
+        assert_eq!(unsupported, vec!["INDIRECT", "CUBE", "INDIRECT"]);
+    }
+
+    #[test]
+    fn test_import_sheet_round_trip() {
+        use crate::spreadsheet::{export_workbook, SpreadsheetFormat};
+
+        let markdown = "\
+| Notes |
+|-------|
+| unrelated |
+
+<!-- !table: fruit -->
+| Item | Price | Qty | Total <!-- !col=B*C --> | Bought |
+|------|-------|-----|-------|--------|
+| Pears | 3 | 2 | 6 | 2026-01-05 |
+| Plums | 1.5 | 4 | 6 | TRUE |
+| Sum | | | 12 <!-- =SUM(D:above) --> | <!-- =MAXIFS(D2:D3, B2:B3, \">1\") + FOO(1) --> |
+";
+        for format in [SpreadsheetFormat::Xlsx, SpreadsheetFormat::Ods] {
+            let book = export_workbook(markdown, format, 0.0).unwrap();
+            let options = SheetImportOptions { sheet: "fruit".to_string(), ..Default::default() };
+            let (table, unsupported) = import_sheet(&book, &options).unwrap();
+            assert_eq!(
+                table,
+                "\
+| Item  | Price | Qty | Total                     | Bought                                            |
+| ----- | ----- | --- | ------------------------- | ------------------------------------------------- |
+| Pears | 3     | 2   | 6 <!-- !=B2*C2 -->        | 2026-01-05                                        |
+| Plums | 1.5   | 4   | 6 <!-- !=B3*C3 -->        | TRUE                                              |
+| Sum   |       |     | 12 <!-- !=SUM(D$2:D3) --> | #NAME? <!-- !=MAXIFS(D2:D3,B2:B3,\">1\")+FOO(1) --> |",
+                "{:?}",
+                format
+            );
+            assert_eq!(unsupported, vec!["FOO"]);
+
+            let options = SheetImportOptions { sheet: "FRUIT".to_string(), range: "B2:D4".to_string(), markers: true };
+            let (table, _) = import_sheet(&book, &options).unwrap();
+            assert!(table.starts_with("| 3 <!-- !A1 -->   | 2 <!-- !B1 --> | 6 <!-- !=A1*B1 --> <!-- !C1 --> |"), "{}", table);
+
+            let missing = SheetImportOptions { sheet: "veg".to_string(), ..Default::default() };
+            assert!(import_sheet(&book, &missing).is_err());
+        }
+        assert!(import_sheet(b"not a spreadsheet", &SheetImportOptions::default()).is_err());
+    }
+}
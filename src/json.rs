@@ -0,0 +1,148 @@
+// json.rs
+
+use serde_json::{json, Value as Json};
+
+use crate::dates;
+use crate::directives::table_names;
+use crate::evaluator::{Value, Workbook};
+use crate::formula::column_name;
+use crate::md_comments::{CommentKind, LocatedHtmlComment};
+use crate::table_parser::TableParser;
+use crate::table_writer::render_cell;
+
+/// Describes every table of `markdown` as JSON: for each cell its address,
+/// source text, comments, formula and the value it computes to with
+/// `TODAY()` returning `today`. Spans are byte ranges into `markdown`.
+///
+/// ```json
+/// {"tables":[{"index":1,"name":"Table1","span":{"start":0,"end":35},"rows":[[
+///   {"address":"A1","row":0,"col":0,"raw":"a <!-- !A1 -->","text":"a",
+///    "formula":null,"column_formula":false,"type":"text","value":"a",
+///    "error":null,"comments":[{"kind":"marker","content":"!A1","span":{"start":4,"end":16}}]},
+///   ...]]}]}
+/// ```
+pub fn document_json(markdown: &str, today: f64) -> String {
+    let tables = TableParser::parse_tables(markdown);
+    let names = table_names(markdown, &tables);
+    let workbook = Workbook::from_tables(&tables, &names);
+    let results = workbook.evaluate(today);
+
+    let tables: Vec<Json> = tables
+        .iter()
+        .zip(&workbook.sheets)
+        .zip(&results)
+        .enumerate()
+        .map(|(index, ((table, sheet), sheet_results))| {
+            let rows: Vec<Json> = table
+                .rows
+                .iter()
+                .enumerate()
+                .map(|(r, row)| {
+                    let cells: Vec<Json> = row
+                        .cells
+                        .iter()
+                        .enumerate()
+                        .map(|(c, cell)| {
+                            let sheet_cell = sheet.cell(r, c).cloned().unwrap_or_default();
+                            let value = sheet_results
+                                .get(r)
+                                .and_then(|row| row.get(c))
+                                .cloned()
+                                .flatten()
+                                .unwrap_or_else(|| Value::from_cell_text(&sheet_cell.text));
+                            let (kind, value, error) = value_fields(&value);
+                            json!({
+                                "address": format!("{}{}", column_name(c), r + 1),
+                                "row": r,
+                                "col": c,
+                                "raw": render_cell(cell).trim(),
+                                "text": sheet_cell.text,
+                                "formula": sheet_cell.formula,
+                                "column_formula": sheet_cell.column_formula,
+                                "type": kind,
+                                "value": value,
+                                "error": error,
+                                "comments": cell.comments().into_iter().map(comment).collect::<Vec<_>>(),
+                            })
+                        })
+                        .collect();
+                    Json::Array(cells)
+                })
+                .collect();
+            json!({
+                "index": index + 1,
+                "name": sheet.name,
+                "span": {"start": table.start_offset, "end": table.end_offset},
+                "rows": rows,
+            })
+        })
+        .collect();
+    format!("{}\n", json!({ "tables": tables }))
+}
+
+/// The `type`, `value` and `error` fields of a computed value. Dates are
+/// written the way cells show them by default; whole numbers as integers.
+fn value_fields(value: &Value) -> (&'static str, Json, Option<String>) {
+    match value.clone().scalar() {
+        Value::Empty => ("empty", Json::Null, None),
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => ("number", json!(n as i64), None),
+        Value::Number(n) => ("number", json!(n), None),
+        Value::Text(s) => ("text", json!(s), None),
+        Value::Bool(b) => ("bool", json!(b), None),
+        Value::Date(serial) => ("date", json!(dates::format_date_default(serial)), None),
+        Value::Error(e) => ("error", Json::Null, Some(e.to_string())),
+        Value::Array(_) => unreachable!("scalar() never returns an array"),
+    }
+}
+
+fn comment(c: &LocatedHtmlComment) -> Json {
+    json!({
+        "kind": kind_name(&c.comment.kind),
+        "content": c.comment.content.trim(),
+        "span": c.span.as_ref().map(|span| json!({"start": span.start, "end": span.end})),
+    })
+}
+
+fn kind_name(kind: &CommentKind) -> &'static str {
+    match kind {
+        CommentKind::Formula => "formula",
+        CommentKind::Marker => "marker",
+        CommentKind::Directive => "directive",
+        CommentKind::Spill => "spill",
+        CommentKind::Generated => "generated",
+        CommentKind::Formatting => "formatting",
+        CommentKind::Unknown => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_json() {
+        let markdown = "\
+Intro.
+
+| Item <!-- !A1 --> | Price |
+|---|---|
+| \"Tea\" | 2 |
+| Total | <!-- =SUM(B2)/0 --> |
+";
+        let json = document_json(markdown, 0.0);
+        assert!(json.starts_with("{\"tables\":[{\"index\":1,\"name\":\"Table1\",\"span\":{\"start\":8,\"end\":93},\"rows\":[["));
+        assert!(json.contains(
+            "{\"address\":\"A1\",\"row\":0,\"col\":0,\"raw\":\"Item <!-- !A1 -->\",\"text\":\"Item\",\"formula\":null,\
+\"column_formula\":false,\"type\":\"text\",\"value\":\"Item\",\"error\":null,\
+\"comments\":[{\"kind\":\"marker\",\"content\":\"!A1\",\"span\":{\"start\":15,\"end\":27}}]}"
+        ));
+        assert!(json.contains("\"address\":\"A2\",\"row\":1,\"col\":0,\"raw\":\"\\\"Tea\\\"\""));
+        assert!(json.contains("\"address\":\"B2\",\"row\":1,\"col\":1,\"raw\":\"2\",\"text\":\"2\",\"formula\":null,\"column_formula\":false,\"type\":\"number\",\"value\":2,"));
+        assert!(json.contains(
+            "\"formula\":\"SUM(B2)/0\",\"column_formula\":false,\"type\":\"error\",\"value\":null,\"error\":\"#DIV/0!\",\
+\"comments\":[{\"kind\":\"formula\",\"content\":\"=SUM(B2)/0\",\"span\":{\"start\":72,\"end\":91}}]}"
+        ));
+        assert_eq!(&markdown[15..27], "<!-- !A1 -->");
+        assert_eq!(&markdown[72..91], "<!-- =SUM(B2)/0 -->");
+    }
+}
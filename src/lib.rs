@@ -0,0 +1,44 @@
+//! Spreadsheet formulas in Markdown tables. [`Document`] is the entry point
+//! for using mdcalc from other programs.
+
+mod md_comments;
+mod table_parser;
+mod comment_stripper;
+mod arrays;
+mod cell_markers;
+mod dates;
+mod directives;
+mod document;
+mod evaluator;
+mod export;
+mod finance;
+mod fix_refs;
+mod formula;
+mod functions;
+mod generators;
+mod html;
+mod import;
+mod json;
+mod lsp;
+mod recalc;
+mod sort;
+mod spreadsheet;
+mod statistics;
+mod table_writer;
+mod watch;
+
+#[allow(deprecated)]
+pub use crate::cell_markers::{apply_marker_mode, apply_markers, make_html_comment_node, MarkerMode};
+pub use crate::dates::parse_date;
+pub use crate::document::{Cell, Document, Table};
+pub use crate::export::{ExportFormat, ExportOptions};
+pub use crate::import::{ImportOptions, SheetImportOptions};
+pub use crate::lsp::run_language_server;
+pub use crate::recalc::RecalcOptions;
+pub use crate::sort::SortOptions;
+pub use crate::spreadsheet::SpreadsheetFormat;
+pub use crate::watch::{WatchOptions, Watcher};
+
+// The owned table model returned by `Document::markdown_tables`.
+pub use crate::md_comments::{CommentKind, HtmlComment, LocatedHtmlComment};
+pub use crate::table_parser::{MarkdownTable, TableCell, TableCellPiece, TableRow};
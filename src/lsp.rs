@@ -0,0 +1,826 @@
+// lsp.rs
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::ops::Range;
+
+use serde_json::{json, Value as Json};
+
+use crate::cell_markers::{apply_markers, MarkerMode};
+use crate::dates;
+use crate::directives::{column_formula, parse_directives, table_names, Directive};
+use crate::evaluator::{format_value, CellAddr, Value, Workbook};
+use crate::formula::{
+    column_index, column_name, formula_source, parse_cell_ref, parse_column_formula, parse_formula_at,
+    parse_relative_ref, tokenize, Expr, Reference, TokenKind,
+};
+use crate::functions;
+use crate::md_comments::{CommentKind, LocatedHtmlComment};
+use crate::recalc::{recalculate_tables, RecalcOptions};
+use crate::table_parser::{is_delimiter_row, is_table_line, MarkdownTable, TableParser};
+use crate::table_writer::{render_table, source_cells};
+
+/// How deep the dependency chain shown on hover goes, and how many lines
+/// it may take.
+const MAX_DEPTH: usize = 6;
+const MAX_DEPENDENCIES: usize = 40;
+
+/// Largest message body accepted, far above any Markdown document, so that
+/// a bad `Content-Length` gives an error instead of a huge allocation.
+const MAX_MESSAGE: usize = 64 << 20;
+
+/// Runs a language server speaking LSP (JSON-RPC with `Content-Length`
+/// headers) over `input` and `output` until the client sends `exit`.
+/// Documents are synced in full. The server publishes diagnostics for
+/// broken formulas, and answers hover, go-to-definition, code action and
+/// inlay hint requests. `TODAY()` returns `options.today` if set.
+pub fn run_language_server(mut input: impl BufRead, mut output: impl Write, options: &RecalcOptions) -> Result<(), String> {
+    let mut server = Server {
+        documents: HashMap::new(),
+        today: options.today,
+    };
+    while let Some(message) = read_message(&mut input)? {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        match message.get("id") {
+            // Responses to requests of ours; the server sends none.
+            Some(_) if method.is_empty() => {}
+            Some(id) => {
+                let response = match server.request(method, params) {
+                    Some(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": -32601, "message": format!("unsupported method '{}'", method)},
+                    }),
+                };
+                write_message(&mut output, &response)?;
+            }
+            None if method == "exit" => return Ok(()),
+            None => {
+                for notification in server.notify(method, params) {
+                    write_message(&mut output, &notification)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_message(input: &mut impl BufRead) -> Result<Option<Json>, String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|e| format!("stdin: {}", e))? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                let value = value.trim();
+                length = Some(value.parse::<usize>().map_err(|_| format!("invalid Content-Length '{}'", value))?);
+            }
+        }
+    }
+    let length = length.unwrap_or_default();
+    if length > MAX_MESSAGE {
+        return Err(format!("Content-Length {} is over the limit of {} bytes", length, MAX_MESSAGE));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| format!("stdin: {}", e))?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| format!("invalid message: {}", e))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| format!("stdout: {}", e))
+}
+
+struct Server {
+    /// Open documents by URI.
+    documents: HashMap<String, String>,
+    today: Option<f64>,
+}
+
+impl Server {
+    /// Answers a request, or `None` for methods the server does not know.
+    fn request(&mut self, method: &str, params: &Json) -> Option<Json> {
+        if let "initialize" | "shutdown" = method {
+            return Some(match method {
+                "initialize" => json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "codeActionProvider": true,
+                        "inlayHintProvider": true,
+                    },
+                    "serverInfo": {"name": "mdcalc", "version": env!("CARGO_PKG_VERSION")},
+                }),
+                _ => Json::Null,
+            });
+        }
+        if !matches!(
+            method,
+            "textDocument/hover" | "textDocument/definition" | "textDocument/codeAction" | "textDocument/inlayHint"
+        ) {
+            return None;
+        }
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let Some(text) = self.documents.get(uri) else {
+            return Some(Json::Null);
+        };
+        let analysis = Analysis::new(text, self.today.unwrap_or_else(dates::today));
+        Some(match method {
+            "textDocument/hover" => offset(text, &params["position"]).and_then(|at| analysis.hover(at)),
+            "textDocument/definition" => offset(text, &params["position"]).and_then(|at| analysis.definition(uri, at)),
+            "textDocument/codeAction" => offset(text, &params["range"]["start"]).map(|at| analysis.code_actions(uri, at)),
+            _ => {
+                let start = offset(text, &params["range"]["start"]).unwrap_or(0);
+                let end = offset(text, &params["range"]["end"]).unwrap_or(text.len());
+                Some(analysis.inlay_hints(start..end))
+            }
+        }
+        .unwrap_or(Json::Null))
+    }
+
+    /// Handles a notification, returning the notifications to send back.
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // Full sync: the last change holds the whole text.
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => None,
+        };
+        let Some(text) = text else {
+            return Vec::new();
+        };
+        let diagnostics = Analysis::new(text, self.today.unwrap_or_else(dates::today)).diagnostics();
+        self.documents.insert(uri.clone(), text.to_string());
+        vec![publish_diagnostics(&uri, diagnostics)]
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    })
+}
+
+/// A document as the server sees it: its tables, where their cells are and
+/// what every cell computes to.
+struct Analysis<'a> {
+    markdown: &'a str,
+    tables: Vec<MarkdownTable>,
+    names: Vec<String>,
+    workbook: Workbook,
+    results: Vec<Vec<Vec<Option<Value>>>>,
+    /// Byte range of every cell, laid out like the rows of `tables`.
+    cells: Vec<Vec<Vec<Range<usize>>>>,
+    today: f64,
+}
+
+impl<'a> Analysis<'a> {
+    fn new(markdown: &'a str, today: f64) -> Analysis<'a> {
+        let tables = TableParser::parse_tables(markdown);
+        let names = table_names(markdown, &tables);
+        let workbook = Workbook::from_tables(&tables, &names);
+        let results = workbook.evaluate(today);
+        let cells = tables.iter().map(|table| cell_spans(markdown, table)).collect();
+        Analysis {
+            markdown,
+            tables,
+            names,
+            workbook,
+            results,
+            cells,
+            today,
+        }
+    }
+
+    /// Errors for formulas that do not parse or use unknown functions, and
+    /// warnings for formulas that evaluate to an error. Column formulas are
+    /// checked once, on their header.
+    fn diagnostics(&self) -> Vec<Json> {
+        let mut diagnostics = Vec::new();
+        let mut columns: HashMap<(usize, usize), bool> = HashMap::new();
+        for (t, sheet) in self.workbook.sheets.iter().enumerate() {
+            for (r, row) in sheet.cells.iter().enumerate() {
+                for (c, cell) in row.iter().enumerate() {
+                    let Some(source) = &cell.formula else { continue };
+                    let broken = if cell.column_formula {
+                        *columns.entry((t, c)).or_insert_with(|| {
+                            let problems = problems(source, true, r, c);
+                            let span = self.column_formula_comment(t, c).and_then(|comment| comment.span.clone());
+                            for message in &problems {
+                                diagnostics.push(self.diagnostic(span.clone().or(self.cell_span(t, 0, c)), 1, message));
+                            }
+                            !problems.is_empty()
+                        })
+                    } else {
+                        let problems = problems(source, false, r, c);
+                        for message in &problems {
+                            diagnostics.push(self.diagnostic(self.formula_span(t, r, c), 1, message));
+                        }
+                        !problems.is_empty()
+                    };
+                    if let (false, Value::Error(e)) = (broken, self.value(CellAddr { sheet: t, row: r, col: c })) {
+                        let message = format!("evaluates to {}", e);
+                        diagnostics.push(self.diagnostic(self.formula_span(t, r, c), 2, &message));
+                    }
+                }
+            }
+        }
+        diagnostics
+    }
+
+    fn diagnostic(&self, span: Option<Range<usize>>, severity: u8, message: &str) -> Json {
+        let span = span.unwrap_or(0..0);
+        json!({
+            "range": range(self.markdown, &span),
+            "severity": severity,
+            "source": "mdcalc",
+            "message": message,
+        })
+    }
+
+    /// The value of the cell at `offset`, its formula and the cells it
+    /// depends on, following formulas down to plain values.
+    fn hover(&self, offset: usize) -> Option<Json> {
+        let (t, r, c) = self.cell_at(offset)?;
+        let addr = CellAddr { sheet: t, row: r, col: c };
+        let mut parts = vec![format!("`{}!{}{}` = `{}`", self.names[t], column_name(c), r + 1, self.shown(addr))];
+        if let Some(cell) = self.workbook.sheets[t].cell(r, c).filter(|cell| cell.formula.is_some()) {
+            let origin = if cell.column_formula { " (column formula)" } else { "" };
+            parts.push(format!("`={}`{}", cell.formula.as_deref().unwrap_or_default(), origin));
+            let mut lines = Vec::new();
+            if !self.dependencies(addr, 0, &mut HashSet::from([addr]), &mut lines) {
+                lines.push("- …".to_string());
+            }
+            if !lines.is_empty() {
+                parts.push(format!("Depends on:\n{}", lines.join("\n")));
+            }
+        }
+        Some(json!({
+            "contents": {"kind": "markdown", "value": parts.join("\n\n")},
+            "range": range(self.markdown, &self.cells[t][r][c]),
+        }))
+    }
+
+    /// Lists the references of the formula at `addr` and, below each, what
+    /// they depend on in turn. Returns `false` once `MAX_DEPENDENCIES` lines
+    /// are listed, with the rest of the walk left out.
+    fn dependencies(&self, addr: CellAddr, depth: usize, seen: &mut HashSet<CellAddr>, lines: &mut Vec<String>) -> bool {
+        let Some(expr) = self.expr(addr) else { return true };
+        let mut refs = Vec::new();
+        references(&expr, &mut refs);
+        for (table, reference) in refs {
+            if lines.len() >= MAX_DEPENDENCIES {
+                return false;
+            }
+            let indent = "  ".repeat(depth);
+            let label = reference_label(table.as_deref(), &reference);
+            let Some((sheet, cells)) = self.resolve(table.as_deref(), &reference, addr.sheet) else {
+                lines.push(format!("{}- `{}` = `#REF!`", indent, label));
+                continue;
+            };
+            let single = matches!(reference, Reference::Cell(_) | Reference::Structured { row: Some(_), .. });
+            if let (true, [(row, col)]) = (single, cells.as_slice()) {
+                let dependency = CellAddr { sheet, row: *row, col: *col };
+                lines.push(format!("{}- `{}` = `{}`", indent, label, self.shown(dependency)));
+                if depth + 1 < MAX_DEPTH && seen.insert(dependency) && !self.dependencies(dependency, depth + 1, seen, lines) {
+                    return false;
+                }
+                continue;
+            }
+            // A range lists the formula cells in it.
+            lines.push(format!("{}- `{}`", indent, label));
+            for (row, col) in cells {
+                let dependency = CellAddr { sheet, row, col };
+                if depth + 2 >= MAX_DEPTH || self.expr(dependency).is_none() || !seen.insert(dependency) {
+                    continue;
+                }
+                if lines.len() >= MAX_DEPENDENCIES {
+                    return false;
+                }
+                let prefix = if sheet == addr.sheet { String::new() } else { format!("{}!", self.names[sheet]) };
+                lines.push(format!(
+                    "{}  - `{}{}{}` = `{}`",
+                    indent,
+                    prefix,
+                    column_name(col),
+                    row + 1,
+                    self.shown(dependency)
+                ));
+                if !self.dependencies(dependency, depth + 2, seen, lines) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// The cell that the reference under `offset` in a formula points at.
+    fn definition(&self, uri: &str, offset: usize) -> Option<Json> {
+        let (t, r, c) = self.cell_at(offset)?;
+        let (comment, source, column) = self.formula_comments(t, r, c).into_iter().find(|(comment, _, _)| {
+            comment.span.as_ref().is_some_and(|span| span.start <= offset && offset < span.end)
+        })?;
+        let span = comment.span.as_ref()?;
+        let content = &comment.comment.content;
+        let source_start = span.start + "<!--".len() + content.find(source.as_str())?;
+        let at = offset.checked_sub(source_start)?;
+        let tokens = tokenize(&source).ok()?;
+        let i = tokens.iter().position(|token| token.start <= at && at <= token.end)?;
+        let kind = |i: Option<usize>| i.and_then(|i| tokens.get(i)).map(|token| &token.kind);
+
+        // A table name before `!` in `rates!A2`, or before `[` in `fruit[Total]`.
+        let table = match (kind(i.checked_sub(2)), kind(i.checked_sub(1))) {
+            (Some(TokenKind::Word(name)), Some(TokenKind::Bang)) => Some(name.as_str()),
+            (Some(_) | None, Some(TokenKind::Word(name)))
+                if matches!(tokens[i].kind, TokenKind::Structured(_)) && tokens[i - 1].end == tokens[i].start =>
+            {
+                Some(name.as_str())
+            }
+            _ => None,
+        };
+        let sheet = match table {
+            Some(name) => self.workbook.sheet_index(name)?,
+            None => t,
+        };
+        let (sheet, row, col) = match &tokens[i].kind {
+            TokenKind::Structured(name) => {
+                let col = self.workbook.sheets[sheet].column_by_name(name.trim_start_matches('@'))?;
+                (sheet, if name.starts_with('@') { r } else { 0 }, col)
+            }
+            TokenKind::Word(name) => match kind(Some(i + 1)) {
+                Some(TokenKind::LParen) => return None,
+                // A table name goes to the table.
+                Some(TokenKind::Bang) => (self.workbook.sheet_index(name)?, 0, 0),
+                Some(TokenKind::Structured(_)) if tokens[i].end == tokens[i + 1].start => {
+                    (self.workbook.sheet_index(name)?, 0, 0)
+                }
+                _ => {
+                    let (row, col) = parse_relative_ref(name, (r, c))
+                        .or_else(|| parse_cell_ref(name))
+                        .map(|cell| (cell.row, cell.col))
+                        .or_else(|| {
+                            // A bare column: the same row in column formulas,
+                            // the header in whole-column ranges such as `B:B`.
+                            let col = column_index(name.trim_start_matches('$'))?;
+                            Some((if column { r } else { 0 }, col))
+                        })?;
+                    (sheet, row, col)
+                }
+            },
+            _ => return None,
+        };
+        let span = self.cell_span(sheet, row, col)?;
+        Some(json!({"uri": uri, "range": range(self.markdown, &span)}))
+    }
+
+    /// "Recalculate table" and "Insert markers" actions for the table at
+    /// `offset`, each replacing the table with its new text.
+    fn code_actions(&self, uri: &str, offset: usize) -> Json {
+        let Some(t) = self
+            .tables
+            .iter()
+            .position(|table| table.start_offset <= offset && offset <= table.end_offset)
+        else {
+            return json!([]);
+        };
+        let original = &self.tables[t];
+
+        let mut recalculated = self.tables.clone();
+        recalculate_tables(&mut recalculated, &self.names, self.today);
+        let mut actions = vec![("Recalculate table", recalculated.swap_remove(t))];
+        for (title, mode) in [
+            ("Insert markers in the header row and first column", MarkerMode::OnlyRowAndColumn),
+            ("Insert markers in every cell", MarkerMode::AllMarkers),
+        ] {
+            let mut table = original.clone();
+            apply_markers(&mut table, mode);
+            actions.push((title, table));
+        }
+
+        let span = original.start_offset..original.end_offset;
+        let actions: Vec<Json> = actions
+            .into_iter()
+            .map(|(title, table)| (title, render_table(self.markdown, original, &table)))
+            .filter(|(_, text)| *text != self.markdown[span.clone()])
+            .map(|(title, text)| {
+                json!({
+                    "title": title,
+                    "kind": "refactor.rewrite",
+                    "edit": {"changes": {uri: [{"range": range(self.markdown, &span), "newText": text}]}},
+                })
+            })
+            .collect();
+        json!(actions)
+    }
+
+    /// The computed value of every formula cell in `within` whose text does
+    /// not show it yet, after its formula comment.
+    fn inlay_hints(&self, within: Range<usize>) -> Json {
+        let mut hints = Vec::new();
+        for (t, sheet) in self.workbook.sheets.iter().enumerate() {
+            for (r, row) in sheet.cells.iter().enumerate() {
+                for (c, cell) in row.iter().enumerate() {
+                    let addr = CellAddr { sheet: t, row: r, col: c };
+                    let shown = self.shown(addr);
+                    if cell.formula.is_none() || cell.text == shown {
+                        continue;
+                    }
+                    let Some(span) = self.formula_span(t, r, c) else { continue };
+                    if span.end < within.start || span.end > within.end {
+                        continue;
+                    }
+                    hints.push(json!({
+                        "position": position(self.markdown, span.end),
+                        "label": format!("= {}", shown),
+                        "paddingLeft": true,
+                    }));
+                }
+            }
+        }
+        json!(hints)
+    }
+
+    /// The table, row and column of the cell at byte `offset`.
+    fn cell_at(&self, offset: usize) -> Option<(usize, usize, usize)> {
+        self.cells.iter().enumerate().find_map(|(t, rows)| {
+            rows.iter().enumerate().find_map(|(r, row)| {
+                row.iter()
+                    .position(|span| span.start <= offset && offset <= span.end)
+                    .map(|c| (t, r, c))
+            })
+        })
+    }
+
+    fn cell_span(&self, t: usize, r: usize, c: usize) -> Option<Range<usize>> {
+        self.cells.get(t)?.get(r)?.get(c).cloned()
+    }
+
+    /// Where a cell's formula is written: its own formula comment, or the
+    /// trimmed cell for cells computed by a column formula.
+    fn formula_span(&self, t: usize, r: usize, c: usize) -> Option<Range<usize>> {
+        let own = self.tables[t].rows.get(r)?.cells.get(c)?.comments().into_iter().find(|comment| {
+            comment.comment.kind == CommentKind::Formula && formula_source(&comment.comment.content).is_some()
+        });
+        if let Some(span) = own.and_then(|comment| comment.span.clone()) {
+            return Some(span);
+        }
+        let span = self.cell_span(t, r, c)?;
+        let text = &self.markdown[span.clone()];
+        let start = span.start + text.len() - text.trim_start().len();
+        Some(start..(span.start + text.trim_end().len()).max(start))
+    }
+
+    /// The `!col=` comment in the header of column `c`.
+    fn column_formula_comment(&self, t: usize, c: usize) -> Option<&LocatedHtmlComment> {
+        self.tables[t].rows.first()?.cells.get(c)?.comments().into_iter().find(|comment| {
+            comment.comment.kind == CommentKind::Directive
+                && parse_directives(&comment.comment.content)
+                    .iter()
+                    .any(|d| matches!(d, Directive::Column { .. }))
+        })
+    }
+
+    /// The formula comments of a cell with their formula source, and
+    /// whether it is a column formula.
+    fn formula_comments(&self, t: usize, r: usize, c: usize) -> Vec<(&LocatedHtmlComment, String, bool)> {
+        let Some(cell) = self.tables[t].rows.get(r).and_then(|row| row.cells.get(c)) else {
+            return Vec::new();
+        };
+        let mut found: Vec<(&LocatedHtmlComment, String, bool)> = cell
+            .comments()
+            .into_iter()
+            .filter(|comment| comment.comment.kind == CommentKind::Formula)
+            .filter_map(|comment| Some((comment, formula_source(&comment.comment.content)?.to_string(), false)))
+            .collect();
+        if let (0, Some(comment), Some(source)) = (r, self.column_formula_comment(t, c), column_formula(cell)) {
+            found.push((comment, source, true));
+        }
+        found
+    }
+
+    fn expr(&self, addr: CellAddr) -> Option<Expr> {
+        let cell = self.workbook.sheets.get(addr.sheet)?.cell(addr.row, addr.col)?;
+        let source = cell.formula.as_deref()?;
+        let parsed = if cell.column_formula {
+            parse_column_formula(source, addr.row, addr.col)
+        } else {
+            parse_formula_at(source, addr.row, addr.col)
+        };
+        parsed.ok()
+    }
+
+    fn value(&self, addr: CellAddr) -> Value {
+        let computed = self.results.get(addr.sheet).and_then(|s| s.get(addr.row)).and_then(|r| r.get(addr.col));
+        match computed.cloned().flatten() {
+            Some(value) => value,
+            None => {
+                let cell = self.workbook.sheets.get(addr.sheet).and_then(|s| s.cell(addr.row, addr.col));
+                Value::from_cell_text(cell.map_or("", |cell| cell.text.as_str()))
+            }
+        }
+    }
+
+    /// A value the way the cell would show it.
+    fn shown(&self, addr: CellAddr) -> String {
+        let format = self.workbook.sheets[addr.sheet].cell(addr.row, addr.col).and_then(|c| c.format.as_deref());
+        format_value(&self.value(addr), format)
+    }
+
+    /// The sheet and cells a reference covers, the way the evaluator reads
+    /// it: whole columns and structured references skip the header.
+    #[allow(clippy::type_complexity)]
+    fn resolve(&self, table: Option<&str>, reference: &Reference, sheet: usize) -> Option<(usize, Vec<(usize, usize)>)> {
+        let sheet = match table {
+            Some(name) => self.workbook.sheet_index(name)?,
+            None => sheet,
+        };
+        let s = &self.workbook.sheets[sheet];
+        let (rows, cols) = (s.row_count(), s.col_count());
+        let rect = |rows: Range<usize>, cols: Range<usize>| -> Vec<(usize, usize)> {
+            rows.flat_map(|r| cols.clone().map(move |c| (r, c))).collect()
+        };
+        let cells = match reference {
+            Reference::Cell(cell) if cell.row < rows && cell.col < cols => vec![(cell.row, cell.col)],
+            Reference::Cell(_) => return None,
+            Reference::Range(start, end) => rect(
+                start.row.min(end.row)..(start.row.max(end.row) + 1).min(rows),
+                start.col.min(end.col)..(start.col.max(end.col) + 1).min(cols),
+            ),
+            Reference::Columns(first, last) => rect(1..rows, *first..(*last + 1).min(cols)),
+            Reference::Structured { column, row } => {
+                let col = s.column_by_name(column)?;
+                match row {
+                    Some(row) if *row < rows => vec![(*row, col)],
+                    Some(_) => return None,
+                    None => rect(1..rows, col..col + 1),
+                }
+            }
+        };
+        Some((sheet, cells))
+    }
+}
+
+/// The byte range of every cell of `table`, row by row.
+fn cell_spans(markdown: &str, table: &MarkdownTable) -> Vec<Vec<Range<usize>>> {
+    let mut rows = Vec::new();
+    let mut offset = table.start_offset;
+    for line in markdown[table.start_offset..table.end_offset].split('\n') {
+        if is_table_line(line) && !line.trim().is_empty() && !is_delimiter_row(line) {
+            rows.push(source_cells(line).into_iter().map(|(start, end)| offset + start..offset + end).collect());
+        }
+        offset += line.len() + 1;
+    }
+    rows
+}
+
+/// Why a formula cannot be computed: it does not parse, or it calls a
+/// function or uses a name mdcalc does not know.
+fn problems(source: &str, column: bool, row: usize, col: usize) -> Vec<String> {
+    let parsed = if column {
+        parse_column_formula(source, row, col)
+    } else {
+        parse_formula_at(source, row, col)
+    };
+    let mut found = Vec::new();
+    match parsed {
+        Ok(expr) => unknown_names(&expr, &mut found),
+        Err(e) => found.push(e.to_string()),
+    }
+    found
+}
+
+fn unknown_names(expr: &Expr, found: &mut Vec<String>) {
+    match expr {
+        Expr::Call(name, args) => {
+            if !functions::is_supported(name) {
+                found.push(format!("unknown function {}", name));
+            }
+            args.iter().for_each(|arg| unknown_names(arg, found));
+        }
+        Expr::Name(name) => found.push(format!("unknown name '{}'", name)),
+        Expr::Unary(_, inner) | Expr::Percent(inner) => unknown_names(inner, found),
+        Expr::Binary(_, lhs, rhs) => {
+            unknown_names(lhs, found);
+            unknown_names(rhs, found);
+        }
+        Expr::Array(rows) => rows.iter().flatten().for_each(|e| unknown_names(e, found)),
+        _ => {}
+    }
+}
+
+/// Every reference of a formula, in order and without repeats.
+fn references(expr: &Expr, found: &mut Vec<(Option<String>, Reference)>) {
+    match expr {
+        Expr::Reference { table, reference } => {
+            let item = (table.clone(), reference.clone());
+            if !found.contains(&item) {
+                found.push(item);
+            }
+        }
+        Expr::Call(_, args) => args.iter().for_each(|arg| references(arg, found)),
+        Expr::Unary(_, inner) | Expr::Percent(inner) => references(inner, found),
+        Expr::Binary(_, lhs, rhs) => {
+            references(lhs, found);
+            references(rhs, found);
+        }
+        Expr::Array(rows) => rows.iter().flatten().for_each(|e| references(e, found)),
+        _ => {}
+    }
+}
+
+fn reference_label(table: Option<&str>, reference: &Reference) -> String {
+    let prefix = table.map_or(String::new(), |t| format!("{}!", t));
+    match reference {
+        Reference::Cell(cell) => format!("{}{}", prefix, cell),
+        Reference::Range(start, end) => format!("{}{}:{}", prefix, start, end),
+        Reference::Columns(first, last) => format!("{}{}:{}", prefix, column_name(*first), column_name(*last)),
+        Reference::Structured { column, row } => {
+            let at = if row.is_some() { "@" } else { "" };
+            format!("{}[{}{}]", table.unwrap_or_default(), at, column)
+        }
+    }
+}
+
+/// An LSP position (zero based line, UTF-16 column) for a byte offset.
+fn position(text: &str, offset: usize) -> Json {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = text[..line_start].matches('\n').count();
+    let character: usize = text[line_start..offset].chars().map(char::len_utf16).sum();
+    json!({"line": line, "character": character})
+}
+
+fn range(text: &str, span: &Range<usize>) -> Json {
+    json!({"start": position(text, span.start), "end": position(text, span.end)})
+}
+
+/// The byte offset of an LSP position, clamped to the end of its line.
+fn offset(text: &str, position: &Json) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, ch) in text[start..].char_indices() {
+        if units >= character || ch == '\n' {
+            return Some(start + i);
+        }
+        units += ch.len_utf16();
+    }
+    Some(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_server() {
+        let doc = "\
+<!-- !table: fruit -->
+| Item | Price | Qty | Total <!-- !col=B*C --> |
+|------|-------|-----|-------|
+| Pears | 3 | 2 | 6 |
+| Sum | | | 0 <!-- =SUM(D2:D2) --> |
+
+| Order | Cost |
+|---|---|
+| a | <!-- =fruit!D3*2 --> |
+| b | <!-- =FOO(1) + --> |
+| c | <!-- =1/0 --> |
+";
+        let document = json!({"uri": "file:///t.md"});
+        let at = |line: u32, character: u32| json!({"line": line, "character": character});
+        let messages = [
+            json!({"id": 1, "method": "initialize", "params": {}}),
+            json!({"method": "textDocument/didOpen", "params": {"textDocument": {"uri": "file:///t.md", "text": doc}}}),
+            json!({"id": 2, "method": "textDocument/hover", "params": {"textDocument": document, "position": at(8, 8)}}),
+            json!({"id": 3, "method": "textDocument/definition", "params": {"textDocument": document, "position": at(8, 19)}}),
+            json!({"id": 4, "method": "textDocument/definition", "params": {"textDocument": document, "position": at(1, 39)}}),
+            json!({"id": 5, "method": "textDocument/codeAction", "params": {"textDocument": document, "range": {"start": at(3, 2), "end": at(3, 2)}}}),
+            json!({"id": 6, "method": "textDocument/inlayHint", "params": {"textDocument": document, "range": {"start": at(0, 0), "end": at(11, 0)}}}),
+            json!({"id": 7, "method": "shutdown"}),
+            json!({"method": "exit"}),
+            json!({"id": 8, "method": "shutdown"}),
+        ];
+        let mut input = Vec::new();
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        run_language_server(input.as_slice(), &mut output, &RecalcOptions::default()).unwrap();
+
+        let mut replies = Vec::new();
+        let mut rest = output.as_slice();
+        while let Some(reply) = read_message(&mut rest).unwrap() {
+            replies.push(reply);
+        }
+        // Nothing is read after `exit`.
+        assert_eq!(replies.len(), 8);
+        assert_eq!(replies[0]["result"]["capabilities"]["hoverProvider"], true);
+
+        let diagnostics = &replies[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 2);
+        assert_eq!(diagnostics[0]["message"], "unexpected end of formula at position 8");
+        assert_eq!(diagnostics[0]["severity"], 1);
+        assert_eq!(diagnostics[0]["range"], json!({"start": at(9, 6), "end": at(9, 24)}));
+        assert_eq!(diagnostics[1]["message"], "evaluates to #DIV/0!");
+        assert_eq!(diagnostics[1]["severity"], 2);
+
+        assert_eq!(
+            replies[2]["result"]["contents"]["value"],
+            "`Table1!B2` = `12`\n\n`=fruit!D3*2`\n\nDepends on:\n- `fruit!D3` = `6`\n  - `D2:D2`\n    - `D2` = `6`\n      - `B2` = `3`\n      - `C2` = `2`"
+        );
+        // `fruit!D3` and the `B` of `!col=B*C`.
+        assert_eq!(replies[3]["result"]["range"], json!({"start": at(4, 11), "end": at(4, 35)}));
+        assert_eq!(replies[4]["result"]["range"], json!({"start": at(1, 8), "end": at(1, 15)}));
+
+        let actions = replies[5]["result"].as_array().unwrap();
+        assert_eq!(actions.len(), 3);
+        assert_eq!(actions[0]["title"], "Recalculate table");
+        let edit = &actions[0]["edit"]["changes"]["file:///t.md"][0];
+        assert_eq!(edit["range"], json!({"start": at(1, 0), "end": at(4, 36)}));
+        assert!(edit["newText"].as_str().unwrap().ends_with("| Sum | | | 6 <!-- =SUM(D2:D2) --> |"));
+        assert!(actions[1]["edit"]["changes"]["file:///t.md"][0]["newText"]
+            .as_str()
+            .unwrap()
+            .contains("| Pears <!-- !A2 --> | 3 | 2 | 6 |"));
+
+        let hints = replies[6]["result"].as_array().unwrap();
+        assert_eq!(hints[0], json!({"position": at(4, 34), "label": "= 6", "paddingLeft": true}));
+        assert_eq!(hints[1]["label"], "= 12");
+        assert_eq!(replies[7]["result"], Json::Null);
+    }
+
+    #[test]
+    fn test_long_dependency_lists_end_once() {
+        let mut doc = String::from("| Item | Double |\n|---|---|\n");
+        for row in 2..=31 {
+            doc.push_str(&format!("| {} | <!-- =A{}*2 --> |\n", row, row));
+        }
+        doc.push_str("| Sum | <!-- =SUM(B2:B31) --> |\n");
+        let mut input = Vec::new();
+        for message in [
+            json!({"method": "textDocument/didOpen", "params": {"textDocument": {"uri": "file:///t.md", "text": doc}}}),
+            json!({"id": 1, "method": "textDocument/hover", "params": {"textDocument": {"uri": "file:///t.md"}, "position": {"line": 32, "character": 12}}}),
+            json!({"method": "exit"}),
+        ] {
+            write_message(&mut input, &message).unwrap();
+        }
+        let mut output = Vec::new();
+        run_language_server(input.as_slice(), &mut output, &RecalcOptions::default()).unwrap();
+        let mut rest = output.as_slice();
+        let hover = loop {
+            let reply = read_message(&mut rest).unwrap().unwrap();
+            if reply["id"] == 1 {
+                break reply["result"]["contents"]["value"].as_str().unwrap().to_string();
+            }
+        };
+        let lines: Vec<&str> = hover.lines().skip_while(|l| *l != "Depends on:").skip(1).collect();
+        assert_eq!(lines.len(), MAX_DEPENDENCIES + 1, "{}", hover);
+        assert_eq!(lines.iter().filter(|l| l.contains('…')).count(), 1);
+        assert_eq!(lines.last(), Some(&"- …"));
+    }
+
+    #[test]
+    fn test_oversized_messages_are_rejected() {
+        let mut input: &[u8] = b"Content-Length: 99999999999\r\n\r\n{}";
+        assert!(read_message(&mut input).unwrap_err().contains("over the limit"));
+    }
+
+    #[test]
+    fn test_positions() {
+        let text = "aé😀b\nxy";
+        assert_eq!(position(text, 7), json!({"line": 0, "character": 4}));
+        assert_eq!(position(text, 9), json!({"line": 1, "character": 0}));
+        assert_eq!(offset(text, &json!({"line": 0, "character": 4})), Some(7));
+        assert_eq!(offset(text, &json!({"line": 0, "character": 40})), Some(8));
+        assert_eq!(offset(text, &json!({"line": 1, "character": 1})), Some(10));
+        assert_eq!(offset(text, &json!({"line": 5, "character": 0})), None);
+    }
+}
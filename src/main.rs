@@ -1,121 +1,376 @@
-
-mod md_comments;
-mod table_parser;
-mod comment_stripper;
-mod cell_markers;
-
 use std::env;
-use crate::md_comments::parse_markdown_for_comments;
-use crate::cell_markers::{apply_marker_mode, make_html_comment_node, MarkerMode};
-use crate::table_parser::TableParser;
-
-use comrak::{parse_document, Arena, ComrakOptions};
-use comrak::nodes::{AstNode, NodeValue};
-
-fn dump_ast<'a>(node: &'a AstNode<'a>, indent: usize) {
-    let pad = "  ".repeat(indent);
-    let val = &node.data.borrow().value;
-    let desc = match val {
-        NodeValue::Document => "Document",
-        NodeValue::Paragraph => "Paragraph",
-        NodeValue::Text(s) => &format!("Text({})", s),
-        NodeValue::HtmlInline(s) => &format!("HtmlInline({})", s),
-        NodeValue::HtmlBlock(s) => &format!("HtmlBlock({})", s.literal),
-        NodeValue::Code(s) => &format!("Code({})", s.literal),
-        NodeValue::SoftBreak => "SoftBreak",
-        NodeValue::LineBreak => "LineBreak",
-        _ => "Other",
-    };
-    println!("{}- {}", pad, desc);
-    for child in node.children() {
-        dump_ast(child, indent + 1);
-    }
-}
-
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
-fn check_comments() {
-    let arena = comrak::Arena::new();
-    let markdown = "Here is <!-- !A --> and <!-- !=B2*C2 --> inline.";
-    let comments = md_comments::parse_markdown_for_comments(&arena, markdown);
+use mdcalc::{
+    parse_date, run_language_server, Document, ExportFormat, ExportOptions, ImportOptions, MarkerMode, RecalcOptions, SheetImportOptions,
+    SortOptions, SpreadsheetFormat, WatchOptions, Watcher,
+};
 
-    for located in comments {
-        println!("{:?}", located);
-    }
-}
+const USAGE: &str = "\
+usage: mdcalc [options] [FILE]
+       mdcalc fix-refs [options] [FILE]
+       mdcalc sort --table N --by C [--desc] [options] [FILE]
+       mdcalc export --table N [--format csv|tsv] [options] [FILE]
+       mdcalc export --workbook BOOK.xlsx|BOOK.ods [options] [FILE]
+       mdcalc json [options] [FILE]
+       mdcalc render --html [options] [FILE]
+       mdcalc import [--markers] [--into DOC --table N] [--format csv|tsv] [options] [FILE]
+       mdcalc import [--sheet NAME] [--range A1:D10] [--markers] [--into DOC --table N] BOOK.xlsx|BOOK.ods
+       mdcalc watch [options] DIR
+       mdcalc lsp [--today YYYY-MM-DD]
 
-fn check_table_parsing() {
-    let markdown = r#"
-| Item <!-- !A -->     | Price <!-- !B --> | Quantity <!-- !C --> | Total <!-- !D -->         |
-|----------------------|-------------------|-----------------------|---------------------------|
-| Apples <!-- !2 -->   | 2                 | 3                     | 6 <!-- !=B2*C2 -->        |
-| Bananas <!-- !3 -->  | 1                 | 5                     | 5 <!-- !=B3*C3 -->        |
-| Cherries <!-- !4 --> | 4                 | 2                     | 8 <!-- !=B4*C4 -->        |
-| Total <!-- !5 -->    |                   |                       | 19 <!-- !=D2+D3+D4 -->    |
-"#;
+Recalculates the formulas in the Markdown tables of FILE (or stdin) and
+prints the result.
 
-    let arena = Arena::new();
-    let options = ComrakOptions::default();
-    let root = parse_document(&arena, markdown, &options);
+commands:
+  fix-refs                    first point formula references back at the cells
+                              recorded by the row and column markers, after
+                              rows or columns were inserted or deleted
+  sort                        first sort the body rows of table N (a number
+                              or a name) by column C (letters or header
+                              text), keeping the header and summary rows in
+                              place and formulas pointing at the same cells
+  export                      print the computed values of table N as CSV
+                              (the default) or TSV instead of the document
+    --formula-columns         add a column of formulas after every column
+                              that has any
+    --formulas-file PATH      also write the formulas of table N to PATH, laid
+                              out like the table
+    --workbook PATH           write every table as a sheet of PATH (.xlsx or
+                              .ods), with formulas translated for it
+  json                        print every table, with cell comments, formulas
+                              and computed values, as JSON; FILE is not
+                              recalculated, so spans are byte offsets into it
+  render --html               print the recalculated document as HTML, with
+                              formulas in the title of their cells and
+                              mdcalc's comments left out
+  import                      read FILE as CSV (TSV if it ends in .tsv) and
+                              print it as a Markdown table
+    --markers                 add markers to the header row and first column
+    --into DOC                replace the rows of table N of DOC instead,
+                              keeping its !col= formula columns, and print
+                              DOC (or rewrite it with --in-place)
+    --sheet NAME              with an .xlsx, .xls or .ods FILE, import this
+                              worksheet (default: the first); formulas become
+                              formula comments
+    --range A1:D10            import only this range of the worksheet
+  watch                       keep recalculating the Markdown files under DIR
+                              in place as they are saved; files that come out
+                              the same are not rewritten
+  lsp                         run a language server for editors over stdin
+                              and stdout
 
-    let comments = md_comments::parse_markdown_for_comments(&arena, markdown);
+options:
+  --in-place                  rewrite FILE instead of printing
+  --delete-all-markers        remove all cell markers
+  --update-markers            renumber existing markers
+  --only-row-column-markers   keep markers in the header row and first column
+  --all-markers               add markers to every cell
+  --today YYYY-MM-DD          date returned by TODAY() (default: current UTC date)";
 
-    dump_ast(root, 2);
+enum Command {
+    Recalc,
+    FixRefs,
+    Sort,
+    Export,
+    Json,
+    Render,
+    Import,
+    Watch,
+    Lsp,
+}
 
-    let tables = TableParser::extract_tables_from_ast(root, Some(&comments), markdown);
-    println!("Found {} tables", tables.len());
+fn main() {
+    let mut options = RecalcOptions::default();
+    let mut in_place = false;
+    let mut file = None;
 
-    for (i, table) in tables.iter().enumerate() {
-        println!("Table {}: {} rows", i + 1, table.rows.len());
-        for row in &table.rows {
-            for cell in &row.cells {
-                print!("[{}] ", cell.raw_text());
+    let mut args = env::args().skip(1).peekable();
+    let command = match args.peek().map(String::as_str) {
+        Some("fix-refs") => {
+            args.next();
+            Command::FixRefs
+        }
+        Some("sort") => {
+            args.next();
+            Command::Sort
+        }
+        Some("export") => {
+            args.next();
+            Command::Export
+        }
+        Some("json") => {
+            args.next();
+            Command::Json
+        }
+        Some("render") => {
+            args.next();
+            Command::Render
+        }
+        Some("import") => {
+            args.next();
+            Command::Import
+        }
+        Some("watch") => {
+            args.next();
+            Command::Watch
+        }
+        Some("lsp") => {
+            args.next();
+            Command::Lsp
+        }
+        _ => Command::Recalc,
+    };
+    let mut table = String::new();
+    let mut format = None;
+    let mut formula_columns = false;
+    let mut formulas_file = None;
+    let mut workbook = None;
+    let mut markers = false;
+    let mut into = None;
+    let mut html = false;
+    let mut sheet = SheetImportOptions::default();
+    let mut sort = SortOptions {
+        table: String::new(),
+        by: String::new(),
+        descending: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--delete-all-markers" => options.marker_mode = Some(MarkerMode::DeleteAll),
+            "--update-markers" => options.marker_mode = Some(MarkerMode::UpdateExisting),
+            "--only-row-column-markers" => options.marker_mode = Some(MarkerMode::OnlyRowAndColumn),
+            "--all-markers" => options.marker_mode = Some(MarkerMode::AllMarkers),
+            "--in-place" => in_place = true,
+            "--by" | "--desc" if !matches!(command, Command::Sort) => fail(&format!("{} needs the sort command", arg)),
+            "--table" if matches!(command, Command::Recalc | Command::FixRefs | Command::Watch | Command::Lsp) => {
+                fail("--table needs the sort, export or import command")
+            }
+            "--format" if !matches!(command, Command::Export | Command::Import) => {
+                fail("--format needs the export or import command")
+            }
+            "--formula-columns" | "--formulas-file" | "--workbook" if !matches!(command, Command::Export) => {
+                fail(&format!("{} needs the export command", arg))
+            }
+            "--html" if !matches!(command, Command::Render) => fail("--html needs the render command"),
+            "--markers" | "--into" | "--sheet" | "--range" if !matches!(command, Command::Import) => {
+                fail(&format!("{} needs the import command", arg))
+            }
+            "--table" => table = args.next().unwrap_or_else(|| fail("--table needs a table")),
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("csv") => Some(ExportFormat::Csv),
+                    Some("tsv") => Some(ExportFormat::Tsv),
+                    _ => fail("--format needs csv or tsv"),
+                }
             }
-            println!();
+            "--formula-columns" => formula_columns = true,
+            "--markers" => markers = true,
+            "--html" => html = true,
+            "--into" => into = Some(args.next().unwrap_or_else(|| fail("--into needs a document"))),
+            "--sheet" => sheet.sheet = args.next().unwrap_or_else(|| fail("--sheet needs a worksheet name")),
+            "--range" => sheet.range = args.next().unwrap_or_else(|| fail("--range needs a range")),
+            "--formulas-file" => formulas_file = Some(args.next().unwrap_or_else(|| fail("--formulas-file needs a path"))),
+            "--workbook" => {
+                let path = args.next().unwrap_or_else(|| fail("--workbook needs a path"));
+                let format = SpreadsheetFormat::from_path(&path)
+                    .unwrap_or_else(|| fail("--workbook needs a .xlsx or .ods path"));
+                workbook = Some((path, format));
+            }
+            "--by" => sort.by = args.next().unwrap_or_else(|| fail("--by needs a column")),
+            "--desc" => sort.descending = true,
+            "--today" => {
+                let value = args.next().unwrap_or_else(|| fail("--today needs a date"));
+                let today = parse_date(&value)
+                    .unwrap_or_else(|| fail(&format!("invalid date '{}', expected YYYY-MM-DD", value)));
+                options.today = Some(today);
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => fail(&format!("unknown option '{}'", arg)),
+            _ if file.is_none() => file = Some(arg),
+            _ => fail("only one FILE may be given"),
         }
     }
-}
 
+    if let Command::Watch = command {
+        if in_place {
+            fail("--in-place cannot be used with watch, which always rewrites files");
+        }
+        let dir = file.unwrap_or_else(|| fail("watch needs a DIR"));
+        watch(&dir, options);
+    }
+    if let Command::Lsp = command {
+        if in_place || file.is_some() || options.marker_mode.is_some() {
+            fail("lsp takes no FILE, --in-place or marker options");
+        }
+        run_language_server(io::stdin().lock(), io::stdout().lock(), &options).unwrap_or_else(|e| fail(&e));
+        return;
+    }
 
-fn main() {
-    check_comments();
-    check_table_parsing();
-
-    let args: Vec<String> = env::args().collect();
-    let mode = if args.contains(&"--delete-all-markers".to_string()) {
-        MarkerMode::DeleteAll
-    } else if args.contains(&"--update-markers".to_string()) {
-        MarkerMode::UpdateExisting
-    } else if args.contains(&"--only-row-column-markers".to_string()) {
-        MarkerMode::OnlyRowAndColumn
-    } else if args.contains(&"--all-markers".to_string()) {
-        MarkerMode::AllMarkers
-    } else {
-        MarkerMode::UpdateExisting // default
+    // Spreadsheets are read as bytes when the import runs.
+    let spreadsheet = matches!(command, Command::Import) && file.as_deref().is_some_and(is_spreadsheet);
+    if !spreadsheet && (!sheet.sheet.is_empty() || !sheet.range.is_empty()) {
+        fail("--sheet and --range need an .xlsx, .xls or .ods FILE");
+    }
+    let input = match &file {
+        Some(_) if spreadsheet => String::new(),
+        Some(path) => read(path),
+        None => {
+            let mut input = String::new();
+            io::stdin()
+                .read_to_string(&mut input)
+                .unwrap_or_else(|e| fail(&format!("stdin: {}", e)));
+            input
+        }
     };
 
-    let markdown = include_str!("../test_data/test.md");
-
-    let arena = Arena::new();
-    let options = ComrakOptions::default();
-    let root = parse_document(&arena, markdown, &options);
-
-
-    let comments = parse_markdown_for_comments(&arena, markdown);
-    println!("comments: {:#?}", comments);
+    // The document to work on, and the file --in-place rewrites.
+    let (markdown, target) = match (&command, &into) {
+        (Command::Import, Some(path)) => (read(path), Some(path.clone())),
+        (Command::Import, None) => (String::new(), None),
+        _ => (input.clone(), file.clone()),
+    };
+    sort.table = table.clone();
+    let export = ExportOptions {
+        table: table.clone(),
+        format: format.unwrap_or(ExportFormat::Csv),
+        formula_columns,
+    };
 
-    let mut tables = TableParser::extract_tables_from_ast(root, Some(&comments), markdown);
+    let mut document = Document::parse(&markdown);
+    match command {
+        Command::Recalc => {}
+        Command::Watch | Command::Lsp => unreachable!("handled before reading FILE"),
+        Command::Export if table.is_empty() && workbook.is_none() => fail("export needs --table or --workbook"),
+        Command::Export if in_place => fail("--in-place cannot be used with export"),
+        Command::Export => {}
+        Command::Json if in_place => fail("--in-place cannot be used with json"),
+        Command::Json if options.marker_mode.is_some() => fail("marker options cannot be used with json"),
+        Command::Json => {}
+        Command::Render if !html => fail("render needs --html"),
+        Command::Render if in_place => fail("--in-place cannot be used with render"),
+        Command::Render => {}
+        Command::Import if spreadsheet => {
+            let path = file.as_deref().unwrap_or_default();
+            let data = fs::read(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+            sheet.markers = markers;
+            let unsupported = match &into {
+                Some(_) if table.is_empty() => fail("--into needs --table"),
+                Some(_) => document
+                    .replace_table_from_sheet(&table, &data, &sheet)
+                    .unwrap_or_else(|e| fail(&format!("{}: {}", path, e))),
+                None if in_place => fail("--in-place needs --into with import"),
+                None => {
+                    let (imported, unsupported) =
+                        Document::from_sheet(&data, &sheet).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+                    document = imported;
+                    unsupported
+                }
+            };
+            if !unsupported.is_empty() {
+                eprintln!("mdcalc: warning: unsupported functions: {}", unsupported.join(", "));
+            }
+        }
+        Command::Import => {
+            let is_tsv = file.as_ref().is_some_and(|f| f.to_ascii_lowercase().ends_with(".tsv"));
+            let import = ImportOptions {
+                format: format.unwrap_or(if is_tsv { ExportFormat::Tsv } else { ExportFormat::Csv }),
+                markers,
+            };
+            match &into {
+                Some(_) if table.is_empty() => fail("--into needs --table"),
+                Some(_) => document.replace_table(&table, &input, &import).unwrap_or_else(|e| fail(&e)),
+                None if in_place => fail("--in-place needs --into with import"),
+                None => document = Document::from_delimited(&input, &import).unwrap_or_else(|e| fail(&e)),
+            }
+        }
+        Command::FixRefs => document.fix_references(),
+        Command::Sort => {
+            if sort.table.is_empty() || sort.by.is_empty() {
+                fail("sort needs --table and --by");
+            }
+            document.sort(&sort, options.today).unwrap_or_else(|e| fail(&e));
+        }
+    }
+    // JSON spans point into FILE as it is; the values are computed anyway.
+    if !matches!(command, Command::Json) {
+        document.recalculate(&options);
+    }
+    let output = match command {
+        Command::Export => {
+            if let Some(path) = &formulas_file {
+                let formulas = document
+                    .export_formulas(&export.table, export.format)
+                    .unwrap_or_else(|e| fail(&e));
+                fs::write(path, formulas).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+            }
+            if let Some((path, format)) = &workbook {
+                let book = document
+                    .export_workbook(*format, options.today)
+                    .unwrap_or_else(|e| fail(&e));
+                fs::write(path, book).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+            }
+            match table.is_empty() {
+                true => String::new(),
+                false => document.export(&export).unwrap_or_else(|e| fail(&e)),
+            }
+        }
+        Command::Json => document.to_json(options.today),
+        Command::Render => document.to_html(),
+        _ => document.render(),
+    };
 
-    println!("Found {} tables", tables.len());
+    match (&target, in_place) {
+        (Some(path), true) => {
+            if output != markdown {
+                fs::write(path, &output).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+            }
+        }
+        (None, true) => fail("--in-place needs a FILE"),
+        _ => {
+            io::stdout()
+                .write_all(output.as_bytes())
+                .unwrap_or_else(|e| fail(&format!("stdout: {}", e)));
+        }
+    }
+}
 
-    for (i, table) in tables.iter_mut().enumerate() {
-        println!("Table {}: {} rows", i + 1, table.rows.len());
-        apply_marker_mode(table, mode, make_html_comment_node(&arena));
-        for row in &table.rows {
-            for cell in &row.cells {
-                print!("[{}] ", cell.raw_text());
+fn watch(dir: &str, options: RecalcOptions) -> ! {
+    let options = WatchOptions {
+        recalc: options,
+        ..WatchOptions::default()
+    };
+    let mut watcher = Watcher::new(Path::new(dir), options).unwrap_or_else(|e| fail(&e));
+    loop {
+        thread::sleep(Duration::from_millis(100));
+        for result in watcher.poll(Instant::now()).unwrap_or_else(|e| fail(&e)) {
+            match result {
+                Ok(path) => println!("recalculated {}", path.display()),
+                Err(e) => eprintln!("mdcalc: {}", e),
             }
-            println!();
         }
     }
+}
+
+fn read(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+}
+
+fn is_spreadsheet(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    [".xlsx", ".xlsm", ".xls", ".ods"].iter().any(|ext| path.ends_with(ext))
+}
 
+fn fail(message: &str) -> ! {
+    eprintln!("mdcalc: {}", message);
+    eprintln!("{}", USAGE);
+    process::exit(2);
 }
@@ -1,15 +1,23 @@
 // md_comments.rs
 
+use std::ops::Range;
+
 use comrak::{nodes::{AstNode, NodeValue}, parse_document, Arena, ComrakOptions};
 
+/// What an HTML comment in a table cell is for, from its content.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CommentKind {
     Formula,    // starts with '=' after optional '!'
     Marker,     // starts with '!'
+    Directive,  // '!' followed by a keyword, e.g. '!table: rates'
+    Spill,      // 'spill': a value spilled from an array formula
+    Generated,  // 'generated': a row or column added for a spill
     Formatting, // starts with '$' or other
     Unknown,    // fallback
 }
 
+/// An HTML comment: its content between `<!--` and `-->`, untrimmed, and
+/// where it was found in the text that was scanned for it.
 #[derive(Debug,Clone)]
 pub struct HtmlComment {
     pub content: String,
@@ -18,9 +26,13 @@ pub struct HtmlComment {
     pub length: usize,
 }
 
+/// A comment found in a document. Owns its data, so tables built from it
+/// outlive the comrak arena they were parsed with.
 #[derive(Debug,Clone)]
-pub struct LocatedHtmlComment<'a> {
-    pub node: &'a AstNode<'a>,
+pub struct LocatedHtmlComment {
+    /// Byte range of the comment in the document, `None` for comments that
+    /// were added after parsing.
+    pub span: Option<Range<usize>>,
     pub comment: HtmlComment,
 }
 
@@ -41,6 +53,13 @@ pub fn extract_html_comments(html: &str) -> Vec<HtmlComment> {
             } else if trimmed.starts_with('!') &&
                     trimmed[1..].chars().all(|c| c.is_ascii_alphanumeric()) {
                 CommentKind::Marker
+            } else if trimmed.starts_with('!') &&
+                    trimmed[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+                CommentKind::Directive
+            } else if trimmed == "spill" {
+                CommentKind::Spill
+            } else if trimmed == "generated" {
+                CommentKind::Generated
             } else if trimmed.starts_with('%') {
                 CommentKind::Formatting
             } else {
@@ -49,7 +68,7 @@ pub fn extract_html_comments(html: &str) -> Vec<HtmlComment> {
 
             let comment = HtmlComment {
                 content: full.to_string(),
-                kind: kind,
+                kind,
                 offset: absolute_begin,
                 length: absolute_end - absolute_begin,
             };
@@ -64,8 +83,8 @@ pub fn extract_html_comments(html: &str) -> Vec<HtmlComment> {
 
 pub fn parse_markdown_for_comments<'a>(
     arena: &'a Arena<AstNode<'a>>,
-    markdown: &'a str,
-) -> Vec<LocatedHtmlComment<'a>> {
+    markdown: &str,
+) -> Vec<LocatedHtmlComment> {
     let options = ComrakOptions::default();
     let root = parse_document(arena, markdown, &options);
     let mut results = Vec::new();
@@ -82,27 +101,43 @@ pub fn parse_markdown_for_comments<'a>(
         let offset_line = source_start.line.saturating_sub(1);
         let col = source_start.column.saturating_sub(1);
 
-        if let Some(line_start_offset) = markdown
+        let approx_offset = markdown
             .lines()
             .take(offset_line)
             .map(|l| l.len() + 1)
             .sum::<usize>()
-            .checked_add(col)
-    {
+            + col;
+
+        // comrak reports inline columns on continuation lines shifted by the
+        // paragraph indent, so find the literal itself nearest to where
+        // comrak says it is.
+        let Some(node_offset) = locate_literal(markdown, raw, approx_offset) else {
+            continue;
+        };
+
         results.extend(
             extract_html_comments(raw)
                 .into_iter()
                 .map(|mut comment| {
-                    comment.offset += line_start_offset;
-                    LocatedHtmlComment { node, comment }
+                    comment.offset += node_offset;
+                    LocatedHtmlComment {
+                        span: Some(comment.offset..comment.offset + comment.length),
+                        comment,
+                    }
                 }),
         );
     }
-    }
 
     results
 }
 
+fn locate_literal(markdown: &str, literal: &str, approx_offset: usize) -> Option<usize> {
+    markdown
+        .match_indices(literal)
+        .map(|(offset, _)| offset)
+        .min_by_key(|offset| offset.abs_diff(approx_offset))
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,459 @@
+// recalc.rs
+
+use crate::cell_markers::{apply_markers, MarkerMode};
+use crate::dates;
+use crate::directives::table_names;
+use crate::evaluator::{format_value, Sheet, Value, Workbook};
+use crate::generators::generate_tables;
+use crate::md_comments::{CommentKind, HtmlComment, LocatedHtmlComment};
+use crate::table_parser::{MarkdownTable, TableCell, TableCellPiece, TableParser, TableRow};
+use crate::table_writer::render_document;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecalcOptions {
+    /// Marker update applied before evaluating, if any.
+    pub marker_mode: Option<MarkerMode>,
+    /// Serial date returned by `TODAY()`; the current date when unset.
+    pub today: Option<f64>,
+}
+
+/// Writes a computed value into the text of a formula cell, keeping the
+/// whitespace around the old text. The value is the text right before the
+/// formula comment (or the first text of cells without one); text after the
+/// comment is the user's and stays. Cells without such text get the value
+/// inserted before their formula comment, or before their first comment.
+pub fn set_cell_value(cell: &mut TableCell, value: &str) {
+    let formula = cell
+        .pieces
+        .iter()
+        .position(|p| matches!(p, TableCellPiece::Comment(c) if c.comment.kind == CommentKind::Formula));
+    let holder = match formula {
+        Some(formula) => cell.pieces[..formula].iter().rposition(|p| matches!(p, TableCellPiece::Text(_))),
+        None => cell.pieces.iter().position(|p| matches!(p, TableCellPiece::Text(_))),
+    };
+    match holder {
+        Some(index) => {
+            if let TableCellPiece::Text(text) = &mut cell.pieces[index] {
+                let leading = &text[..text.len() - text.trim_start().len()];
+                let trailing = &text[text.trim_end().len()..];
+                *text = format!("{}{}{}", leading, value, trailing);
+            }
+        }
+        None if value.is_empty() => {}
+        None => {
+            let at = formula.unwrap_or(0);
+            // Text after a comment needs its own separating space.
+            let text = if at == 0 { value.to_string() } else { format!(" {}", value) };
+            cell.pieces.insert(at, TableCellPiece::Text(text));
+        }
+    }
+}
+
+fn has_comment(cell: &TableCell, kind: CommentKind) -> bool {
+    cell.pieces
+        .iter()
+        .any(|piece| matches!(piece, TableCellPiece::Comment(c) if c.comment.kind == kind))
+}
+
+fn comment_piece(kind: CommentKind, content: &str) -> TableCellPiece {
+    TableCellPiece::Comment(LocatedHtmlComment {
+        span: None,
+        comment: HtmlComment {
+            content: content.to_string(),
+            kind,
+            offset: 0,
+            length: 0,
+        },
+    })
+}
+
+/// Adds the rows and columns that array results spill into past the edge of
+/// the table. The first cell of a new row, and the header cell of a new
+/// column, is marked with a `<!-- generated -->` comment.
+fn grow_table(table: &mut MarkdownTable, results: &[Vec<Option<Value>>]) {
+    let width = table
+        .rows
+        .iter()
+        .map(|r| r.cells.len())
+        .chain(results.iter().map(Vec::len))
+        .max()
+        .unwrap_or(0);
+    while table.rows.len() < results.len() {
+        let mut first = TableCell { pieces: Vec::new(), span: None };
+        first.pieces.push(comment_piece(CommentKind::Generated, "generated"));
+        table.rows.push(TableRow { cells: vec![first] });
+    }
+    for (index, row) in table.rows.iter_mut().enumerate() {
+        while row.cells.len() < width {
+            let mut cell = TableCell { pieces: Vec::new(), span: None };
+            if index == 0 {
+                cell.pieces.push(comment_piece(CommentKind::Generated, "generated"));
+            }
+            row.cells.push(cell);
+        }
+    }
+}
+
+/// Removes trailing generated rows and columns that no longer hold any value.
+fn shrink_table(table: &mut MarkdownTable, sheet: &Sheet, results: &[Vec<Option<Value>>]) {
+    let unused = |row: usize, col: usize| {
+        results.get(row).and_then(|r| r.get(col)).is_none_or(Option::is_none)
+            && sheet
+                .cell(row, col)
+                .is_none_or(|c| c.formula.is_none() && (c.text.is_empty() || c.spilled))
+    };
+    while table.rows.len() > 1 {
+        let row = table.rows.len() - 1;
+        let generated = table.rows[row].cells.first().is_some_and(|c| has_comment(c, CommentKind::Generated));
+        if !generated || !(0..table.rows[row].cells.len()).all(|col| unused(row, col)) {
+            break;
+        }
+        table.rows.pop();
+    }
+    while table.rows[0].cells.len() > 1 {
+        let col = table.rows[0].cells.len() - 1;
+        if !has_comment(&table.rows[0].cells[col], CommentKind::Generated) || !(0..table.rows.len()).all(|row| unused(row, col)) {
+            break;
+        }
+        for row in table.rows.iter_mut() {
+            row.cells.truncate(col);
+        }
+    }
+}
+
+/// Evaluates every formula in `tables` and stores the formatted results in
+/// the cells. Cells filled by an array result are tagged with a
+/// `<!-- spill -->` comment, and cleared again once nothing spills into them.
+/// Tables grow to hold the results that spill past their edge, and shrink
+/// again once the generated rows and columns are no longer needed.
+pub fn recalculate_tables(tables: &mut [MarkdownTable], names: &[String], today: f64) {
+    let workbook = Workbook::from_tables(tables, names);
+    let results = workbook.evaluate(today);
+
+    for ((table, sheet), sheet_results) in tables.iter_mut().zip(&workbook.sheets).zip(results) {
+        if table.rows.is_empty() {
+            continue;
+        }
+        grow_table(table, &sheet_results);
+        for (row_index, (row, row_results)) in table.rows.iter_mut().zip(&sheet_results).enumerate() {
+            for (col_index, (cell, result)) in row.cells.iter_mut().zip(row_results).enumerate() {
+                let sheet_cell = sheet.cell(row_index, col_index);
+                let format = sheet_cell.and_then(|c| c.format.as_deref());
+                match result {
+                    Some(value) if sheet_cell.is_some_and(|c| c.formula.is_some()) => {
+                        set_cell_value(cell, &format_value(value, format));
+                    }
+                    Some(value) => {
+                        set_cell_value(cell, &format_value(value, format));
+                        if !has_comment(cell, CommentKind::Spill) {
+                            cell.pieces.push(comment_piece(CommentKind::Spill, "spill"));
+                        }
+                    }
+                    None if sheet_cell.is_some_and(|c| c.spilled) => {
+                        set_cell_value(cell, "");
+                        cell.pieces
+                            .retain(|p| !matches!(p, TableCellPiece::Comment(c) if c.comment.kind == CommentKind::Spill));
+                    }
+                    None => {}
+                }
+            }
+        }
+        shrink_table(table, sheet, &sheet_results);
+    }
+}
+
+/// Recalculates every table in a Markdown document, optionally updating
+/// markers, regenerates the tables of generator directives such as
+/// `!filter`, and returns the rewritten document.
+pub fn recalculate_markdown(markdown: &str, options: &RecalcOptions) -> String {
+    let today = options.today.unwrap_or_else(dates::today);
+    let recalculated = recalculate_once(markdown, options, today);
+    let generated = generate_tables(&recalculated, today);
+    if generated == recalculated {
+        return recalculated;
+    }
+    // Formulas may refer to the generated tables.
+    recalculate_once(&generated, options, today)
+}
+
+fn recalculate_once(markdown: &str, options: &RecalcOptions, today: f64) -> String {
+    let originals = TableParser::parse_tables(markdown);
+    let names = table_names(markdown, &originals);
+    let mut tables = originals.clone();
+
+    if let Some(mode) = options.marker_mode {
+        for table in tables.iter_mut() {
+            apply_markers(table, mode);
+        }
+    }
+
+    recalculate_tables(&mut tables, &names, today);
+    render_document(markdown, &originals, &tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table_writer::render_cell;
+
+    #[test]
+    fn test_set_value_in_cells_without_text() {
+        let markdown = "\
+| a | b | c | d |
+|---|---|---|---|
+| 2026-01-02 | |  <!-- !=A2-1 --> | <!-- !D --> <!-- =A2+1 --> <!-- %Y --> |
+";
+        let mut tables = TableParser::parse_tables(markdown);
+        let row = &mut tables[0].rows[1];
+        assert!(row.cells[2].pieces.iter().all(|p| matches!(p, TableCellPiece::Comment(_))));
+
+        for (col, value) in [(1, "x"), (2, "2026-01-01"), (3, "2026")] {
+            set_cell_value(&mut row.cells[col], value);
+            set_cell_value(&mut row.cells[col], value);
+        }
+        assert_eq!(render_cell(&row.cells[1]), " x ");
+        assert_eq!(render_cell(&row.cells[2]), " 2026-01-01 <!-- !=A2-1 --> ");
+        // A marker before the formula keeps its place.
+        assert_eq!(render_cell(&row.cells[3]), " <!-- !D --> 2026 <!-- =A2+1 --> <!-- %Y --> ");
+
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert!(result.ends_with("| 2026-01-02 | | 2026-01-01 <!-- !=A2-1 --> | <!-- !D --> 2026 <!-- =A2+1 --> <!-- %Y --> |\n"), "{}", result);
+        assert_eq!(recalculate_markdown(&result, &RecalcOptions::default()), result);
+    }
+
+    #[test]
+    fn test_format_comment_on_numbers() {
+        let markdown = "\
+| Price | Qty | Total | Year |
+|---|---|---|---|
+| 2.5 | 3 | 0 <!-- =A2*B2 --> <!-- %.2f --> | 0 <!-- =46000 --> <!-- %Y --> |
+| 1000 | -0.001 | <!-- =A3*1234.5 --> <!-- %,.0f USD --> | <!-- =B3 --> <!-- %.1f%% --> |
+| 1 | 2 | <!-- =A4/B4 --> <!-- %q --> | <!-- =DATE(2026,1,2) --> <!-- %.0f --> |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert!(result.contains("| 7.50 <!-- =A2*B2 --> <!-- %.2f --> | 2025 <!-- =46000 --> <!-- %Y --> |"), "{}", result);
+        assert!(result.contains("| 1,234,500 USD <!-- =A3*1234.5 --> <!-- %,.0f USD --> | 0.0% <!-- =B3 --> <!-- %.1f%% --> |"), "{}", result);
+        // Patterns without a field mdcalc knows are shown as an error.
+        assert!(result.contains("| #VALUE! <!-- =A4/B4 --> <!-- %q --> | 46024 <!-- =DATE(2026,1,2) --> <!-- %.0f --> |"), "{}", result);
+        assert_eq!(recalculate_markdown(&result, &RecalcOptions::default()), result);
+    }
+
+    #[test]
+    fn test_serials_outside_the_calendar() {
+        let markdown = "\
+| Start | Far | Later | Year |
+|---|---|---|---|
+| 2026-01-01 | <!-- =1e20 --> <!-- %Y --> | <!-- =A2+1e300 --> | <!-- =YEAR(1e300) --> |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert!(result.contains("| 100000000000000000000 <!-- =1e20 --> <!-- %Y --> | #NUM! <!-- =A2+1e300 --> | #NUM! <!-- =YEAR(1e300) --> |"), "{}", result);
+    }
+
+    #[test]
+    fn test_set_value_keeps_text_after_formula() {
+        let markdown = "\
+| Price | Qty | Total |
+|---|---|---|
+| 2 | 3 | 0 <!-- =A2*B2 --> USD |
+| 4 | 1 | <!-- !C3 --> <!-- =A3*B3 --> USD |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert!(result.contains("| 2 | 3 | 6 <!-- =A2*B2 --> USD |"), "{}", result);
+        assert!(result.contains("| 4 | 1 | <!-- !C3 --> 4 <!-- =A3*B3 --> USD |"), "{}", result);
+        assert_eq!(recalculate_markdown(&result, &RecalcOptions::default()), result);
+    }
+
+    #[test]
+    fn test_recalculate_fruit_table() {
+        let markdown = "\
+| Item <!-- !A --> | Price <!-- !B --> | Quantity <!-- !C --> | Total <!-- !D --> |
+|------------------|------------------|----------------------|--------------------|
+| Apples <!-- !2 --> | 2 | 3 | 0 <!-- !=B2*C2 --> |
+| Bananas <!-- !3 --> | 1 | 5 | 5 <!-- !=B3*C3 --> |
+| Total <!-- !4 --> | | | 1 <!-- !=D2+D3 --> |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert_eq!(
+            result,
+            markdown
+                .replace("| 0 <!-- !=B2*C2 -->", "| 6 <!-- !=B2*C2 -->")
+                .replace("| 1 <!-- !=D2+D3 -->", "| 11 <!-- !=D2+D3 -->")
+        );
+        assert_eq!(recalculate_markdown(&result, &RecalcOptions::default()), result);
+    }
+
+    #[test]
+    fn test_lookup_into_named_table() {
+        let markdown = "\
+<!-- !table: rates -->
+| Fruit | Rate |
+|-------|------|
+| Apples | 2 |
+| Cherries | 4 |
+
+| Order | Qty | Cost |
+|-------|-----|------|
+| Cherries | 3 | ? <!-- =VLOOKUP(A2, rates!A2:B3, 2, FALSE)*B2 --> |
+| Kiwi | 1 | ? <!-- =IFERROR(INDEX(rates!B:B, MATCH(A3, rates!A:A, 0)), 0) --> |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert!(result.contains("| Cherries | 3 | 12 <!-- =VLOOKUP"));
+        assert!(result.contains("| Kiwi | 1 | 0 <!-- =IFERROR"));
+    }
+
+    #[test]
+    fn test_dates_and_formatting() {
+        let markdown = "\
+| Task | Start | End | Days |
+|------|-------|-----|------|
+| Build | 2026-10-01 | ? <!-- =EDATE(B2, 1) --> | ? <!-- =NETWORKDAYS(B2, C2) --> |
+| Ship | ? <!-- =C2+7 --> <!-- %d %b %Y --> | ? <!-- =TODAY() --> | ? <!-- =C3-C2 --> |
+";
+        let options = RecalcOptions {
+            today: dates::parse_date("2026-10-17"),
+            ..RecalcOptions::default()
+        };
+        let result = recalculate_markdown(markdown, &options);
+        assert!(result.contains("| Build | 2026-10-01 | 2026-11-01 <!-- =EDATE(B2, 1) --> | 22 <!--"));
+        assert!(result.contains("| Ship | 08 Nov 2026 <!-- =C2+7 --> <!-- %d %b %Y --> | 2026-10-17 <!--"));
+        assert!(result.contains("| -15 <!-- =C3-C2 --> |"));
+    }
+
+    #[test]
+    fn test_relative_row_references() {
+        let markdown = "\
+| Item | Price | Quantity | Total | Running |
+|------|-------|----------|-------|---------|
+| Apples | 2 | 3 | ? <!-- =B@*C@ --> | ? <!-- =D@ --> |
+| Bananas | 1 | 5 | ? <!-- =B@*C@ --> | ? <!-- =R[-1]C+RC[-1] --> |
+| Cherries | 4 | 2 | ? <!-- =RC[-2]*RC[-1] --> | ? <!-- =R[-1]C+RC[-1] --> |
+| Dates | 1 | 1 | ? <!-- =RC --> | |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert!(result.contains("| Dates | 1 | 1 | #NAME? <!-- =RC --> | |"));
+        assert!(result.contains("| Apples | 2 | 3 | 6 <!-- =B@*C@ --> | 6 <!--"));
+        assert!(result.contains("| Bananas | 1 | 5 | 5 <!-- =B@*C@ --> | 11 <!--"));
+        assert!(result.contains("| Cherries | 4 | 2 | 8 <!-- =RC[-2]*RC[-1] --> | 19 <!--"));
+    }
+
+    #[test]
+    fn test_column_formula_from_header() {
+        let markdown = "\
+| Item | Price | Quantity | Total <!-- !D !col=B*C --> |
+|------|-------|----------|----------------------------|
+| Apples | 2 | 3 | |
+| Bananas | 1 | 5 | 0 |
+| Total | | | 1 <!-- =SUM(D2:D3) --> |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert_eq!(
+            result,
+            markdown
+                .replace("| 3 | |", "| 3 | 6 |")
+                .replace("| 5 | 0 |", "| 5 | 5 |")
+                .replace("| 1 <!-- =SUM", "| 11 <!-- =SUM")
+        );
+        assert_eq!(recalculate_markdown(&result, &RecalcOptions::default()), result);
+    }
+
+    #[test]
+    fn test_footer_sums_rows_above() {
+        let markdown = "\
+| Item | Price | Quantity | Total <!-- !col=B*C --> |
+|------|-------|----------|-------------------------|
+| Apples | 2 | 3 | |
+| Bananas | 1 | 5 | |
+| Cherries | 4 | 2 | |
+| Total | | ? <!-- =SUM(C:above) --> | ? <!-- =SUM(above) --> |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert!(result.ends_with("| Total | | 10 <!-- =SUM(C:above) --> | 19 <!-- =SUM(above) --> |\n"), "{}", result);
+    }
+
+    #[test]
+    fn test_structured_references() {
+        let markdown = "\
+<!-- !table: fruit -->
+| Item | Unit Price | Quantity | Total <!-- !col=[@Unit Price]*[@Quantity] --> |
+|------|------------|----------|------------------------------------------------|
+| Apples | 2 | 3 | |
+| Bananas | 1 | 5 | |
+
+| Summary | Value |
+|---------|-------|
+| Revenue | ? <!-- =SUM(fruit[Total]) --> |
+| Missing | ? <!-- =SUM(fruit[Cost]) --> |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert!(result.contains("| Apples | 2 | 3 | 6 |"));
+        assert!(result.contains("| Revenue | 11 <!--"));
+        assert!(result.contains("| Missing | #REF! <!--"));
+    }
+
+    #[test]
+    fn test_array_results_spill_down() {
+        let markdown = "\
+| Item | Price | Quantity | Total |
+|------|-------|----------|-------|
+| Apples | 2 | 3 | ? <!-- =B2:B4*C2:C4 --> |
+| Bananas | 1 | 5 | |
+| Cherries | 4 | 2 | |
+
+| n | Blocked |
+|---|---------|
+| ? <!-- =SEQUENCE(2) --> | ? <!-- =SEQUENCE(2) --> |
+| | mine |
+";
+        let result = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert!(result.contains("| Apples | 2 | 3 | 6 <!-- =B2:B4*C2:C4 --> |"), "{}", result);
+        assert!(result.contains("| Bananas | 1 | 5 | 5 <!-- spill --> |"));
+        assert!(result.contains("| Cherries | 4 | 2 | 8 <!-- spill --> |"));
+        assert!(result.contains("| 1 <!-- =SEQUENCE(2) --> | #SPILL! <!-- =SEQUENCE(2) --> |"));
+        assert!(result.contains("| 2 <!-- spill --> | mine |"));
+        assert_eq!(recalculate_markdown(&result, &RecalcOptions::default()), result);
+
+        // Shrinking the array clears the cells it no longer covers.
+        let shrunk = recalculate_markdown(&result.replace("B2:B4*C2:C4", "B2:B3*C2:C3"), &RecalcOptions::default());
+        assert!(shrunk.contains("| Bananas | 1 | 5 | 5 <!-- spill --> |"));
+        assert!(shrunk.contains("| Cherries | 4 | 2 |"), "{}", shrunk);
+        assert!(!shrunk.contains("| Cherries | 4 | 2 | 8"));
+    }
+
+    #[test]
+    fn test_spill_grows_and_shrinks_table() {
+        let markdown = "\
+| n    | Square |
+|------|-------:|
+| x | ? <!-- =SEQUENCE(3, 2) --> |
+
+Done.
+";
+        let grown = recalculate_markdown(markdown, &RecalcOptions::default());
+        assert_eq!(
+            grown,
+            "\
+| n    | Square | <!-- generated --> |
+|------|-------:|--------|
+| x | 1 <!-- =SEQUENCE(3, 2) --> | 2 <!-- spill --> |
+| <!-- generated --> | 3 <!-- spill --> | 4 <!-- spill --> |
+| <!-- generated --> | 5 <!-- spill --> | 6 <!-- spill --> |
+
+Done.
+"
+        );
+        assert_eq!(recalculate_markdown(&grown, &RecalcOptions::default()), grown);
+
+        let shrunk = recalculate_markdown(&grown.replace("SEQUENCE(3, 2)", "SEQUENCE(2)"), &RecalcOptions::default());
+        assert_eq!(
+            shrunk,
+            "\
+| n    | Square |
+|------|-------:|
+| x | 1 <!-- =SEQUENCE(2) --> |
+| <!-- generated --> | 2 <!-- spill --> |
+
+Done.
+"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+// sort.rs
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::directives::table_names;
+use crate::evaluator::{compare_values, Value, Workbook};
+use crate::fix_refs::{refresh_markers, rewrite_tables, Moves};
+use crate::formula::column_index;
+use crate::table_parser::{is_delimiter_row, is_table_line, TableParser};
+use crate::table_writer::render_document;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortOptions {
+    /// One based table number, or a table name.
+    pub table: String,
+    /// Column letters, or the header text of the column.
+    pub by: String,
+    pub descending: bool,
+}
+
+pub fn find_table(selector: &str, names: &[String]) -> Option<usize> {
+    match selector.parse::<usize>() {
+        Ok(number) => number.checked_sub(1).filter(|i| *i < names.len()),
+        Err(_) => names.iter().position(|n| n.eq_ignore_ascii_case(selector)),
+    }
+}
+
+/// Sorts the body rows of one table by a column, keeping the header and any
+/// trailing summary rows in place. Formula references into the table are
+/// rewritten to follow the rows, and the markers are renumbered.
+pub fn sort_markdown(markdown: &str, options: &SortOptions, today: f64) -> Result<String, String> {
+    let originals = TableParser::parse_tables(markdown);
+    let names = table_names(markdown, &originals);
+    let index = find_table(&options.table, &names).ok_or_else(|| format!("no table '{}'", options.table))?;
+
+    let workbook = Workbook::from_tables(&originals, &names);
+    let values = workbook.evaluate(today);
+    let sheet = &workbook.sheets[index];
+    let col = column_index(&options.by)
+        .filter(|c| *c < sheet.col_count())
+        .or_else(|| sheet.column_by_name(&options.by))
+        .ok_or_else(|| format!("no column '{}' in table '{}'", options.by, sheet.name))?;
+
+    let key = |row: usize| match values[index][row].get(col) {
+        Some(Some(value)) => value.clone(),
+        _ => sheet
+            .cell(row, col)
+            .map_or(Value::Empty, |c| Value::from_cell_text(&c.text)),
+    };
+    let body_end = sheet.summary_start();
+    let mut order: Vec<usize> = (1..body_end).collect();
+    order.sort_by(|a, b| {
+        let (a, b) = (key(*a), key(*b));
+        // Blank cells go last in either direction.
+        match (a == Value::Empty, b == Value::Empty) {
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            _ if options.descending => compare_values(&b, &a),
+            _ => compare_values(&a, &b),
+        }
+    });
+
+    // Move the source lines first so every row keeps its own formatting,
+    // then fix the formulas of the reordered document.
+    let table = &originals[index];
+    let reordered = format!(
+        "{}{}{}",
+        &markdown[..table.start_offset],
+        reorder_rows(&markdown[table.start_offset..table.end_offset], &order),
+        &markdown[table.end_offset..]
+    );
+
+    let originals = TableParser::parse_tables(&reordered);
+    let mut tables = originals.clone();
+
+    // Summary rows stay where they are, but their formulas and the
+    // references to them are rewritten like those of the body rows.
+    let mut rows: HashMap<usize, usize> = order.iter().enumerate().map(|(new, old)| (*old, new + 1)).collect();
+    rows.extend((body_end..sheet.row_count()).map(|row| (row, row)));
+    let mut moves = vec![Moves::default(); tables.len()];
+    moves[index] = Moves::from_rows(rows);
+    rewrite_tables(&mut tables, &names, &moves);
+    refresh_markers(&mut tables[index]);
+
+    Ok(render_document(&reordered, &originals, &tables))
+}
+
+/// Reorders the body row lines of a table's source so that body row `i + 1`
+/// becomes the line of body row `order[i]`. Other lines stay in place.
+fn reorder_rows(source: &str, order: &[usize]) -> String {
+    let lines: Vec<&str> = source.split('\n').collect();
+    let rows: Vec<usize> = (0..lines.len())
+        .filter(|&i| is_table_line(lines[i]) && !lines[i].trim().is_empty() && !is_delimiter_row(lines[i]))
+        .collect();
+    let mut out = lines.clone();
+    for (new, old) in order.iter().enumerate() {
+        out[rows[new + 1]] = lines[rows[*old]];
+    }
+    out.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recalc::{recalculate_markdown, RecalcOptions};
+
+    fn sort(markdown: &str, table: &str, by: &str, descending: bool) -> String {
+        let options = SortOptions {
+            table: table.to_string(),
+            by: by.to_string(),
+            descending,
+        };
+        sort_markdown(markdown, &options, 0.0).unwrap()
+    }
+
+    #[test]
+    fn test_sort_keeps_formulas_and_summary_rows() {
+        let markdown = "\
+| Item <!-- !A --> | Price <!-- !B --> | Quantity <!-- !C --> | Total <!-- !D --> |
+|---|---|---|---|
+| Apples <!-- !2 --> | 2 | 3 | 6 <!-- !=B2*C2 --> |
+| Bananas <!-- !3 --> | 1 | 5 | 5 <!-- !=B3*C3 --> |
+| Cherries <!-- !4 --> | 4 | 2 | 8 <!-- !=$B$4*C4 --> |
+| Total <!-- !5 --> | 6 <!-- !=D2 --> | | 19 <!-- !=SUM(D:above) --> |
+
+| Best | Value |
+|---|---|
+| Cherries | 8 <!-- =Table1!D4 --> |
+| All | 19 <!-- =Table1!D5 --> |
+";
+        let sorted = sort(markdown, "1", "Total", true);
+        assert_eq!(
+            sorted,
+            "\
+| Item <!-- !A1 --> | Price <!-- !B1 --> | Quantity <!-- !C1 --> | Total <!-- !D1 --> |
+|---|---|---|---|
+| Cherries <!-- !A2 --> | 4 | 2 | 8 <!-- !=$B$2*C2 --> |
+| Apples <!-- !A3 --> | 2 | 3 | 6 <!-- !=B3*C3 --> |
+| Bananas <!-- !A4 --> | 1 | 5 | 5 <!-- !=B4*C4 --> |
+| Total <!-- !A5 --> | 6 <!-- !=D3 --> | | 19 <!-- !=SUM(D:above) --> |
+
+| Best | Value |
+|---|---|
+| Cherries | 8 <!-- =Table1!D2 --> |
+| All | 19 <!-- =Table1!D5 --> |
+"
+        );
+        assert_eq!(recalculate_markdown(&sorted, &RecalcOptions::default()), sorted);
+    }
+
+    #[test]
+    fn test_sort_by_letter_with_blanks_last() {
+        let markdown = "| Name | n |\n|---|---|\n| b | |\n| c | 1 |\n| a | 2 |\n";
+        assert_eq!(sort(markdown, "Table1", "A", false), "| Name | n |\n|---|---|\n| a | 2 |\n| b | |\n| c | 1 |\n");
+        assert_eq!(sort(markdown, "1", "B", true), "| Name | n |\n|---|---|\n| a | 2 |\n| c | 1 |\n| b | |\n");
+        let missing = SortOptions { table: "2".to_string(), by: "A".to_string(), descending: false };
+        assert!(sort_markdown(markdown, &missing, 0.0).is_err());
+    }
+}
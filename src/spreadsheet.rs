@@ -0,0 +1,588 @@
+// spreadsheet.rs
+
+use std::io::{Cursor, Write};
+
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::dates;
+use crate::directives::table_names;
+use crate::evaluator::{format_number, Value, Workbook};
+use crate::formula::{column_name, parse_column_formula, parse_formula_at, BinaryOp, CellRef, Expr, Reference, UnaryOp};
+use crate::table_parser::TableParser;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpreadsheetFormat {
+    /// Office Open XML, as written by Excel.
+    Xlsx,
+    /// OpenDocument, as written by LibreOffice.
+    Ods,
+}
+
+impl SpreadsheetFormat {
+    /// The format a file name asks for by its extension.
+    pub fn from_path(path: &str) -> Option<SpreadsheetFormat> {
+        let lower = path.to_ascii_lowercase();
+        if lower.ends_with(".xlsx") {
+            Some(SpreadsheetFormat::Xlsx)
+        } else if lower.ends_with(".ods") {
+            Some(SpreadsheetFormat::Ods)
+        } else {
+            None
+        }
+    }
+}
+
+/// Functions that spreadsheets store under a prefix because they are newer
+/// than the file format.
+const NEWER_FUNCTIONS: [&str; 16] = [
+    "CONCAT",
+    "FILTER",
+    "MAXIFS",
+    "MINIFS",
+    "MODE.SNGL",
+    "PERCENTILE.INC",
+    "QUARTILE.INC",
+    "RANK.EQ",
+    "SEQUENCE",
+    "SORT",
+    "STDEV.P",
+    "STDEV.S",
+    "UNIQUE",
+    "VAR.P",
+    "VAR.S",
+    "XLOOKUP",
+];
+
+/// The largest row count of a worksheet.
+const MAX_ROWS: usize = 1_048_576;
+
+struct ExportCell {
+    value: Value,
+    formula: Option<String>,
+}
+
+/// Exports every table of `markdown` as a worksheet of an xlsx or ods file,
+/// with formulas translated to the spreadsheet's own syntax and their values
+/// as of `today` stored alongside. Each table starts at A1 of its sheet, so
+/// A1 references keep pointing at the same cells.
+pub fn export_workbook(markdown: &str, format: SpreadsheetFormat, today: f64) -> Result<Vec<u8>, String> {
+    let tables = TableParser::parse_tables(markdown);
+    if tables.is_empty() {
+        return Err("no tables to export".to_string());
+    }
+    let names = table_names(markdown, &tables);
+    let workbook = Workbook::from_tables(&tables, &names);
+    let results = workbook.evaluate(today);
+
+    let sheet_names: Vec<String> = workbook
+        .sheets
+        .iter()
+        .enumerate()
+        .map(|(i, sheet)| sheet_name(&sheet.name, i))
+        .collect();
+    let sheets: Vec<Vec<Vec<ExportCell>>> = workbook
+        .sheets
+        .iter()
+        .enumerate()
+        .map(|(index, sheet)| {
+            let translator = Translator { workbook: &workbook, sheet_names: &sheet_names, format };
+            sheet
+                .cells
+                .iter()
+                .enumerate()
+                .map(|(r, row)| {
+                    row.iter()
+                        .enumerate()
+                        .map(|(c, cell)| {
+                            let computed = results[index].get(r).and_then(|row| row.get(c)).cloned().flatten();
+                            let formula = cell.formula.as_deref().and_then(|source| {
+                                let parsed = if cell.column_formula {
+                                    parse_column_formula(source, r, c)
+                                } else {
+                                    parse_formula_at(source, r, c)
+                                };
+                                parsed.ok().map(|expr| translator.expr(&expr, index))
+                            });
+                            ExportCell {
+                                value: computed
+                                    .map(Value::scalar)
+                                    .unwrap_or_else(|| Value::from_cell_text(&cell.text.replace("\\|", "|"))),
+                                formula,
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+
+    match format {
+        SpreadsheetFormat::Xlsx => xlsx(&sheet_names, &sheets),
+        SpreadsheetFormat::Ods => ods(&sheet_names, &sheets),
+    }
+}
+
+/// A table name as a sheet name: without the characters spreadsheets reject
+/// and at most 31 characters long.
+fn sheet_name(name: &str, index: usize) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| if matches!(c, '[' | ']' | ':' | '*' | '?' | '/' | '\\') { '_' } else { c })
+        .take(31)
+        .collect();
+    if cleaned.trim().is_empty() {
+        format!("Sheet{}", index + 1)
+    } else {
+        cleaned
+    }
+}
+
+/// Writes parsed formulas in Excel or OpenFormula syntax.
+struct Translator<'a> {
+    workbook: &'a Workbook,
+    sheet_names: &'a [String],
+    format: SpreadsheetFormat,
+}
+
+impl Translator<'_> {
+    fn expr(&self, expr: &Expr, sheet: usize) -> String {
+        let separator = match self.format {
+            SpreadsheetFormat::Xlsx => ",",
+            SpreadsheetFormat::Ods => ";",
+        };
+        match expr {
+            Expr::Number(n) => n.to_string(),
+            Expr::Text(s) => format!("\"{}\"", s.replace('"', "\"\"")),
+            Expr::Bool(b) => match self.format {
+                SpreadsheetFormat::Xlsx => if *b { "TRUE" } else { "FALSE" }.to_string(),
+                SpreadsheetFormat::Ods => if *b { "TRUE()" } else { "FALSE()" }.to_string(),
+            },
+            Expr::Array(rows) => {
+                let row_separator = match self.format {
+                    SpreadsheetFormat::Xlsx => ";",
+                    SpreadsheetFormat::Ods => "|",
+                };
+                let rows: Vec<String> = rows
+                    .iter()
+                    .map(|r| r.iter().map(|e| self.expr(e, sheet)).collect::<Vec<_>>().join(separator))
+                    .collect();
+                format!("{{{}}}", rows.join(row_separator))
+            }
+            Expr::Reference { table, reference } => {
+                let target = match table {
+                    Some(name) => match self.workbook.sheet_index(name) {
+                        Some(index) => index,
+                        None => return "#REF!".to_string(),
+                    },
+                    None => sheet,
+                };
+                self.reference(reference, target, table.is_some())
+            }
+            Expr::Name(name) => name.clone(),
+            Expr::Error(code) => code.clone(),
+            Expr::Unary(op, inner) => {
+                let sign = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Plus => "+",
+                };
+                format!("{}{}", sign, self.operand(inner, sheet, matches!(**inner, Expr::Binary(..))))
+            }
+            Expr::Percent(inner) => {
+                let wrap = matches!(**inner, Expr::Binary(..) | Expr::Unary(..));
+                format!("{}%", self.operand(inner, sheet, wrap))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                // Operators are left associative, so an equal operator on
+                // the right needs parentheses too.
+                let wrap = |side: &Expr, right: bool| match side {
+                    Expr::Binary(inner, ..) => {
+                        precedence(*inner) < precedence(*op) || (right && precedence(*inner) == precedence(*op))
+                    }
+                    _ => false,
+                };
+                format!(
+                    "{}{}{}",
+                    self.operand(lhs, sheet, wrap(lhs, false)),
+                    operator(*op),
+                    self.operand(rhs, sheet, wrap(rhs, true))
+                )
+            }
+            Expr::Call(name, args) => {
+                let name = match (self.format, NEWER_FUNCTIONS.contains(&name.as_str())) {
+                    (SpreadsheetFormat::Xlsx, true) if name == "SORT" || name == "FILTER" => format!("_xlfn._xlws.{}", name),
+                    (SpreadsheetFormat::Xlsx, true) => format!("_xlfn.{}", name),
+                    (SpreadsheetFormat::Ods, true) => format!("COM.MICROSOFT.{}", name),
+                    (_, false) => name.clone(),
+                };
+                let args: Vec<String> = args.iter().map(|a| self.expr(a, sheet)).collect();
+                format!("{}({})", name, args.join(separator))
+            }
+        }
+    }
+
+    fn operand(&self, expr: &Expr, sheet: usize, wrap: bool) -> String {
+        if wrap {
+            format!("({})", self.expr(expr, sheet))
+        } else {
+            self.expr(expr, sheet)
+        }
+    }
+
+    /// A cell or range of sheet `target`. Whole columns and structured
+    /// references become the body rows of the table, as mdcalc reads them.
+    fn reference(&self, reference: &Reference, target: usize, qualified: bool) -> String {
+        let sheet = &self.workbook.sheets[target];
+        let body = |first: usize, last: usize| match sheet.row_count() {
+            0 | 1 => None,
+            rows => Some((
+                CellRef { col: first, row: 1, col_absolute: false, row_absolute: false },
+                CellRef { col: last, row: rows - 1, col_absolute: false, row_absolute: false },
+            )),
+        };
+        let range = match reference {
+            Reference::Cell(cell) => Some((*cell, *cell)),
+            Reference::Range(start, end) => Some((*start, *end)),
+            Reference::Columns(first, last) => body(*first, *last),
+            Reference::Structured { column, row } => match (sheet.column_by_name(column), row) {
+                (Some(col), Some(row)) => {
+                    let cell = CellRef { col, row: *row, col_absolute: false, row_absolute: false };
+                    Some((cell, cell))
+                }
+                (Some(col), None) => body(col, col),
+                (None, _) => None,
+            },
+        };
+        let Some((start, end)) = range.filter(|(s, e)| s.row.max(e.row) < MAX_ROWS && s.col.max(e.col) < 16_384) else {
+            return "#REF!".to_string();
+        };
+
+        let name = self.sheet_names[target].replace('\'', "''");
+        match self.format {
+            SpreadsheetFormat::Xlsx => {
+                let prefix = if qualified { format!("'{}'!", name) } else { String::new() };
+                if start == end {
+                    format!("{}{}", prefix, start)
+                } else {
+                    format!("{}{}:{}", prefix, start, end)
+                }
+            }
+            SpreadsheetFormat::Ods => {
+                let prefix = if qualified { format!("$'{}'", name) } else { String::new() };
+                if start == end {
+                    format!("[{}.{}]", prefix, start)
+                } else {
+                    format!("[{}.{}:.{}]", prefix, start, end)
+                }
+            }
+        }
+    }
+}
+
+fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 1,
+        BinaryOp::Concat => 2,
+        BinaryOp::Add | BinaryOp::Sub => 3,
+        BinaryOp::Mul | BinaryOp::Div => 4,
+        BinaryOp::Pow => 5,
+    }
+}
+
+fn operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Pow => "^",
+        BinaryOp::Concat => "&",
+        BinaryOp::Eq => "=",
+        BinaryOp::Ne => "<>",
+        BinaryOp::Lt => "<",
+        BinaryOp::Le => "<=",
+        BinaryOp::Gt => ">",
+        BinaryOp::Ge => ">=",
+    }
+}
+
+fn xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n";
+const MAIN_NS: &str = "http://schemas.openxmlformats.org/spreadsheetml/2006/main";
+const REL_NS: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+
+fn xlsx(names: &[String], sheets: &[Vec<Vec<ExportCell>>]) -> Result<Vec<u8>, String> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+    let mut types = format!(
+        "{}<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+<Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\
+<Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+<Override PartName=\"/xl/workbook.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml\"/>\
+<Override PartName=\"/xl/styles.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml\"/>",
+        XML_HEADER
+    );
+    for i in 1..=sheets.len() {
+        types.push_str(&format!(
+            "<Override PartName=\"/xl/worksheets/sheet{}.xml\" \
+ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>",
+            i
+        ));
+    }
+    types.push_str("</Types>");
+    add(&mut zip, "[Content_Types].xml", types.as_bytes())?;
+
+    add(
+        &mut zip,
+        "_rels/.rels",
+        format!(
+            "{}<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+<Relationship Id=\"rId1\" Type=\"{}/officeDocument\" Target=\"xl/workbook.xml\"/></Relationships>",
+            XML_HEADER, REL_NS
+        )
+        .as_bytes(),
+    )?;
+
+    // Recalculate on open, as the stored values are only mdcalc's.
+    let mut workbook = format!("{}<workbook xmlns=\"{}\" xmlns:r=\"{}\"><sheets>", XML_HEADER, MAIN_NS, REL_NS);
+    let mut rels = format!(
+        "{}<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">",
+        XML_HEADER
+    );
+    for (i, name) in names.iter().enumerate() {
+        workbook.push_str(&format!("<sheet name=\"{}\" sheetId=\"{}\" r:id=\"rId{}\"/>", xml(name), i + 1, i + 1));
+        rels.push_str(&format!(
+            "<Relationship Id=\"rId{}\" Type=\"{}/worksheet\" Target=\"worksheets/sheet{}.xml\"/>",
+            i + 1,
+            REL_NS,
+            i + 1
+        ));
+    }
+    workbook.push_str("</sheets><calcPr fullCalcOnLoad=\"1\"/></workbook>");
+    rels.push_str(&format!(
+        "<Relationship Id=\"rId{}\" Type=\"{}/styles\" Target=\"styles.xml\"/></Relationships>",
+        names.len() + 1,
+        REL_NS
+    ));
+    add(&mut zip, "xl/workbook.xml", workbook.as_bytes())?;
+    add(&mut zip, "xl/_rels/workbook.xml.rels", rels.as_bytes())?;
+
+    // Style 1 shows dates the way mdcalc writes them.
+    add(
+        &mut zip,
+        "xl/styles.xml",
+        format!(
+            "{}<styleSheet xmlns=\"{}\">\
+<numFmts count=\"1\"><numFmt numFmtId=\"164\" formatCode=\"yyyy-mm-dd\"/></numFmts>\
+<fonts count=\"1\"><font><sz val=\"11\"/><name val=\"Calibri\"/></font></fonts>\
+<fills count=\"2\"><fill><patternFill patternType=\"none\"/></fill><fill><patternFill patternType=\"gray125\"/></fill></fills>\
+<borders count=\"1\"><border><left/><right/><top/><bottom/><diagonal/></border></borders>\
+<cellStyleXfs count=\"1\"><xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\"/></cellStyleXfs>\
+<cellXfs count=\"2\"><xf numFmtId=\"0\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\"/>\
+<xf numFmtId=\"164\" fontId=\"0\" fillId=\"0\" borderId=\"0\" xfId=\"0\" applyNumberFormat=\"1\"/></cellXfs>\
+</styleSheet>",
+            XML_HEADER, MAIN_NS
+        )
+        .as_bytes(),
+    )?;
+
+    for (i, rows) in sheets.iter().enumerate() {
+        let mut sheet = format!("{}<worksheet xmlns=\"{}\"><sheetData>", XML_HEADER, MAIN_NS);
+        for (r, row) in rows.iter().enumerate() {
+            sheet.push_str(&format!("<row r=\"{}\">", r + 1));
+            for (c, cell) in row.iter().enumerate() {
+                sheet.push_str(&xlsx_cell(&format!("{}{}", column_name(c), r + 1), cell));
+            }
+            sheet.push_str("</row>");
+        }
+        sheet.push_str("</sheetData></worksheet>");
+        add(&mut zip, &format!("xl/worksheets/sheet{}.xml", i + 1), sheet.as_bytes())?;
+    }
+    Ok(zip.finish().map_err(|e| e.to_string())?.into_inner())
+}
+
+/// Adds a deflated file to the archive.
+fn add(zip: &mut ZipWriter<Cursor<Vec<u8>>>, name: &str, data: &[u8]) -> Result<(), String> {
+    zip.start_file(name, SimpleFileOptions::default()).map_err(|e| e.to_string())?;
+    zip.write_all(data).map_err(|e| e.to_string())
+}
+
+fn xlsx_cell(address: &str, cell: &ExportCell) -> String {
+    let formula = cell.formula.as_ref().map_or(String::new(), |f| format!("<f>{}</f>", xml(f)));
+    let (attributes, value) = match (&cell.value, &cell.formula) {
+        (Value::Empty, None) => return String::new(),
+        (Value::Empty, Some(_)) => (String::new(), String::new()),
+        (Value::Number(n), _) => (String::new(), format!("<v>{}</v>", n)),
+        (Value::Date(serial), _) => (" s=\"1\"".to_string(), format!("<v>{}</v>", serial)),
+        (Value::Bool(b), _) => (" t=\"b\"".to_string(), format!("<v>{}</v>", u8::from(*b))),
+        (Value::Error(e), _) => (" t=\"e\"".to_string(), format!("<v>{}</v>", xml(&e.to_string()))),
+        (Value::Text(s), Some(_)) => (" t=\"str\"".to_string(), format!("<v>{}</v>", xml(s))),
+        (Value::Text(s), None) => (" t=\"inlineStr\"".to_string(), format!("<is><t>{}</t></is>", xml(s))),
+        (Value::Array(_), _) => unreachable!("cell values are scalars"),
+    };
+    format!("<c r=\"{}\"{}>{}{}</c>", address, attributes, formula, value)
+}
+
+fn ods(names: &[String], sheets: &[Vec<Vec<ExportCell>>]) -> Result<Vec<u8>, String> {
+    const MIMETYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // The mimetype must come first, stored, for the file to be recognised.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file("mimetype", stored).map_err(|e| e.to_string())?;
+    zip.write_all(MIMETYPE.as_bytes()).map_err(|e| e.to_string())?;
+    add(
+        &mut zip,
+        "META-INF/manifest.xml",
+        format!(
+            "{}<manifest:manifest xmlns:manifest=\"urn:oasis:names:tc:opendocument:xmlns:manifest:1.0\" manifest:version=\"1.2\">\
+<manifest:file-entry manifest:full-path=\"/\" manifest:version=\"1.2\" manifest:media-type=\"{}\"/>\
+<manifest:file-entry manifest:full-path=\"content.xml\" manifest:media-type=\"text/xml\"/>\
+</manifest:manifest>",
+            XML_HEADER, MIMETYPE
+        )
+        .as_bytes(),
+    )?;
+
+    let mut content = format!(
+        "{}<office:document-content \
+xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\" \
+xmlns:style=\"urn:oasis:names:tc:opendocument:xmlns:style:1.0\" \
+xmlns:text=\"urn:oasis:names:tc:opendocument:xmlns:text:1.0\" \
+xmlns:table=\"urn:oasis:names:tc:opendocument:xmlns:table:1.0\" \
+xmlns:number=\"urn:oasis:names:tc:opendocument:xmlns:datastyle:1.0\" \
+xmlns:of=\"urn:oasis:names:tc:opendocument:xmlns:of:1.2\" office:version=\"1.2\">\
+<office:automatic-styles>\
+<number:date-style style:name=\"N1\"><number:year number:style=\"long\"/><number:text>-</number:text>\
+<number:month number:style=\"long\"/><number:text>-</number:text><number:day number:style=\"long\"/></number:date-style>\
+<style:style style:name=\"date\" style:family=\"table-cell\" style:data-style-name=\"N1\"/>\
+</office:automatic-styles><office:body><office:spreadsheet>",
+        XML_HEADER
+    );
+    for (name, rows) in names.iter().zip(sheets) {
+        content.push_str(&format!("<table:table table:name=\"{}\">", xml(name)));
+        for row in rows {
+            content.push_str("<table:table-row>");
+            for cell in row {
+                content.push_str(&ods_cell(cell));
+            }
+            content.push_str("</table:table-row>");
+        }
+        content.push_str("</table:table>");
+    }
+    content.push_str("</office:spreadsheet></office:body></office:document-content>");
+    add(&mut zip, "content.xml", content.as_bytes())?;
+    Ok(zip.finish().map_err(|e| e.to_string())?.into_inner())
+}
+
+fn ods_cell(cell: &ExportCell) -> String {
+    let formula = cell
+        .formula
+        .as_ref()
+        .map_or(String::new(), |f| format!(" table:formula=\"of:={}\"", xml(f)));
+    let value = match &cell.value {
+        Value::Empty if cell.formula.is_none() => return "<table:table-cell/>".to_string(),
+        Value::Empty => String::new(),
+        // Like LibreOffice, store errors as their text.
+        Value::Error(_) => " office:value-type=\"string\"".to_string(),
+        Value::Number(n) => format!(" office:value-type=\"float\" office:value=\"{}\"", n),
+        Value::Date(serial) => format!(
+            " table:style-name=\"date\" office:value-type=\"date\" office:date-value=\"{}\"",
+            dates::format_date(*serial, "%Y-%m-%dT%H:%M:%S")
+        ),
+        Value::Bool(b) => format!(" office:value-type=\"boolean\" office:boolean-value=\"{}\"", b),
+        Value::Text(_) => " office:value-type=\"string\"".to_string(),
+        Value::Array(_) => unreachable!("cell values are scalars"),
+    };
+    let text = match &cell.value {
+        Value::Number(n) => format_number(*n),
+        other => other.to_string(),
+    };
+    format!("<table:table-cell{}{}><text:p>{}</text:p></table:table-cell>", formula, value, xml(&text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKDOWN: &str = "\
+<!-- !table: fruit -->
+| Item | Price | Qty | Total <!-- !col=B*C --> |
+|------|-------|-----|-------|
+| Pears | 3 | 2 | 6 |
+| Plums | 1.5 | 4 | 6 |
+| Sum | | | 12 <!-- =SUM(D:above) --> |
+
+| Check |
+|-------|
+| <!-- =IF(fruit!D4>10, \"big\", \"small\") & \"!\" --> |
+| <!-- =-(1+2)^2 --> |
+";
+
+    fn translate(format: SpreadsheetFormat, table: usize, row: usize, col: usize) -> Option<String> {
+        let tables = TableParser::parse_tables(MARKDOWN);
+        let names = table_names(MARKDOWN, &tables);
+        let workbook = Workbook::from_tables(&tables, &names);
+        let sheet_names: Vec<String> = workbook.sheets.iter().map(|s| s.name.clone()).collect();
+        let translator = Translator { workbook: &workbook, sheet_names: &sheet_names, format };
+        let cell = workbook.sheets[table].cell(row, col)?;
+        let source = cell.formula.as_deref()?;
+        let expr = if cell.column_formula {
+            parse_column_formula(source, row, col)
+        } else {
+            parse_formula_at(source, row, col)
+        };
+        Some(translator.expr(&expr.ok()?, table))
+    }
+
+    #[test]
+    fn test_translate_formulas() {
+        use SpreadsheetFormat::*;
+        assert_eq!(translate(Xlsx, 0, 2, 3).as_deref(), Some("B3*C3"));
+        assert_eq!(translate(Xlsx, 0, 3, 3).as_deref(), Some("SUM(D$2:D3)"));
+        assert_eq!(
+            translate(Xlsx, 1, 1, 0).as_deref(),
+            Some("IF('fruit'!D4>10,\"big\",\"small\")&\"!\"")
+        );
+        assert_eq!(translate(Xlsx, 1, 2, 0).as_deref(), Some("-(1+2)^2"));
+        assert_eq!(translate(Ods, 0, 3, 3).as_deref(), Some("SUM([.D$2:.D3])"));
+        assert_eq!(
+            translate(Ods, 1, 1, 0).as_deref(),
+            Some("IF([$'fruit'.D4]>10;\"big\";\"small\")&\"!\"")
+        );
+    }
+
+    /// The contents of one file of a zip archive.
+    fn entry(archive: &[u8], name: &str) -> String {
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        std::io::read_to_string(archive.by_name(name).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_export_workbook() {
+        let xlsx = export_workbook(MARKDOWN, SpreadsheetFormat::Xlsx, 0.0).unwrap();
+        let text = entry(&xlsx, "xl/workbook.xml");
+        assert!(text.contains("<sheet name=\"fruit\" sheetId=\"1\" r:id=\"rId1\"/><sheet name=\"Table1\""));
+        let text = entry(&xlsx, "xl/worksheets/sheet1.xml");
+        assert!(text.contains("<c r=\"A1\" t=\"inlineStr\"><is><t>Item</t></is></c>"));
+        assert!(text.contains("<c r=\"B3\"><v>1.5</v></c>"));
+        assert!(text.contains("<c r=\"D4\"><f>SUM(D$2:D3)</f><v>12</v></c>"));
+        let text = entry(&xlsx, "xl/worksheets/sheet2.xml");
+        assert!(text.contains("<c r=\"A2\" t=\"str\"><f>IF('fruit'!D4&gt;10,&quot;big&quot;,&quot;small&quot;)&amp;&quot;!&quot;</f><v>big!</v></c>"));
+
+        let ods = export_workbook(MARKDOWN, SpreadsheetFormat::Ods, 0.0).unwrap();
+        assert_eq!(&ods[30..38], b"mimetype");
+        assert_eq!(entry(&ods, "mimetype"), "application/vnd.oasis.opendocument.spreadsheet");
+        let text = entry(&ods, "content.xml");
+        assert!(text.contains(
+            "<table:table-cell table:formula=\"of:=SUM([.D$2:.D3])\" office:value-type=\"float\" office:value=\"12\"><text:p>12</text:p></table:table-cell>"
+        ));
+
+        assert!(export_workbook("No tables.\n", SpreadsheetFormat::Xlsx, 0.0).is_err());
+    }
+}
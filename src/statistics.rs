@@ -0,0 +1,145 @@
+// statistics.rs
+
+use std::cmp::Ordering;
+
+use crate::evaluator::{CellError, Value};
+use crate::functions::{arity, numbers};
+
+fn sorted(mut values: Vec<f64>) -> Vec<f64> {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    values
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Variance of `values`; `sample` divides by n - 1 instead of n.
+fn variance(values: &[f64], sample: bool) -> Result<f64, CellError> {
+    let n = values.len();
+    if n == 0 || (sample && n < 2) {
+        return Err(CellError::Div0);
+    }
+    let m = mean(values);
+    let squares: f64 = values.iter().map(|v| (v - m).powi(2)).sum();
+    Ok(squares / if sample { (n - 1) as f64 } else { n as f64 })
+}
+
+/// Inclusive percentile with linear interpolation (PERCENTILE.INC).
+fn percentile(values: &[f64], k: f64) -> Result<f64, CellError> {
+    if values.is_empty() || !(0.0..=1.0).contains(&k) {
+        return Err(CellError::Num);
+    }
+    let sorted = sorted(values.to_vec());
+    let position = k * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    Ok(sorted[lower] + (sorted[upper] - sorted[lower]) * (position - lower as f64))
+}
+
+fn kth(args: &[Value], largest: bool) -> Result<Value, CellError> {
+    arity(args, 2, 2)?;
+    let values = sorted(numbers(&args[..1])?);
+    let k = args[1].as_number()?.ceil();
+    if k < 1.0 || k as usize > values.len() {
+        return Err(CellError::Num);
+    }
+    let k = k as usize;
+    Ok(Value::Number(if largest { values[values.len() - k] } else { values[k - 1] }))
+}
+
+fn mode(values: &[f64]) -> Result<f64, CellError> {
+    // The most frequent value; ties go to the value seen first.
+    let mut best: Option<(f64, usize)> = None;
+    for (i, v) in values.iter().enumerate() {
+        if values[..i].contains(v) {
+            continue;
+        }
+        let count = values[i..].iter().filter(|x| *x == v).count();
+        if count > 1 && best.is_none_or(|(_, c)| count > c) {
+            best = Some((*v, count));
+        }
+    }
+    best.map(|(v, _)| v).ok_or(CellError::NA)
+}
+
+fn rank(args: &[Value]) -> Result<Value, CellError> {
+    arity(args, 2, 3)?;
+    let number = args[0].as_number()?;
+    let values = numbers(&args[1..2])?;
+    let ascending = match args.get(2) {
+        Some(order) => order.as_number()? != 0.0,
+        None => false,
+    };
+    if !values.contains(&number) {
+        return Err(CellError::NA);
+    }
+    let ahead = values
+        .iter()
+        .filter(|v| if ascending { **v < number } else { **v > number })
+        .count();
+    Ok(Value::Number((ahead + 1) as f64))
+}
+
+/// Statistical functions. Returns `None` for names that are not statistical
+/// functions.
+pub fn call(name: &str, args: &[Value]) -> Option<Result<Value, CellError>> {
+    let result = match name {
+        "MEDIAN" => numbers(args).and_then(|n| percentile(&n, 0.5)).map(Value::Number),
+        "MODE" | "MODE.SNGL" => numbers(args).and_then(|n| mode(&n)).map(Value::Number),
+        "VAR" | "VAR.S" => numbers(args).and_then(|n| variance(&n, true)).map(Value::Number),
+        "VARP" | "VAR.P" => numbers(args).and_then(|n| variance(&n, false)).map(Value::Number),
+        "STDEV" | "STDEV.S" => numbers(args).and_then(|n| variance(&n, true)).map(|v| Value::Number(v.sqrt())),
+        "STDEVP" | "STDEV.P" => numbers(args).and_then(|n| variance(&n, false)).map(|v| Value::Number(v.sqrt())),
+        "PERCENTILE" | "PERCENTILE.INC" => arity(args, 2, 2).and_then(|_| {
+            let k = args[1].as_number()?;
+            percentile(&numbers(&args[..1])?, k).map(Value::Number)
+        }),
+        "QUARTILE" | "QUARTILE.INC" => arity(args, 2, 2).and_then(|_| {
+            let quart = args[1].as_number()?.trunc();
+            if !(0.0..=4.0).contains(&quart) {
+                return Err(CellError::Num);
+            }
+            percentile(&numbers(&args[..1])?, quart / 4.0).map(Value::Number)
+        }),
+        "RANK" | "RANK.EQ" => rank(args),
+        "LARGE" => kth(args, true),
+        "SMALL" => kth(args, false),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(values: &[f64]) -> Value {
+        Value::Array(values.iter().map(|v| vec![Value::Number(*v)]).collect())
+    }
+
+    fn number(name: &str, args: &[Value]) -> f64 {
+        match call(name, args) {
+            Some(Ok(Value::Number(n))) => n,
+            other => panic!("{} returned {:?}", name, other),
+        }
+    }
+
+    #[test]
+    fn test_statistical_functions() {
+        let data = range(&[4.0, 1.0, 3.0, 2.0, 3.0, 8.0]);
+        let only_data = [data.clone()];
+        assert_eq!(number("MEDIAN", &only_data), 3.0);
+        assert_eq!(number("MODE", &only_data), 3.0);
+        assert_eq!(call("MODE", &[range(&[1.0, 2.0])]), Some(Err(CellError::NA)));
+        assert!((number("VAR", &only_data) - 5.9).abs() < 1e-12);
+        assert!((number("STDEV.P", &only_data) - (29.5f64 / 6.0).sqrt()).abs() < 1e-12);
+        assert_eq!(number("PERCENTILE", &[data.clone(), Value::Number(0.3)]), 2.5);
+        assert_eq!(number("QUARTILE", &[data.clone(), Value::Number(3.0)]), 3.75);
+        assert_eq!(number("RANK", &[Value::Number(3.0), data.clone()]), 3.0);
+        assert_eq!(number("RANK", &[Value::Number(3.0), data.clone(), Value::Number(1.0)]), 3.0);
+        assert_eq!(number("LARGE", &[data.clone(), Value::Number(2.0)]), 4.0);
+        assert_eq!(number("SMALL", &[data.clone(), Value::Number(2.0)]), 2.0);
+        assert_eq!(call("SMALL", &[data, Value::Number(7.0)]), Some(Err(CellError::Num)));
+    }
+}
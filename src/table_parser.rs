@@ -1,36 +1,50 @@
 // table_parser.rs
 
-use comrak::{nodes::{AstNode, NodeValue}, Arena};
+use std::ops::Range;
 
-use crate::comment_stripper::{strip_comments_from_line, strip_comments_from_line_with_comments, CommentStrippedLine};
-use crate::md_comments::{HtmlComment, LocatedHtmlComment};
+use comrak::nodes::{AstNode, NodeValue};
+use comrak::{parse_document, Arena, ComrakOptions};
 
+use crate::comment_stripper::{strip_comments_from_line, CommentStrippedLine};
+use crate::md_comments::{parse_markdown_for_comments, LocatedHtmlComment};
+
+/// A run of text or a comment of a table cell, in source order.
 #[derive(Debug, Clone)]
-pub enum TableCellPiece<'a> {
+pub enum TableCellPiece {
     Text(String),
-    Comment(LocatedHtmlComment<'a>),
+    Comment(LocatedHtmlComment),
 }
 
+/// A cell of a table, split into text and comments.
 #[derive(Debug, Clone)]
-pub struct TableCell<'a> {
-    pub pieces: Vec<TableCellPiece<'a>>,
+pub struct TableCell {
+    pub pieces: Vec<TableCellPiece>,
+    /// Byte range of the cell between its pipes in the document, `None` for
+    /// cells that were added after parsing.
+    pub span: Option<Range<usize>>,
 }
 
+/// A row of a table; the delimiter row is left out.
 #[derive(Debug, Clone)]
-pub struct TableRow<'a> {
-    pub cells: Vec<TableCell<'a>>,
+pub struct TableRow {
+    pub cells: Vec<TableCell>,
 }
 
+/// A table of a document. Comments keep their source spans rather than
+/// pointers into the comrak AST, so tables are plain owned data that can be
+/// stored, cached and sent between threads.
 #[derive(Debug, Clone)]
-pub struct MarkdownTable<'a> {
-    pub rows: Vec<TableRow<'a>>,
+pub struct MarkdownTable {
+    pub rows: Vec<TableRow>,
     pub start_offset: usize,
     pub end_offset: usize,
 }
 
 
-impl<'a> TableCell<'a> {
+impl TableCell {
 
+    /// The text of the cell as written in its source span, with the comments
+    /// cut out and the text around them left untrimmed.
     pub fn raw_text(&self) -> String {
         self.pieces.iter().filter_map(|p| {
             if let TableCellPiece::Text(s) = p {
@@ -41,7 +55,7 @@ impl<'a> TableCell<'a> {
         }).collect::<Vec<&str>>().join("")
     }
 
-    pub fn comments(&self) -> Vec<&LocatedHtmlComment<'a>> {
+    pub fn comments(&self) -> Vec<&LocatedHtmlComment> {
         self.pieces.iter().filter_map(|p| {
             if let TableCellPiece::Comment(c) = p {
                 Some(c)
@@ -65,11 +79,19 @@ impl<'a> TableCell<'a> {
 pub struct TableParser;
 
 impl TableParser {
+    /// Parses every table of a Markdown document.
+    pub fn parse_tables(markdown: &str) -> Vec<MarkdownTable> {
+        let arena = Arena::new();
+        let root = parse_document(&arena, markdown, &ComrakOptions::default());
+        let comments = parse_markdown_for_comments(&arena, markdown);
+        Self::extract_tables_from_ast(root, Some(&comments), markdown)
+    }
+
     pub fn extract_tables_from_ast<'a>(
         root: &'a AstNode<'a>,
-        comments: Option<&'a [LocatedHtmlComment<'a>]>,
-        markdown: &'a str,
-    ) -> Vec<MarkdownTable<'a>> {
+        comments: Option<&[LocatedHtmlComment]>,
+        markdown: &str,
+    ) -> Vec<MarkdownTable> {
         let mut tables = Vec::new();
         let lines: Vec<&str> = markdown.lines().collect();
         let line_offsets: Vec<usize> = lines
@@ -83,94 +105,50 @@ impl TableParser {
 
         for node in root.descendants() {
             if let NodeValue::Paragraph = &node.data.borrow().value {
-                if let Some(text) = Self::collect_literal_text(node) {
-                    let start_line = node.data.borrow().sourcepos.start.line;
-                    let end_line = node.data.borrow().sourcepos.end.line;
-                    if let (Some(start_offset), Some(end_offset)) = (
-                    line_offsets.get(start_line - 1),
-                    line_offsets.get(end_line - 1).map(|o| o + lines[end_line - 1].len()),
-                    ) {
-                        let table_lines: Vec<&str> = text.lines().filter(|l| l.starts_with('|')).collect();
-                        if table_lines.len() >= 2 {
-                            let mut table = Self::parse_table_lines(&table_lines, comments);
-                            table.start_offset = *start_offset;
-                            table.end_offset = end_offset;
-                            tables.push(table);
-                        }
-                    }
+                let start_line = node.data.borrow().sourcepos.start.line;
+                let end_line = node.data.borrow().sourcepos.end.line;
+                if start_line == 0 || end_line > lines.len() {
+                    continue;
                 }
-            }
-        }
 
-        tables
-    }
-
-    fn collect_literal_text<'a>(node: &'a AstNode<'a>) -> Option<String> {
-        let mut text = String::new();
-        for child in node.children() {
-            match &child.data.borrow().value {
-                NodeValue::Text(s) | NodeValue::HtmlInline(s) => {
-                    text.push_str(s);
-                }
-                NodeValue::Code(code_node) => {
-                    text.push_str(&code_node.literal);
-                }
-                NodeValue::SoftBreak | NodeValue::LineBreak => {
-                    text.push('\n');
+                // Work from the source lines rather than the inline nodes so
+                // that comment offsets and cell text line up with the file.
+                let table_lines: Vec<(usize, &str)> = (start_line - 1..end_line)
+                    .map(|i| (line_offsets[i], lines[i]))
+                    .filter(|(_, l)| is_table_line(l))
+                    .collect();
+
+                if table_lines.len() >= 2 {
+                    let (first_offset, _) = table_lines[0];
+                    let (last_offset, last_line) = table_lines[table_lines.len() - 1];
+                    let mut table = Self::parse_table_lines(&table_lines, comments);
+                    table.start_offset = first_offset;
+                    table.end_offset = last_offset + last_line.len();
+                    tables.push(table);
                 }
-                _ => {}
             }
         }
-        if text.is_empty() {
-            None
-        } else {
-            Some(text)
-        }
+
+        tables
     }
 
-    fn parse_table_lines<'a>(
-        lines: &[&str],
-        comments: Option<&'a [LocatedHtmlComment<'a>]>,
-    ) -> MarkdownTable<'a> {
+    fn parse_table_lines(
+        lines: &[(usize, &str)],
+        comments: Option<&[LocatedHtmlComment]>,
+    ) -> MarkdownTable {
         let mut rows = Vec::new();
 
-        for line in lines {
-            if line.trim().is_empty() || line.contains("---") {
+        for &(line_offset, line) in lines {
+            if line.trim().is_empty() || is_delimiter_row(line) {
                 continue;
             }
 
-            let stripped: CommentStrippedLine<'a> = match comments {
-                Some(all_comments) => strip_comments_from_line_with_comments(line, all_comments),
-                None => strip_comments_from_line(line, 0, None),
-            };
-
-            let raw_cells: Vec<&str> = stripped
-                .stripped
-                .trim()
-                .trim_matches('|')
-                .split('|')
-                .collect();
-
-            // Track starting positions of each cell
-            let mut cell_starts = Vec::new();
-            let mut index = 0;
-            for segment in &raw_cells {
-                cell_starts.push(index);
-                index += segment.len() + 1; // +1 for the '|' delimiter
-            }
-
-            if cell_starts.len() != raw_cells.len() {
-                eprintln!(
-                    "Warning: Mismatched cell starts vs raw cells. Skipping row: {}",
-                    line
-                );
-                continue;
-            }
+            let stripped: CommentStrippedLine =
+                strip_comments_from_line(line, line_offset, comments);
 
             let mut cells = Vec::new();
-            for (i, raw) in raw_cells.iter().enumerate() {
-                let cell_start = cell_starts[i];
-                let cell_end = cell_start + raw.len();
+            for (cell_start, cell_end) in split_cells(&stripped.stripped) {
+                let raw = &line[cell_start..cell_end];
                 let mut pieces = Vec::new();
                 let mut last_offset = 0;
 
@@ -178,16 +156,16 @@ impl TableParser {
                     .comments
                     .iter()
                     .filter(|c| c.comment.offset >= cell_start && c.comment.offset < cell_end)
-            {
-                if c.comment.offset > last_offset {
-                    let text_slice = &raw[last_offset..c.comment.offset - cell_start];
-                    if !text_slice.trim().is_empty() {
-                        pieces.push(TableCellPiece::Text(text_slice.to_string()));
+                {
+                    if c.comment.offset - cell_start > last_offset {
+                        let text_slice = &raw[last_offset..c.comment.offset - cell_start];
+                        if !text_slice.trim().is_empty() {
+                            pieces.push(TableCellPiece::Text(text_slice.to_string()));
+                        }
                     }
+                    pieces.push(TableCellPiece::Comment(c.clone()));
+                    last_offset = c.comment.offset - cell_start + c.comment.length;
                 }
-                pieces.push(TableCellPiece::Comment(c.clone()));
-                last_offset = c.comment.offset - cell_start + c.comment.length;
-            }
 
                 if last_offset < raw.len() {
                     let remaining = &raw[last_offset..];
@@ -196,7 +174,10 @@ impl TableParser {
                     }
                 }
 
-                cells.push(TableCell { pieces });
+                cells.push(TableCell {
+                    pieces,
+                    span: Some(line_offset + cell_start..line_offset + cell_end),
+                });
             }
 
             rows.push(TableRow { cells });
@@ -207,7 +188,44 @@ impl TableParser {
             start_offset: 0,
             end_offset: 0,
         }
-    }    
+    }
+}
+
+/// True for lines that belong to a pipe table (header, delimiter or body row).
+pub fn is_table_line(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+/// True for the `|---|:--:|` line separating the header from the body.
+pub fn is_delimiter_row(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.contains('-')
+        && trimmed
+            .chars()
+            .all(|c| matches!(c, '|' | '-' | ':' | ' ' | '\t'))
+}
+
+/// Splits a comment-stripped table line into `(start, end)` byte ranges, one
+/// per cell, excluding the outer pipes. Escaped pipes (`\|`) stay in the cell.
+pub fn split_cells(line: &str) -> Vec<(usize, usize)> {
+    let bytes = line.as_bytes();
+    let mut pipes = Vec::new();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'|' && (i == 0 || bytes[i - 1] != b'\\') {
+            pipes.push(i);
+        }
+    }
+
+    let content_end = line.trim_end().len();
+    let mut cells = Vec::new();
+    for (n, &pipe) in pipes.iter().enumerate() {
+        let start = pipe + 1;
+        let end = pipes.get(n + 1).copied().unwrap_or(content_end);
+        if start < end || pipes.get(n + 1).is_some() {
+            cells.push((start, end));
+        }
+    }
+    cells
 }
 
 
@@ -247,6 +265,25 @@ mod tests {
         assert_eq!(marker_comments.len(), 1);
         assert!(marker_comments[0].comment.content.contains("!A"));
     }
+
+    #[test]
+    fn test_tables_outlive_the_arena() {
+        fn assert_owned<T: Send + Sync + 'static>(_: &T) {}
+
+        let markdown = "Intro\n\n| A | B <!-- =A2 --> |\n|---|---|\n| 1 | 1 |\n";
+        let tables = TableParser::parse_tables(markdown);
+        assert_owned(&tables);
+
+        let handle = std::thread::spawn(move || tables);
+        let tables = handle.join().unwrap();
+        let comment = tables[0].rows[0].cells[1].comments()[0].clone();
+        let span = comment.span.unwrap();
+        assert_eq!(&markdown[span], "<!-- =A2 -->");
+        let cell = &tables[0].rows[0].cells[1];
+        assert_eq!(&markdown[cell.span.clone().unwrap()], " B <!-- =A2 --> ");
+        assert_eq!(cell.raw_text(), " B ");
+        assert_eq!(tables[0].start_offset, markdown.find('|').unwrap());
+    }
 }
 
 
@@ -0,0 +1,210 @@
+// table_writer.rs
+
+use crate::md_comments::extract_html_comments;
+use crate::table_parser::{is_delimiter_row, is_table_line, split_cells, MarkdownTable, TableCell, TableCellPiece, TableRow};
+
+/// Renders a cell as it appears between two pipes, padded by one space on
+/// either side.
+pub fn render_cell(cell: &TableCell) -> String {
+    let mut out = String::new();
+    for piece in &cell.pieces {
+        match piece {
+            TableCellPiece::Text(t) => out.push_str(t),
+            TableCellPiece::Comment(c) => {
+                if !out.is_empty() && !out.ends_with(' ') {
+                    out.push(' ');
+                }
+                out.push_str(&format!("<!-- {} -->", c.comment.content.trim()));
+            }
+        }
+    }
+    if !out.starts_with(' ') {
+        out.insert(0, ' ');
+    }
+    if !out.ends_with(' ') {
+        out.push(' ');
+    }
+    out
+}
+
+pub fn render_row(row: &TableRow) -> String {
+    let cells: Vec<String> = row.cells.iter().map(render_cell).collect();
+    format!("|{}|", cells.join("|"))
+}
+
+/// Renders a new table from cell texts, the first row being the header, with
+/// every column padded to its widest cell.
+pub fn render_grid(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|c| {
+            rows.iter()
+                .filter_map(|r| r.get(c))
+                .map(|t| t.chars().count())
+                .max()
+                .unwrap_or(0)
+                .max(3)
+        })
+        .collect();
+    let line = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+    let pad = |row: &Vec<String>| {
+        widths
+            .iter()
+            .enumerate()
+            .map(|(c, w)| {
+                let text = row.get(c).map_or("", String::as_str);
+                format!("{}{}", text, " ".repeat(w - text.chars().count()))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut lines = Vec::new();
+    if let Some(header) = rows.first() {
+        lines.push(line(pad(header)));
+        lines.push(line(widths.iter().map(|w| "-".repeat(*w)).collect()));
+    }
+    lines.extend(rows.iter().skip(1).map(|r| line(pad(r))));
+    lines.join("\n")
+}
+
+/// Cell ranges of a source line, ignoring pipes inside HTML comments.
+pub fn source_cells(line: &str) -> Vec<(usize, usize)> {
+    let mut masked = line.to_string();
+    for c in extract_html_comments(line) {
+        masked.replace_range(c.offset..c.offset + c.length, &"-".repeat(c.length));
+    }
+    split_cells(&masked)
+}
+
+/// Renders one row against its source line, keeping the original text of
+/// every cell that did not change.
+fn render_row_against(line: &str, original: &TableRow, updated: &TableRow) -> String {
+    let unchanged = original.cells.len() == updated.cells.len()
+        && original
+            .cells
+            .iter()
+            .zip(&updated.cells)
+            .all(|(o, u)| render_cell(o) == render_cell(u));
+    if unchanged {
+        return line.to_string();
+    }
+
+    let spans = source_cells(line);
+    if spans.len() != original.cells.len() {
+        return render_row(updated);
+    }
+
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let mut out = format!("{}|", indent);
+    for (i, cell) in updated.cells.iter().enumerate() {
+        match (spans.get(i), original.cells.get(i)) {
+            (Some(&(start, end)), Some(old)) if render_cell(old) == render_cell(cell) => {
+                out.push_str(&line[start..end]);
+            }
+            (Some(&(start, end)), _) => {
+                let rendered = render_cell(cell);
+                let width = line[start..end].chars().count();
+                out.push_str(&rendered);
+                // Keep column alignment when the new content is shorter.
+                for _ in rendered.chars().count()..width {
+                    out.push(' ');
+                }
+            }
+            (None, _) => out.push_str(&render_cell(cell)),
+        }
+        out.push('|');
+    }
+    out
+}
+
+/// Renders a delimiter row for `columns` columns, keeping the source of the
+/// columns it already has.
+fn render_delimiter(line: &str, columns: usize) -> String {
+    let spans = split_cells(line);
+    if spans.len() == columns {
+        return line.to_string();
+    }
+    // New columns copy the last delimiter cell without its alignment colons.
+    let template = spans.last().map_or(" --- ".to_string(), |&(start, end)| line[start..end].replace(':', "-"));
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let mut out = format!("{}|", indent);
+    for i in 0..columns {
+        match spans.get(i) {
+            Some(&(start, end)) => out.push_str(&line[start..end]),
+            None => out.push_str(&template),
+        }
+        out.push('|');
+    }
+    out
+}
+
+/// Renders a row that has no source line, padding every cell to the width of
+/// its column in the delimiter row.
+fn render_new_row(delimiter: &str, row: &TableRow) -> String {
+    let spans = split_cells(delimiter);
+    let indent = &delimiter[..delimiter.len() - delimiter.trim_start().len()];
+    let mut out = format!("{}|", indent);
+    for (i, cell) in row.cells.iter().enumerate() {
+        let rendered = render_cell(cell);
+        let width = spans.get(i).map_or(0, |&(start, end)| delimiter[start..end].chars().count());
+        out.push_str(&rendered);
+        for _ in rendered.chars().count()..width {
+            out.push(' ');
+        }
+        out.push('|');
+    }
+    out
+}
+
+/// Renders the source span of `original` with the contents of `updated`.
+/// Delimiter rows and untouched cells keep their exact original text. Rows
+/// missing from `updated` are dropped and extra rows are added after the last
+/// row.
+pub fn render_table(markdown: &str, original: &MarkdownTable, updated: &MarkdownTable) -> String {
+    let source = &markdown[original.start_offset..original.end_offset];
+    let columns = updated.rows.first().map_or(0, |r| r.cells.len());
+    let mut row = 0;
+    let mut delimiter = String::new();
+    let mut last_row_line = None;
+    let mut lines = Vec::new();
+
+    for line in source.split('\n') {
+        if !is_table_line(line) || line.trim().is_empty() {
+            lines.push(line.to_string());
+            continue;
+        }
+        if is_delimiter_row(line) {
+            delimiter = render_delimiter(line, columns);
+            lines.push(delimiter.clone());
+            continue;
+        }
+        match (original.rows.get(row), updated.rows.get(row)) {
+            (Some(o), Some(u)) => {
+                lines.push(render_row_against(line, o, u));
+                last_row_line = Some(lines.len());
+            }
+            (Some(_), None) => {}
+            (None, _) => lines.push(line.to_string()),
+        }
+        row += 1;
+    }
+
+    if let Some(end) = last_row_line {
+        let added = updated.rows.iter().skip(original.rows.len()).map(|r| render_new_row(&delimiter, r));
+        lines.splice(end..end, added);
+    }
+    lines.join("\n")
+}
+
+/// Applies `render_table` for every table, returning the new document.
+pub fn render_document(markdown: &str, originals: &[MarkdownTable], updated: &[MarkdownTable]) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut last = 0;
+    for (original, table) in originals.iter().zip(updated) {
+        out.push_str(&markdown[last..original.start_offset]);
+        out.push_str(&render_table(markdown, original, table));
+        last = original.end_offset;
+    }
+    out.push_str(&markdown[last..]);
+    out
+}
@@ -0,0 +1,225 @@
+// watch.rs
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::recalc::{recalculate_markdown, RecalcOptions};
+
+/// Settings for [`Watcher`].
+#[derive(Debug, Clone, Copy)]
+pub struct WatchOptions {
+    pub recalc: RecalcOptions,
+    /// How long a file must stay unchanged before it is recalculated, so an
+    /// editor that saves in several writes is only answered once.
+    pub debounce: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            recalc: RecalcOptions::default(),
+            debounce: Duration::from_millis(300),
+        }
+    }
+}
+
+/// What a file looked like when it was last scanned.
+type Stamp = (Option<SystemTime>, u64);
+
+/// Watches the Markdown files under a directory by polling, and recalculates
+/// each one in place once it has been saved. Files whose recalculated text
+/// is the same are not written, so editors do not see them change.
+pub struct Watcher {
+    dir: PathBuf,
+    options: WatchOptions,
+    seen: HashMap<PathBuf, Stamp>,
+    /// Changed files and when the latest change was seen.
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl Watcher {
+    /// Starts watching `dir`. Files already there are left alone until they
+    /// change.
+    pub fn new(dir: &Path, options: WatchOptions) -> Result<Watcher, String> {
+        let mut watcher = Watcher {
+            dir: dir.to_path_buf(),
+            options,
+            seen: HashMap::new(),
+            pending: HashMap::new(),
+        };
+        watcher.seen = watcher.scan()?;
+        Ok(watcher)
+    }
+
+    /// Scans the directory once, at time `now`, and recalculates the changed
+    /// files that have settled. Returns the files that were rewritten, and an
+    /// error for each file that could not be.
+    pub fn poll(&mut self, now: Instant) -> Result<Vec<Result<PathBuf, String>>, String> {
+        let current = self.scan()?;
+        for (path, stamp) in &current {
+            if self.seen.get(path) != Some(stamp) {
+                self.pending.insert(path.clone(), now);
+            }
+        }
+        self.pending.retain(|path, _| current.contains_key(path));
+        self.seen = current;
+
+        let mut settled: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, &changed)| now.duration_since(changed) >= self.options.debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+        settled.sort();
+        let mut results = Vec::new();
+        for path in settled {
+            self.pending.remove(&path);
+            match recalculate_file(&path, &self.options.recalc) {
+                Ok(false) => {}
+                Ok(true) => {
+                    // Our own write is not a change to answer.
+                    if let Ok(stamp) = stamp(&path) {
+                        self.seen.insert(path.clone(), stamp);
+                    }
+                    results.push(Ok(path));
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+        Ok(results)
+    }
+
+    fn scan(&self) -> Result<HashMap<PathBuf, Stamp>, String> {
+        let mut files = HashMap::new();
+        scan_dir(&self.dir, &mut files)?;
+        Ok(files)
+    }
+}
+
+/// Recalculates the Markdown file at `path`, writing it back only if its
+/// text changes. Returns whether it was written.
+fn recalculate_file(path: &Path, options: &RecalcOptions) -> Result<bool, String> {
+    let markdown = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let output = recalculate_markdown(&markdown, options);
+    if output == markdown {
+        return Ok(false);
+    }
+    write_unless_changed(path, &markdown, &output)
+}
+
+/// Replaces the file at `path`, whose text was `read`, with `text` unless it
+/// was saved again in the meantime. The newer save is then left alone, to be
+/// recalculated once it settles. The text goes to a hidden file next to it
+/// first and is renamed into place, so the file never holds half of it and
+/// the check for a newer save comes right before the switch.
+fn write_unless_changed(path: &Path, read: &str, text: &str) -> Result<bool, String> {
+    let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    let name = path.file_name().map_or_else(Default::default, |n| n.to_string_lossy().to_string());
+    let temp = path.with_file_name(format!(".{}.mdcalc", name));
+    fs::write(&temp, text).map_err(error)?;
+    let result = (|| {
+        fs::set_permissions(&temp, fs::metadata(path)?.permissions())?;
+        if fs::read_to_string(path)? != read {
+            return Ok(false);
+        }
+        fs::rename(&temp, path)?;
+        Ok(true)
+    })();
+    if result.as_ref().map_or(true, |written| !written) {
+        let _ = fs::remove_file(&temp);
+    }
+    result.map_err(error)
+}
+
+/// Collects the `.md` and `.markdown` files under `dir`, skipping hidden
+/// files and directories.
+fn scan_dir(dir: &Path, files: &mut HashMap<PathBuf, Stamp>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let Ok(kind) = entry.file_type() else { continue };
+        if kind.is_dir() {
+            // A directory may vanish between listing and reading it.
+            let _ = scan_dir(&path, files);
+        } else if is_markdown(&name) {
+            if let Ok(stamp) = stamp(&path) {
+                files.insert(path, stamp);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn stamp(path: &Path) -> Result<Stamp, String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok((metadata.modified().ok(), metadata.len()))
+}
+
+fn is_markdown(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".md") || name.ends_with(".markdown")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watcher() {
+        let dir = std::env::temp_dir().join(format!("mdcalc-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        let doc = dir.join("doc.md");
+        let hidden = dir.join(".hidden/doc.md");
+        let notes = dir.join("notes.txt");
+        let table = "| a | b |\n|---|---|\n| 2 | <!-- =A2*3 --> |\n";
+        fs::write(&doc, "old\n").unwrap();
+        for path in [&hidden, &notes] {
+            fs::write(path, table).unwrap();
+        }
+
+        let options = WatchOptions {
+            recalc: RecalcOptions {
+                marker_mode: None,
+                today: Some(0.0),
+            },
+            debounce: Duration::from_millis(100),
+        };
+        let mut watcher = Watcher::new(&dir, options).unwrap();
+        let start = Instant::now();
+        assert!(watcher.poll(start).unwrap().is_empty());
+
+        // A save is answered once it has settled.
+        fs::write(&doc, table).unwrap();
+        assert!(watcher.poll(start + Duration::from_millis(10)).unwrap().is_empty());
+        let written = watcher.poll(start + Duration::from_millis(200)).unwrap();
+        assert_eq!(written, vec![Ok(doc.clone())]);
+        assert_eq!(fs::read_to_string(&doc).unwrap(), "| a | b |\n|---|---|\n| 2 | 6 <!-- =A2*3 --> |\n");
+
+        // Neither the rewrite nor an already computed file trigger a write.
+        assert!(watcher.poll(start + Duration::from_millis(400)).unwrap().is_empty());
+        assert!(!recalculate_file(&doc, &options.recalc).unwrap());
+        assert_eq!(fs::read_to_string(&hidden).unwrap(), table);
+        assert_eq!(fs::read_to_string(&notes).unwrap(), table);
+
+        // A save made while recalculating is not overwritten, even one of
+        // the same length within the same modification time.
+        let read = fs::read_to_string(&doc).unwrap();
+        let newer = read.replace('6', "7");
+        assert_eq!(newer.len(), read.len());
+        fs::write(&doc, &newer).unwrap();
+        assert!(!write_unless_changed(&doc, &read, "stale").unwrap());
+        assert_eq!(fs::read_to_string(&doc).unwrap(), newer);
+        assert!(write_unless_changed(&doc, &newer, "fresh\n").unwrap());
+        assert_eq!(fs::read_to_string(&doc).unwrap(), "fresh\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3, "no temporary file is left behind");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}